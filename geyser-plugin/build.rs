@@ -0,0 +1,16 @@
+fn main() -> Result<(), std::io::Error> {
+    const PROTOC_ENVAR: &str = "PROTOC";
+    if std::env::var(PROTOC_ENVAR).is_err() {
+        #[cfg(not(windows))]
+        std::env::set_var(PROTOC_ENVAR, protobuf_src::protoc());
+    }
+
+    let proto_base_path = std::path::PathBuf::from("proto");
+    let proto = proto_base_path.join("inscriptions.proto");
+    println!("cargo:rerun-if-changed={}", proto.display());
+
+    tonic_build::configure()
+        .build_client(false)
+        .build_server(true)
+        .compile(&[proto], &[proto_base_path])
+}
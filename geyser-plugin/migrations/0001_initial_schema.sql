@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS {schema}.{prefix}inscriptions (
+    slot BIGINT NOT NULL,
+    signature {key_type} NOT NULL,
+    account {key_type} NOT NULL,
+    mint_account {key_type},
+    metadata_account {key_type},
+    authority {key_type} NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}inscriptions_pk PRIMARY KEY (account)
+);
+
+CREATE TABLE IF NOT EXISTS {schema}.{prefix}inscription_events (
+    slot BIGINT NOT NULL,
+    signature {key_type} NOT NULL,
+    instruction_index INT NOT NULL,
+    instruction_type VARCHAR(32) NOT NULL,
+    inscription_account {key_type},
+    metadata_account {key_type},
+    mint_account {key_type},
+    authority {key_type},
+    accounts {key_type}[] NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}inscription_events_pk PRIMARY KEY (signature, instruction_index)
+);
+
+CREATE INDEX IF NOT EXISTS {prefix}inscription_events_inscription_account ON {schema}.{prefix}inscription_events (inscription_account);
+CREATE INDEX IF NOT EXISTS {prefix}inscription_events_slot ON {schema}.{prefix}inscription_events (slot);
+
+CREATE TABLE IF NOT EXISTS {schema}.{prefix}slots (
+    slot BIGINT NOT NULL,
+    parent BIGINT,
+    status VARCHAR(16) NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}slots_pk PRIMARY KEY (slot)
+);
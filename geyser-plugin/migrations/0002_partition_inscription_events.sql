@@ -0,0 +1,52 @@
+-- Turn inscription_events into a table partitioned by slot range. The
+-- existing table becomes its first partition, covering the slots up to the
+-- last one it holds. Unique constraints of a partitioned table must include
+-- the partition key, hence the slot in the primary key.
+ALTER TABLE {schema}.{prefix}inscription_events RENAME TO {prefix}inscription_events_0;
+ALTER TABLE {schema}.{prefix}inscription_events_0 DROP CONSTRAINT {prefix}inscription_events_pk;
+ALTER TABLE {schema}.{prefix}inscription_events_0
+    ADD CONSTRAINT {prefix}inscription_events_0_pk PRIMARY KEY (slot, signature, instruction_index);
+ALTER INDEX {schema}.{prefix}inscription_events_inscription_account RENAME TO {prefix}inscription_events_0_inscription_account;
+ALTER INDEX {schema}.{prefix}inscription_events_slot RENAME TO {prefix}inscription_events_0_slot;
+
+CREATE TABLE {schema}.{prefix}inscription_events (
+    slot BIGINT NOT NULL,
+    signature {key_type} NOT NULL,
+    instruction_index INT NOT NULL,
+    instruction_type VARCHAR(32) NOT NULL,
+    inscription_account {key_type},
+    metadata_account {key_type},
+    mint_account {key_type},
+    authority {key_type},
+    accounts {key_type}[] NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}inscription_events_pk PRIMARY KEY (slot, signature, instruction_index)
+) PARTITION BY RANGE (slot);
+
+CREATE INDEX {prefix}inscription_events_inscription_account ON {schema}.{prefix}inscription_events (inscription_account);
+CREATE INDEX {prefix}inscription_events_signature ON {schema}.{prefix}inscription_events (signature);
+
+-- The partitions of inscription_events, those detached included.
+CREATE TABLE {schema}.{prefix}event_partitions (
+    name TEXT NOT NULL,
+    start_slot BIGINT NOT NULL,
+    end_slot BIGINT NOT NULL,
+    created_on TIMESTAMP NOT NULL,
+    detached_on TIMESTAMP,
+    archived_as TEXT,
+    CONSTRAINT {prefix}event_partitions_pk PRIMARY KEY (name)
+);
+
+DO $$
+DECLARE
+    upper_slot BIGINT;
+BEGIN
+    SELECT GREATEST(MAX(slot) + 1, 1) INTO upper_slot FROM {schema}.{prefix}inscription_events_0;
+    EXECUTE format(
+        'ALTER TABLE {schema}.{prefix}inscription_events ATTACH PARTITION '
+        '{schema}.{prefix}inscription_events_0 FOR VALUES FROM (0) TO (%s)',
+        upper_slot
+    );
+    INSERT INTO {schema}.{prefix}event_partitions (name, start_slot, end_slot, created_on)
+    VALUES ('{prefix}inscription_events_0', 0, upper_slot, now());
+END $$;
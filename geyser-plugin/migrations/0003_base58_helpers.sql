@@ -0,0 +1,88 @@
+-- base58 presents a key column as base58 text whatever the key_storage of
+-- the tables, base58_decode turns base58 text into the bytes stored by the
+-- binary key_storage, e.g. for
+--   SELECT * FROM inscriptions WHERE account = base58_decode('...')
+CREATE OR REPLACE FUNCTION {schema}.{prefix}base58(data BYTEA) RETURNS TEXT
+LANGUAGE plpgsql IMMUTABLE STRICT PARALLEL SAFE AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz';
+    num NUMERIC := 0;
+    encoded TEXT := '';
+BEGIN
+    FOR i IN 0 .. length(data) - 1 LOOP
+        num := num * 256 + get_byte(data, i);
+    END LOOP;
+    WHILE num > 0 LOOP
+        encoded := substr(alphabet, mod(num, 58)::INT + 1, 1) || encoded;
+        num := div(num, 58);
+    END LOOP;
+    FOR i IN 0 .. length(data) - 1 LOOP
+        EXIT WHEN get_byte(data, i) <> 0;
+        encoded := '1' || encoded;
+    END LOOP;
+    RETURN encoded;
+END $$;
+
+CREATE OR REPLACE FUNCTION {schema}.{prefix}base58(data TEXT) RETURNS TEXT
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$ SELECT data $$;
+
+CREATE OR REPLACE FUNCTION {schema}.{prefix}base58(data BYTEA[]) RETURNS TEXT[]
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$
+    SELECT COALESCE(array_agg({schema}.{prefix}base58(key) ORDER BY position), '{}')
+    FROM unnest(data) WITH ORDINALITY AS keys(key, position)
+$$;
+
+CREATE OR REPLACE FUNCTION {schema}.{prefix}base58(data TEXT[]) RETURNS TEXT[]
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$ SELECT data $$;
+
+CREATE OR REPLACE FUNCTION {schema}.{prefix}base58_decode(encoded TEXT) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT PARALLEL SAFE AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz';
+    num NUMERIC := 0;
+    digit INT;
+    data BYTEA := '';
+BEGIN
+    FOR i IN 1 .. length(encoded) LOOP
+        digit := strpos(alphabet, substr(encoded, i, 1)) - 1;
+        IF digit < 0 THEN
+            RAISE EXCEPTION 'Invalid base58 character % in %', substr(encoded, i, 1), encoded;
+        END IF;
+        num := num * 58 + digit;
+    END LOOP;
+    WHILE num > 0 LOOP
+        data := set_byte(decode('00', 'hex'), 0, mod(num, 256)::INT) || data;
+        num := div(num, 256);
+    END LOOP;
+    FOR i IN 1 .. length(encoded) LOOP
+        EXIT WHEN substr(encoded, i, 1) <> '1';
+        data := decode('00', 'hex') || data;
+    END LOOP;
+    RETURN data;
+END $$;
+
+-- The tables with their keys in base58, for human consumption.
+CREATE OR REPLACE VIEW {schema}.{prefix}inscriptions_base58 AS
+SELECT
+    slot,
+    {schema}.{prefix}base58(signature) AS signature,
+    {schema}.{prefix}base58(account) AS account,
+    {schema}.{prefix}base58(mint_account) AS mint_account,
+    {schema}.{prefix}base58(metadata_account) AS metadata_account,
+    {schema}.{prefix}base58(authority) AS authority,
+    updated_on
+FROM {schema}.{prefix}inscriptions;
+
+CREATE OR REPLACE VIEW {schema}.{prefix}inscription_events_base58 AS
+SELECT
+    slot,
+    {schema}.{prefix}base58(signature) AS signature,
+    instruction_index,
+    instruction_type,
+    {schema}.{prefix}base58(inscription_account) AS inscription_account,
+    {schema}.{prefix}base58(metadata_account) AS metadata_account,
+    {schema}.{prefix}base58(mint_account) AS mint_account,
+    {schema}.{prefix}base58(authority) AS authority,
+    {schema}.{prefix}base58(accounts) AS accounts,
+    updated_on
+FROM {schema}.{prefix}inscription_events;
@@ -0,0 +1,9 @@
+-- The highest slot whose events, and those of every slot before it, are
+-- committed, per indexed program. The plugin skips the slots up to it when
+-- the validator replays them after a restart.
+CREATE TABLE {schema}.{prefix}indexer_state (
+    program_id {key_type} NOT NULL,
+    committed_slot BIGINT NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}indexer_state_pk PRIMARY KEY (program_id)
+);
@@ -0,0 +1,11 @@
+-- The slot ranges, both ends included, the plugin missed, e.g. when the
+-- validator restarted from a snapshot ahead of the committed slot. A gap is
+-- open until a backfill marks it filled.
+CREATE TABLE {schema}.{prefix}backfill_gaps (
+    program_id {key_type} NOT NULL,
+    start_slot BIGINT NOT NULL,
+    end_slot BIGINT NOT NULL,
+    detected_on TIMESTAMP NOT NULL,
+    filled_on TIMESTAMP,
+    CONSTRAINT {prefix}backfill_gaps_pk PRIMARY KEY (program_id, start_slot)
+);
@@ -0,0 +1,346 @@
+pub mod block_json;
+pub mod blockstore;
+
+/// Indexes blocks the plugin never saw, read from outside of the validator,
+/// into the sinks of a plugin configuration. The blocks come from a
+/// `BlockSource`: `getBlock` JSON files, or the ledger of a node. The
+/// instructions are decoded by `decode_inscription_events`, as for the
+/// transactions the validator notifies, and written block by block in slot
+/// order. The last block written is recorded in a progress file, so that an
+/// interrupted backfill resumes after it, along with the blocks missing from
+/// the source, which it tries again.
+use {
+    crate::{
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, SinkConfig},
+        inscription_sink::{self, InscriptionSink},
+        metrics::PluginMetrics,
+        postgres_client::{
+            postgres_client_migration, postgres_client_partition::EventPartitions,
+            postgres_client_transaction::InscriptionEvent, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    domichain_sdk::pubkey::Pubkey,
+    log::*,
+    std::{
+        collections::BTreeSet,
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+/// The inscription events of one block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockEvents {
+    pub slot: u64,
+    pub parent_slot: Option<u64>,
+    pub events: Vec<InscriptionEvent>,
+    /// The transactions of the block.
+    pub transactions: usize,
+    /// The transactions saved without their status, or in an encoding the
+    /// instructions cannot be read from, which are not indexed.
+    pub undecoded_transactions: usize,
+    /// Inscription program instructions which could not be deserialized.
+    pub decode_failures: usize,
+}
+
+/// What a block source holds for a slot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlotBlock {
+    Block(BlockEvents),
+    /// The slot was skipped by the cluster.
+    Skipped,
+    /// The block of the slot is not in the source, e.g. it was purged from
+    /// the ledger.
+    Missing,
+}
+
+/// Confirmed blocks to backfill.
+pub trait BlockSource {
+    /// The slots from `start_slot` to `end_slot`, both included, the source
+    /// holds, in order.
+    fn slots(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>, String>;
+
+    /// Decode the inscription events of the block of `slot`.
+    fn read_block(&self, program_id: &Pubkey, slot: u64) -> Result<SlotBlock, String>;
+}
+
+/// The configuration to backfill with: `config` with only the sinks whose
+/// `type` is one of `kinds`, or by default only its storage sinks. The
+/// streaming and webhook sinks are left out unless selected, so that the
+/// backfill neither binds the addresses of a running plugin nor notifies the
+/// endpoints of historical events.
+pub fn select_sinks(
+    config: &GeyserPluginPostgresConfig,
+    kinds: Option<&[&str]>,
+) -> Result<GeyserPluginPostgresConfig, String> {
+    let sinks = config.sinks();
+    if let Some(kind) = kinds
+        .unwrap_or_default()
+        .iter()
+        .find(|kind| !sinks.iter().any(|sink| sink.kind() == **kind))
+    {
+        return Err(format!("No {:?} sink is configured", kind));
+    }
+    let selected = sinks
+        .into_iter()
+        .filter(|sink| match kinds {
+            Some(kinds) => kinds.contains(&sink.kind()),
+            None => sink.is_storage(),
+        })
+        .collect::<Vec<_>>();
+    if selected.is_empty() {
+        return Err("The configuration has no storage sink to backfill".to_string());
+    }
+    Ok(GeyserPluginPostgresConfig {
+        sinks: Some(selected),
+        ..config.clone()
+    })
+}
+
+/// The sinks of a plugin configuration, written to from the backfill thread.
+pub struct BackfillWriter {
+    sinks: Vec<Box<dyn InscriptionSink>>,
+    /// The partitions of the event table, when the `Postgres` sink is
+    /// configured.
+    partitions: Option<EventPartitions>,
+}
+
+impl BackfillWriter {
+    /// Create the sinks of `config`, usually narrowed by `select_sinks`. As
+    /// when the plugin loads, the database schema is checked, and migrated
+    /// with `auto_migrate`, first.
+    pub fn new(config: &GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
+        config.validate().into_result()?;
+        let partitions = if config.sinks().contains(&SinkConfig::Postgres) {
+            let mut client = SimplePostgresClient::connect_to_db(config)?;
+            postgres_client_migration::prepare_schema(
+                &mut client,
+                &SchemaLayout::new(config)?,
+                config.auto_migrate.unwrap_or_default(),
+            )?;
+            Some(EventPartitions::new(config)?)
+        } else {
+            None
+        };
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let shared_sinks = inscription_sink::build_shared_sinks(config, &metrics)?;
+        let sinks = inscription_sink::build_worker_sinks(config, &shared_sinks)?;
+        Ok(Self { sinks, partitions })
+    }
+
+    /// Check the events of the slots `[start_slot, end_slot]` can be
+    /// written: none of them lies in a partition detached by
+    /// `partition_retention_slots`, whose inserts would fail.
+    pub fn check_slots(&self, start_slot: u64, end_slot: u64) -> Result<(), String> {
+        let partitions = match &self.partitions {
+            Some(partitions) => partitions,
+            None => return Ok(()),
+        };
+        let detached = partitions
+            .detached_partitions(start_slot, end_slot)
+            .map_err(|err| err.to_string())?;
+        if detached.is_empty() {
+            return Ok(());
+        }
+        let ranges = detached
+            .iter()
+            .map(|(table, start, end)| format!("[{}, {}) detached as {}", start, end, table))
+            .collect::<Vec<_>>();
+        Err(format!(
+            "The slots {} to {} overlap partitions detached by \"partition_retention_slots\": \
+            {}. Attach them back with ALTER TABLE ... ATTACH PARTITION, and clear their \
+            \"detached_on\" in the \"event_partitions\" table, or backfill the slots \
+            outside of them",
+            start_slot,
+            end_slot,
+            ranges.join(", ")
+        ))
+    }
+
+    /// Write the events of `block` to every sink, and record the block as
+    /// rooted. Stops at the first sink failing.
+    pub fn write_block(&mut self, block: &BlockEvents) -> Result<(), GeyserPluginError> {
+        if let Some(partitions) = &self.partitions {
+            partitions.cover_now(block.slot)?;
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.ensure_connected()?;
+            if !block.events.is_empty() {
+                sink.write_events(&block.events)?;
+            }
+            sink.update_slot_status(block.slot, block.parent_slot, &SlotStatus::Rooted)?;
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The last slot backfilled, and the slots up to it whose block was missing
+/// from the source, kept in a file: the last slot on the first line, then one
+/// missing slot per line.
+pub struct BackfillProgress {
+    path: PathBuf,
+    last_slot: Option<u64>,
+    /// Retried by the next run, which does not skip them.
+    missing_slots: BTreeSet<u64>,
+}
+
+impl BackfillProgress {
+    /// Read the progress of `path`, which does not exist before the first
+    /// block is written.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(format!(
+                    "Failed to read the backfill progress file {:?}: ({})",
+                    path, err
+                ))
+            }
+        };
+        let mut slots = contents.lines().map(|line| {
+            line.trim()
+                .parse::<u64>()
+                .map_err(|err| format!("Invalid backfill progress file {:?}: ({})", path, err))
+        });
+        let last_slot = slots.next().transpose()?;
+        let missing_slots = slots.collect::<Result<BTreeSet<_>, _>>()?;
+        if let Some(last_slot) = last_slot {
+            info!(
+                "Resuming the backfill after the slot {}, recorded in {:?}, and at the {} \
+                slots whose block was missing",
+                last_slot,
+                path,
+                missing_slots.len()
+            );
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            last_slot,
+            missing_slots,
+        })
+    }
+
+    /// Whether a previous run recorded its progress in the file.
+    pub fn is_resumed(&self) -> bool {
+        self.last_slot.is_some()
+    }
+
+    /// Whether `slot` was backfilled by a previous run.
+    pub fn is_done(&self, slot: u64) -> bool {
+        self.last_slot.map(|last_slot| slot <= last_slot) == Some(true)
+            && !self.missing_slots.contains(&slot)
+    }
+
+    /// Record that the block of `slot` is written, as is every block up to
+    /// the last slot recorded but the missing ones.
+    pub fn record(&mut self, slot: u64) -> Result<(), String> {
+        self.missing_slots.remove(&slot);
+        self.advance(slot)
+    }
+
+    /// Record that the block of `slot` is missing from the source, so that
+    /// the next run tries it again.
+    pub fn record_missing(&mut self, slot: u64) -> Result<(), String> {
+        self.missing_slots.insert(slot);
+        self.advance(slot)
+    }
+
+    /// Move the last slot up to `slot` and save the progress. The file is
+    /// replaced, so that it is never left half written.
+    fn advance(&mut self, slot: u64) -> Result<(), String> {
+        let last_slot = self.last_slot.map_or(slot, |last_slot| last_slot.max(slot));
+        let contents = std::iter::once(last_slot)
+            .chain(self.missing_slots.iter().copied())
+            .map(|slot| format!("{}\n", slot))
+            .collect::<String>();
+        let temporary_path = self.path.with_extension("tmp");
+        fs::write(&temporary_path, contents)
+            .and_then(|_| fs::rename(&temporary_path, &self.path))
+            .map_err(|err| {
+                format!(
+                    "Failed to write the backfill progress file {:?}: ({})",
+                    self.path, err
+                )
+            })?;
+        self.last_slot = Some(last_slot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn config(sinks: serde_json::Value) -> GeyserPluginPostgresConfig {
+        serde_json::from_value(json!({ "sinks": sinks })).unwrap()
+    }
+
+    #[test]
+    fn test_select_sinks() {
+        let config = config(json!([
+            {"type": "postgres"},
+            {"type": "webhook", "endpoints": [{"url": "http://127.0.0.1:8080/"}]},
+        ]));
+        let kinds = |config: &GeyserPluginPostgresConfig| {
+            config
+                .sinks()
+                .iter()
+                .map(|sink| sink.kind())
+                .collect::<Vec<_>>()
+        };
+
+        // The webhook only gets historical events when asked to.
+        assert_eq!(kinds(&select_sinks(&config, None).unwrap()), ["postgres"]);
+        assert_eq!(
+            kinds(&select_sinks(&config, Some(&["webhook", "postgres"])).unwrap()),
+            ["postgres", "webhook"]
+        );
+        assert!(select_sinks(&config, Some(&["sqlite"])).is_err());
+        assert!(select_sinks(&config, Some(&[])).is_err());
+
+        let config = self::config(json!([
+            {"type": "webhook", "endpoints": [{"url": "http://127.0.0.1:8080/"}]},
+        ]));
+        assert!(select_sinks(&config, None).is_err());
+    }
+
+    #[test]
+    fn test_backfill_progress() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("backfill.progress");
+        let mut progress = BackfillProgress::load(&path).unwrap();
+        assert!(!progress.is_resumed());
+        assert!(!progress.is_done(10));
+
+        progress.record(10).unwrap();
+        progress.record_missing(11).unwrap();
+        progress.record(12).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "12\n11\n");
+
+        // A resumed run skips the blocks written, not the missing one.
+        let mut progress = BackfillProgress::load(&path).unwrap();
+        assert!(progress.is_resumed());
+        assert!(progress.is_done(10));
+        assert!(!progress.is_done(11));
+        assert!(progress.is_done(12));
+        assert!(!progress.is_done(13));
+
+        // Writing the missing block does not move the last slot back.
+        progress.record(11).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "12\n");
+        assert!(BackfillProgress::load(&path).unwrap().is_done(11));
+
+        // The files of earlier versions hold only the last slot.
+        fs::write(&path, "7\n").unwrap();
+        let progress = BackfillProgress::load(&path).unwrap();
+        assert!(progress.is_done(7));
+        assert!(!progress.is_done(8));
+
+        fs::write(&path, "7\nabc\n").unwrap();
+        assert!(BackfillProgress::load(&path).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+/// Reads confirmed blocks saved from the `getBlock` RPC method, one block
+/// per file named after its slot: `<slot>.json`, or `<slot>.json.gz` when
+/// compressed. A file holds either the whole JSON-RPC response or only its
+/// `result`. The transactions must be requested with the `json`, `base58`
+/// or `base64` encoding, and with `"transactionDetails": "full"`.
+use {
+    crate::{
+        backfill::{BlockEvents, BlockSource, SlotBlock},
+        postgres_client::postgres_client_transaction::{
+            decode_inscription_events, InscriptionEvent,
+        },
+    },
+    domichain_sdk::{
+        instruction::CompiledInstruction,
+        message::{v0::LoadedAddresses, AccountKeys},
+        pubkey::Pubkey,
+        signature::Signature,
+    },
+    domichain_transaction_status::{
+        EncodedTransaction, EncodedTransactionWithStatusMeta, UiConfirmedBlock, UiLoadedAddresses,
+        UiMessage, UiTransactionStatusMeta,
+    },
+    flate2::read::GzDecoder,
+    log::*,
+    serde_json::Value,
+    std::{
+        fs::{self, File},
+        io::{BufReader, Read},
+        path::PathBuf,
+        str::FromStr,
+    },
+};
+
+/// The JSON-RPC error codes of `getBlock` for a slot without a block: the
+/// slot was skipped, or is missing from the ledger of the node.
+const SLOT_SKIPPED_ERROR_CODES: [i64; 2] = [-32007, -32009];
+
+/// A block file, and the slot of its name.
+struct BlockFile {
+    slot: u64,
+    path: PathBuf,
+}
+
+impl BlockFile {
+    fn new(path: PathBuf) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let (slot, extension) = file_name.split_once('.')?;
+        if extension != "json" && extension != "json.gz" {
+            return None;
+        }
+        Some(Self {
+            slot: slot.parse().ok()?,
+            path,
+        })
+    }
+
+    /// Read the block of the file, `None` when the slot was skipped by the
+    /// cluster: the `result` is `null`, or the error says so.
+    fn read(&self) -> Result<Option<UiConfirmedBlock>, String> {
+        let file = File::open(&self.path)
+            .map_err(|err| format!("Failed to open {:?}: ({})", self.path, err))?;
+        let reader: Box<dyn Read> = if self.path.extension() == Some("gz".as_ref()) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut value: Value = serde_json::from_reader(BufReader::new(reader))
+            .map_err(|err| format!("Failed to parse {:?}: ({})", self.path, err))?;
+        if let Some(error) = value.get("error") {
+            let code = error.get("code").and_then(Value::as_i64);
+            if SLOT_SKIPPED_ERROR_CODES
+                .iter()
+                .any(|skipped| code == Some(*skipped))
+            {
+                return Ok(None);
+            }
+            return Err(format!(
+                "{:?} holds the error response {}",
+                self.path, error
+            ));
+        }
+        if value.get("jsonrpc").is_some() {
+            value = value
+                .get_mut("result")
+                .map(Value::take)
+                .unwrap_or(Value::Null);
+        }
+        if value.is_null() {
+            return Ok(None);
+        }
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(|err| format!("{:?} does not hold a block: ({})", self.path, err))
+    }
+}
+
+/// The block files of inputs, by slot.
+pub struct BlockFiles {
+    files: Vec<BlockFile>,
+}
+
+impl BlockFiles {
+    /// List the block files of `inputs`, files or directories. The files of
+    /// a directory which are not named after a slot are ignored.
+    pub fn new(inputs: &[PathBuf]) -> Result<Self, String> {
+        let mut files = Vec::new();
+        for input in inputs {
+            if input.is_dir() {
+                let entries = fs::read_dir(input).map_err(|err| {
+                    format!("Failed to read the directory {:?}: ({})", input, err)
+                })?;
+                for entry in entries {
+                    let path = entry
+                        .map_err(|err| {
+                            format!("Failed to read the directory {:?}: ({})", input, err)
+                        })?
+                        .path();
+                    match BlockFile::new(path.clone()) {
+                        Some(file) => files.push(file),
+                        None => debug!("Ignoring {:?}, which is not named after a slot", path),
+                    }
+                }
+            } else {
+                files.push(BlockFile::new(input.clone()).ok_or_else(|| {
+                    format!(
+                        "{:?} is not named after a slot, as <slot>.json or <slot>.json.gz",
+                        input
+                    )
+                })?);
+            }
+        }
+        files.sort_by_key(|file| file.slot);
+        if let Some(files) = files
+            .windows(2)
+            .find(|files| files[0].slot == files[1].slot)
+        {
+            return Err(format!(
+                "{:?} and {:?} hold the same slot",
+                files[0].path, files[1].path
+            ));
+        }
+        Ok(Self { files })
+    }
+}
+
+impl BlockSource for BlockFiles {
+    fn slots(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>, String> {
+        Ok(self
+            .files
+            .iter()
+            .map(|file| file.slot)
+            .filter(|slot| *slot >= start_slot && *slot <= end_slot)
+            .collect())
+    }
+
+    fn read_block(&self, program_id: &Pubkey, slot: u64) -> Result<SlotBlock, String> {
+        let file = self
+            .files
+            .binary_search_by_key(&slot, |file| file.slot)
+            .map(|index| &self.files[index])
+            .map_err(|_| format!("No block file holds the slot {}", slot))?;
+        match file.read()? {
+            Some(block) => decode_block(program_id, slot, &block).map(SlotBlock::Block),
+            None => Ok(SlotBlock::Skipped),
+        }
+    }
+}
+
+/// The signature, account keys and top level instructions of a
+/// transaction, `None` when its encoding does not keep the instructions.
+fn transaction_parts(
+    transaction: &EncodedTransaction,
+) -> Option<(Signature, Vec<Pubkey>, Vec<CompiledInstruction>)> {
+    if let Some(transaction) = transaction.decode() {
+        let message = &transaction.message;
+        return Some((
+            *transaction.signatures.first()?,
+            message.static_account_keys().to_vec(),
+            message.instructions().to_vec(),
+        ));
+    }
+    let transaction = match transaction {
+        EncodedTransaction::Json(transaction) => transaction,
+        _ => return None,
+    };
+    let message = match &transaction.message {
+        UiMessage::Raw(message) => message,
+        UiMessage::Parsed(_) => return None,
+    };
+    let account_keys = parse_keys(&message.account_keys)?;
+    let instructions = message
+        .instructions
+        .iter()
+        .map(|instruction| {
+            Some(CompiledInstruction {
+                program_id_index: instruction.program_id_index,
+                accounts: instruction.accounts.clone(),
+                data: bs58::decode(&instruction.data).into_vec().ok()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((
+        Signature::from_str(transaction.signatures.first()?).ok()?,
+        account_keys,
+        instructions,
+    ))
+}
+
+fn parse_keys(keys: &[String]) -> Option<Vec<Pubkey>> {
+    keys.iter().map(|key| Pubkey::from_str(key).ok()).collect()
+}
+
+/// The addresses a versioned transaction loaded from lookup tables.
+fn loaded_addresses(meta: &UiTransactionStatusMeta) -> Option<LoadedAddresses> {
+    match Option::<&UiLoadedAddresses>::from(meta.loaded_addresses.as_ref()) {
+        Some(loaded_addresses) => Some(LoadedAddresses {
+            writable: parse_keys(&loaded_addresses.writable)?,
+            readonly: parse_keys(&loaded_addresses.readonly)?,
+        }),
+        None => Some(LoadedAddresses::default()),
+    }
+}
+
+/// Decode the events of a transaction, none when it failed. `None` when it
+/// cannot be decoded.
+fn decode_transaction(
+    program_id: &Pubkey,
+    slot: u64,
+    transaction: &EncodedTransactionWithStatusMeta,
+) -> Option<(Vec<InscriptionEvent>, usize)> {
+    let meta = transaction.meta.as_ref()?;
+    if meta.err.is_some() {
+        return Some((vec![], 0));
+    }
+    let (signature, static_keys, instructions) = transaction_parts(&transaction.transaction)?;
+    let loaded_addresses = loaded_addresses(meta)?;
+    let account_keys = AccountKeys::new(&static_keys, Some(&loaded_addresses));
+    Some(decode_inscription_events(
+        program_id,
+        slot,
+        &signature,
+        &account_keys,
+        &instructions,
+    ))
+}
+
+/// Decode the inscription instructions of the successful transactions of
+/// `block`, as the plugin does for the transactions the validator notifies.
+/// Fails when the block was saved without its transactions.
+pub fn decode_block(
+    program_id: &Pubkey,
+    slot: u64,
+    block: &UiConfirmedBlock,
+) -> Result<BlockEvents, String> {
+    let transactions = block.transactions.as_deref().ok_or_else(|| {
+        format!(
+            "The block of the slot {} was saved without its transactions, \
+            \"transactionDetails\" must be \"full\"",
+            slot
+        )
+    })?;
+    let mut block_events = BlockEvents {
+        slot,
+        parent_slot: Some(block.parent_slot),
+        transactions: transactions.len(),
+        ..BlockEvents::default()
+    };
+    for transaction in transactions {
+        match decode_transaction(program_id, slot, transaction) {
+            Some((events, decode_failures)) => {
+                block_events.events.extend(events);
+                block_events.decode_failures += decode_failures;
+            }
+            None => block_events.undecoded_transactions += 1,
+        }
+    }
+    Ok(block_events)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        domichain_sdk::{
+            instruction::{AccountMeta, Instruction},
+            message::Message,
+            transaction::Transaction,
+        },
+        domichain_transaction_status::{Encodable, UiTransactionEncoding},
+        serde_json::json,
+    };
+
+    fn transaction() -> Transaction {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[3, 1, 4, 1, 5],
+            vec![
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(payer, true),
+            ],
+        );
+        Transaction::new_unsigned(Message::new(&[instruction], Some(&payer)))
+    }
+
+    #[test]
+    fn test_transaction_parts_encodings() {
+        let transaction = transaction();
+        for encoding in [
+            UiTransactionEncoding::Binary,
+            UiTransactionEncoding::Base58,
+            UiTransactionEncoding::Base64,
+            UiTransactionEncoding::Json,
+        ] {
+            let (signature, account_keys, instructions) =
+                transaction_parts(&transaction.encode(encoding))
+                    .unwrap_or_else(|| panic!("{:?} is not decoded", encoding));
+            assert_eq!(signature, transaction.signatures[0]);
+            assert_eq!(account_keys, transaction.message.account_keys);
+            assert_eq!(instructions, transaction.message.instructions);
+        }
+    }
+
+    #[test]
+    fn test_transaction_parts_without_instructions() {
+        let transaction = transaction();
+        assert!(
+            transaction_parts(&transaction.encode(UiTransactionEncoding::JsonParsed)).is_none()
+        );
+
+        let mut encoded =
+            serde_json::to_value(transaction.encode(UiTransactionEncoding::Json)).unwrap();
+        encoded["message"]["accountKeys"][0] = json!("not a key");
+        let encoded: EncodedTransaction = serde_json::from_value(encoded).unwrap();
+        assert!(transaction_parts(&encoded).is_none());
+    }
+
+    fn meta(loaded_addresses: Option<Value>) -> UiTransactionStatusMeta {
+        let mut meta = json!({
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+        });
+        if let Some(loaded_addresses) = loaded_addresses {
+            meta["loadedAddresses"] = loaded_addresses;
+        }
+        serde_json::from_value(meta).unwrap()
+    }
+
+    #[test]
+    fn test_loaded_addresses() {
+        let writable = Pubkey::new_unique();
+        let readonly = Pubkey::new_unique();
+        assert_eq!(
+            loaded_addresses(&meta(Some(json!({
+                "writable": [writable.to_string()],
+                "readonly": [readonly.to_string()],
+            })))),
+            Some(LoadedAddresses {
+                writable: vec![writable],
+                readonly: vec![readonly],
+            })
+        );
+
+        // Legacy transactions load nothing.
+        assert_eq!(
+            loaded_addresses(&meta(None)),
+            Some(LoadedAddresses::default())
+        );
+
+        assert_eq!(
+            loaded_addresses(&meta(Some(json!({
+                "writable": ["not a key"],
+                "readonly": [],
+            })))),
+            None
+        );
+    }
+}
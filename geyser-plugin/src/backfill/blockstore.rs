@@ -0,0 +1,224 @@
+/// Reads the rooted blocks of the ledger of a node, e.g. an archive node,
+/// with their transaction statuses. The `Blockstore` is opened as a
+/// secondary instance, which only reads the ledger and may be opened while
+/// the node runs. The statuses are only kept by nodes with the transaction
+/// history enabled.
+use {
+    crate::{
+        backfill::{BlockEvents, BlockSource, SlotBlock},
+        postgres_client::postgres_client_transaction::decode_inscription_events,
+    },
+    domichain_ledger::{
+        blockstore::Blockstore,
+        blockstore_db::BlockstoreError,
+        blockstore_options::{AccessType, BlockstoreOptions},
+    },
+    domichain_sdk::{message::AccountKeys, pubkey::Pubkey},
+    domichain_transaction_status::VersionedConfirmedBlock,
+    std::path::Path,
+};
+
+/// The rooted blocks of a ledger.
+pub struct LedgerBlocks {
+    blockstore: Blockstore,
+}
+
+impl LedgerBlocks {
+    /// Open the ledger of `ledger_path`, the directory of the node's
+    /// `--ledger` option.
+    pub fn open(ledger_path: &Path) -> Result<Self, String> {
+        Blockstore::open_with_options(
+            ledger_path,
+            BlockstoreOptions {
+                access_type: AccessType::Secondary,
+                enforce_ulimit_nofile: false,
+                ..BlockstoreOptions::default()
+            },
+        )
+        .map(|blockstore| Self { blockstore })
+        .map_err(|err| format!("Failed to open the ledger {:?}: ({})", ledger_path, err))
+    }
+}
+
+/// Decode the inscription instructions of the successful transactions of
+/// `block`, as the plugin does for the transactions the validator notifies.
+pub fn decode_block(
+    program_id: &Pubkey,
+    slot: u64,
+    block: &VersionedConfirmedBlock,
+) -> BlockEvents {
+    let mut block_events = BlockEvents {
+        slot,
+        parent_slot: Some(block.parent_slot),
+        transactions: block.transactions.len(),
+        ..BlockEvents::default()
+    };
+    for transaction in &block.transactions {
+        if transaction.meta.status.is_err() {
+            continue;
+        }
+        let signature = match transaction.transaction.signatures.first() {
+            Some(signature) => signature,
+            None => {
+                block_events.undecoded_transactions += 1;
+                continue;
+            }
+        };
+        let message = &transaction.transaction.message;
+        let (events, decode_failures) = decode_inscription_events(
+            program_id,
+            slot,
+            signature,
+            &AccountKeys::new(
+                message.static_account_keys(),
+                Some(&transaction.meta.loaded_addresses),
+            ),
+            message.instructions(),
+        );
+        block_events.events.extend(events);
+        block_events.decode_failures += decode_failures;
+    }
+    block_events
+}
+
+impl BlockSource for LedgerBlocks {
+    fn slots(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>, String> {
+        Ok(self
+            .blockstore
+            .rooted_slot_iterator(start_slot)
+            .map_err(|err| format!("Failed to read the roots of the ledger: ({})", err))?
+            .take_while(|slot| *slot <= end_slot)
+            .collect())
+    }
+
+    fn read_block(&self, program_id: &Pubkey, slot: u64) -> Result<SlotBlock, String> {
+        match self.blockstore.get_rooted_block(slot, false) {
+            Ok(block) => Ok(SlotBlock::Block(decode_block(program_id, slot, &block))),
+            Err(BlockstoreError::SlotUnavailable | BlockstoreError::SlotCleanedUp) => {
+                Ok(SlotBlock::Missing)
+            }
+            Err(BlockstoreError::MissingTransactionMetadata) => Err(format!(
+                "The ledger has no transaction status for the slot {}, the node must run with \
+                --enable-rpc-transaction-history",
+                slot
+            )),
+            Err(err) => Err(format!(
+                "Failed to read the block of the slot {} from the ledger: ({})",
+                slot, err
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        borsh::BorshDeserialize,
+        domichain_sdk::{
+            instruction::{AccountMeta, Instruction},
+            message::Message,
+            signature::Signature,
+            transaction::{Transaction, TransactionError, VersionedTransaction},
+        },
+        domichain_transaction_status::{TransactionStatusMeta, VersionedTransactionWithStatusMeta},
+        mpl_inscription_program::instruction::MplInscriptionInstruction,
+    };
+
+    /// The data of a `Close` instruction, which has no arguments.
+    fn close_data() -> Vec<u8> {
+        (0..=u8::MAX)
+            .map(|discriminant| vec![discriminant])
+            .find(|data| {
+                matches!(
+                    MplInscriptionInstruction::try_from_slice(data),
+                    Ok(MplInscriptionInstruction::Close)
+                )
+            })
+            .expect("no data decodes as Close")
+    }
+
+    fn transaction(
+        instructions: &[Instruction],
+        status: Result<(), TransactionError>,
+    ) -> VersionedTransactionWithStatusMeta {
+        let payer = Pubkey::new_unique();
+        let mut transaction = Transaction::new_unsigned(Message::new(instructions, Some(&payer)));
+        transaction.signatures[0] = Signature::new_unique();
+        VersionedTransactionWithStatusMeta {
+            transaction: VersionedTransaction::from(transaction),
+            meta: TransactionStatusMeta {
+                status,
+                ..TransactionStatusMeta::default()
+            },
+        }
+    }
+
+    fn block(transactions: Vec<VersionedTransactionWithStatusMeta>) -> VersionedConfirmedBlock {
+        VersionedConfirmedBlock {
+            previous_blockhash: String::default(),
+            blockhash: String::default(),
+            parent_slot: 41,
+            transactions,
+            rewards: vec![],
+            block_time: None,
+            block_height: None,
+        }
+    }
+
+    #[test]
+    fn test_decode_block() {
+        let program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let close = Instruction::new_with_bytes(
+            program_id,
+            &close_data(),
+            accounts
+                .iter()
+                .map(|key| AccountMeta::new(*key, false))
+                .collect(),
+        );
+        let undecodable = Instruction::new_with_bytes(program_id, &[], vec![]);
+        let other_program = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+
+        let decoded = transaction(&[other_program, close.clone(), undecodable], Ok(()));
+        let signature = decoded.transaction.signatures[0];
+        let mut unsigned = transaction(&[close.clone()], Ok(()));
+        unsigned.transaction.signatures.clear();
+        let block = block(vec![
+            decoded,
+            transaction(&[close], Err(TransactionError::AccountNotFound)),
+            unsigned,
+        ]);
+
+        let block_events = decode_block(&program_id, 42, &block);
+        assert_eq!(block_events.slot, 42);
+        assert_eq!(block_events.parent_slot, Some(41));
+        assert_eq!(block_events.transactions, 3);
+        assert_eq!(block_events.undecoded_transactions, 1);
+        assert_eq!(block_events.decode_failures, 1);
+
+        // Only the successful transaction is decoded.
+        assert_eq!(block_events.events.len(), 1);
+        let event = &block_events.events[0];
+        assert_eq!(event.slot, 42);
+        assert_eq!(event.signature, signature);
+        assert_eq!(event.instruction_index, 1);
+        assert_eq!(event.inscription_account, Some(accounts[0]));
+        assert_eq!(event.authority, Some(accounts[2]));
+        assert_eq!(event.accounts, accounts);
+    }
+
+    #[test]
+    fn test_decode_empty_block() {
+        let block_events = decode_block(&Pubkey::new_unique(), 7, &block(vec![]));
+        assert_eq!(
+            block_events,
+            BlockEvents {
+                slot: 7,
+                parent_slot: Some(41),
+                ..BlockEvents::default()
+            }
+        );
+    }
+}
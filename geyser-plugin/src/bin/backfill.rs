@@ -0,0 +1,312 @@
+/// Indexes the inscriptions of confirmed blocks, saved from the `getBlock`
+/// RPC method or read from the ledger of a node, into the sinks of a plugin
+/// configuration, for the history before the plugin was deployed or the
+/// slots it missed. The blocks are written in slot order, and the progress
+/// file, when given, lets an interrupted backfill resume after the last block
+/// written, trying the missing blocks again.
+use {
+    clap::{crate_description, crate_version, value_t_or_exit, App, Arg},
+    domichain_geyser_plugin_inscriptions::{
+        backfill::{
+            block_json::BlockFiles, blockstore::LedgerBlocks, select_sinks, BackfillProgress,
+            BackfillWriter, BlockSource, SlotBlock,
+        },
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, SinkConfig},
+        postgres_client::{
+            postgres_client_backfill, ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    domichain_sdk::pubkey::Pubkey,
+    log::*,
+    std::{
+        path::{Path, PathBuf},
+        process::exit,
+        time::{Duration, Instant},
+    },
+};
+
+/// How often the progress is logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct Summary {
+    blocks: usize,
+    skipped_slots: usize,
+    missing_slots: usize,
+    events: usize,
+    undecoded_transactions: usize,
+    decode_failures: usize,
+}
+
+fn backfill_blocks(
+    config: &GeyserPluginPostgresConfig,
+    program_id: &Pubkey,
+    source: &dyn BlockSource,
+    slots: &[u64],
+    mut progress: Option<BackfillProgress>,
+) -> Result<Summary, String> {
+    let mut writer = BackfillWriter::new(config).map_err(|err| err.to_string())?;
+    if let Some((first, last)) = slots.first().zip(slots.last()) {
+        writer.check_slots(*first, *last)?;
+    }
+    let mut summary = Summary::default();
+    let start = Instant::now();
+    let mut last_report = start;
+    for (index, slot) in slots.iter().copied().enumerate() {
+        let missing = match source.read_block(program_id, slot)? {
+            SlotBlock::Block(block) => {
+                if block.undecoded_transactions > 0 {
+                    warn!(
+                        "{} transactions of the slot {} could not be decoded",
+                        block.undecoded_transactions, slot
+                    );
+                }
+                writer
+                    .write_block(&block)
+                    .map_err(|err| format!("Failed to write the slot {}: {}", slot, err))?;
+                summary.blocks += 1;
+                summary.events += block.events.len();
+                summary.undecoded_transactions += block.undecoded_transactions;
+                summary.decode_failures += block.decode_failures;
+                false
+            }
+            SlotBlock::Skipped => {
+                summary.skipped_slots += 1;
+                false
+            }
+            SlotBlock::Missing => {
+                warn!("The block of the slot {} is missing from the source", slot);
+                summary.missing_slots += 1;
+                true
+            }
+        };
+        match &mut progress {
+            Some(progress) if missing => progress.record_missing(slot)?,
+            Some(progress) => progress.record(slot)?,
+            None => {}
+        }
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL || index + 1 == slots.len() {
+            last_report = Instant::now();
+            info!(
+                "Backfilled {} of {} slots, up to the slot {}: {} events, {:.1} slots/s",
+                index + 1,
+                slots.len(),
+                slot,
+                summary.events,
+                (index + 1) as f64 / start.elapsed().as_secs_f64().max(0.001)
+            );
+        }
+    }
+    Ok(summary)
+}
+
+fn mark_gaps_filled(
+    config: &GeyserPluginPostgresConfig,
+    program_id: &Pubkey,
+    (start_slot, end_slot): (u64, u64),
+) -> Result<u64, String> {
+    let layout = SchemaLayout::new(config).map_err(|err| err.to_string())?;
+    let mut client = SimplePostgresClient::connect_to_db(config).map_err(|err| err.to_string())?;
+    postgres_client_backfill::mark_gaps_filled(
+        &mut client,
+        &layout,
+        program_id,
+        start_slot,
+        end_slot,
+    )
+    .map_err(|err| err.to_string())
+}
+
+fn main() {
+    domichain_logger::setup_with_default("info");
+    let matches = App::new("inscriptions-backfill")
+        .about(crate_description!())
+        .version(crate_version!())
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .help("The plugin configuration file, for the sinks to write to"),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .value_name("FILE")
+                .takes_value(true)
+                .help(
+                    "Record the last slot written, and the missing blocks, in this file, and \
+                    skip the slots up to the one it holds but the missing blocks",
+                ),
+        )
+        .arg(
+            Arg::with_name("start_slot")
+                .long("start-slot")
+                .value_name("SLOT")
+                .takes_value(true)
+                .help("Backfill the blocks from this slot on"),
+        )
+        .arg(
+            Arg::with_name("end_slot")
+                .long("end-slot")
+                .value_name("SLOT")
+                .takes_value(true)
+                .help("Backfill the blocks up to this slot, included"),
+        )
+        .arg(
+            Arg::with_name("mark_gaps_filled")
+                .long("mark-gaps-filled")
+                .help(
+                    "Once done, mark the backfill gaps within the slots backfilled as filled. \
+                    The inputs must hold every block of these slots, backfilled in one run",
+                ),
+        )
+        .arg(
+            Arg::with_name("sinks")
+                .long("sinks")
+                .value_name("TYPE")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .help(
+                    "Write to the configured sinks of these types, e.g. postgres,webhook. \
+                    By default, only to the postgres, json_lines, sqlite and parquet sinks",
+                ),
+        )
+        .arg(
+            Arg::with_name("ledger")
+                .long("ledger")
+                .value_name("DIR")
+                .takes_value(true)
+                .conflicts_with("inputs")
+                .help(
+                    "Read the rooted blocks of the ledger of a node, opened read only, \
+                    instead of getBlock responses",
+                ),
+        )
+        .arg(
+            Arg::with_name("inputs")
+                .index(1)
+                .value_name("INPUT")
+                .takes_value(true)
+                .multiple(true)
+                .required_unless("ledger")
+                .help(
+                    "The getBlock responses, as <slot>.json or <slot>.json.gz files, or \
+                    directories of them",
+                ),
+        )
+        .get_matches();
+
+    let config_file = matches.value_of("config").unwrap();
+    let config = GeyserPluginPostgresConfig::load(config_file).unwrap_or_else(|err| {
+        eprintln!("Failed to read {:?}: {}", config_file, err);
+        exit(1);
+    });
+    let kinds = matches
+        .values_of("sinks")
+        .map(|kinds| kinds.collect::<Vec<_>>());
+    let config = select_sinks(&config, kinds.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
+    });
+    info!(
+        "Backfilling into the sinks {}",
+        config
+            .sinks()
+            .iter()
+            .map(|sink| sink.kind())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let program_id = ParallelPostgresClient::parse_program_id(&config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
+    });
+    let start_slot = if matches.is_present("start_slot") {
+        value_t_or_exit!(matches, "start_slot", u64)
+    } else {
+        0
+    };
+    let end_slot = if matches.is_present("end_slot") {
+        value_t_or_exit!(matches, "end_slot", u64)
+    } else {
+        u64::MAX
+    };
+    let mark_gaps = matches.is_present("mark_gaps_filled");
+    if mark_gaps && !config.sinks().contains(&SinkConfig::Postgres) {
+        eprintln!("--mark-gaps-filled requires the \"Postgres\" sink");
+        exit(1);
+    }
+    let source: Result<Box<dyn BlockSource>, String> = match matches.value_of("ledger") {
+        Some(ledger_path) => {
+            LedgerBlocks::open(Path::new(ledger_path)).map(|source| Box::new(source) as _)
+        }
+        None => BlockFiles::new(
+            &matches
+                .values_of("inputs")
+                .unwrap()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>(),
+        )
+        .map(|source| Box::new(source) as _),
+    };
+
+    let result = source.and_then(|source| {
+        let slots = source.slots(start_slot, end_slot)?;
+        let progress = matches
+            .value_of("progress")
+            .map(|path| BackfillProgress::load(Path::new(path)))
+            .transpose()?;
+        // The blocks written by earlier runs are not known to be complete.
+        if mark_gaps && progress.iter().any(BackfillProgress::is_resumed) {
+            return Err(
+                "--mark-gaps-filled requires backfilling the whole range in one run, and the \
+                progress file records an earlier run"
+                    .to_string(),
+            );
+        }
+        let pending = slots
+            .iter()
+            .copied()
+            .filter(|slot| progress.iter().all(|progress| !progress.is_done(*slot)))
+            .collect::<Vec<_>>();
+        info!("Backfilling {} slots", pending.len());
+        let summary = backfill_blocks(&config, &program_id, source.as_ref(), &pending, progress)?;
+        let filled_gaps = match slots.first().zip(slots.last()) {
+            Some(_) if mark_gaps && summary.missing_slots > 0 => {
+                warn!("The backfill gaps are not marked as filled: blocks are missing");
+                0
+            }
+            Some((first, last)) if mark_gaps => {
+                mark_gaps_filled(&config, &program_id, (*first, *last))?
+            }
+            _ => 0,
+        };
+        Ok((summary, filled_gaps))
+    });
+
+    match result {
+        Ok((summary, filled_gaps)) => {
+            info!(
+                "Backfilled {} blocks, {} skipped slots and {} missing blocks: {} events, {} \
+                undecoded transactions, {} inscription instructions which failed to decode",
+                summary.blocks,
+                summary.skipped_slots,
+                summary.missing_slots,
+                summary.events,
+                summary.undecoded_transactions,
+                summary.decode_failures
+            );
+            if mark_gaps {
+                info!("Marked {} backfill gaps as filled", filled_gaps);
+            }
+        }
+        Err(err) => {
+            eprintln!("Backfill failed: {}", err);
+            exit(1);
+        }
+    }
+}
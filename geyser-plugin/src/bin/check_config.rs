@@ -0,0 +1,120 @@
+/// Checks a plugin configuration file without loading it in a validator: the
+/// file is parsed and validated, then, with the `postgres` sink, the
+/// connection to the PostgreSQL database is tested and its schema version
+/// compared to the one the plugin requires. Nothing is written to the
+/// database.
+use {
+    clap::{crate_description, crate_version, App, Arg},
+    domichain_geyser_plugin_inscriptions::{
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, SinkConfig},
+        postgres_client::{
+            postgres_client_backfill::{self, BackfillGap},
+            postgres_client_migration::{self, SCHEMA_VERSION},
+            ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    std::process::exit,
+};
+
+/// Connect to the database and check its schema, returning the warnings.
+fn check_database(config: &GeyserPluginPostgresConfig) -> Result<Vec<String>, String> {
+    let layout = SchemaLayout::new(config).map_err(|err| err.to_string())?;
+    let mut client = SimplePostgresClient::connect_to_db(config).map_err(|err| err.to_string())?;
+    let server_version: String = client
+        .query_one("SHOW server_version", &[])
+        .map_err(|err| format!("Failed to query the server version: {}", err))?
+        .get(0);
+    println!("Connected to PostgreSQL {}", server_version);
+
+    let version = postgres_client_migration::current_version(&mut client, &layout)
+        .map_err(|err| format!("Failed to read the schema version: {}", err))?;
+    let auto_migrate = config.auto_migrate.unwrap_or_default();
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "The database schema is at version {}, ahead of the version {} this plugin supports",
+            version, SCHEMA_VERSION
+        ));
+    }
+    if version < SCHEMA_VERSION {
+        let msg = format!(
+            "The database schema is at version {}, behind the version {} this plugin requires",
+            version, SCHEMA_VERSION
+        );
+        if !auto_migrate {
+            return Err(format!(
+                "{}. Set \"auto_migrate\" to true to apply the pending migrations.",
+                msg
+            ));
+        }
+        return Ok(vec![format!(
+            "{}. The pending migrations will be applied at load time.",
+            msg
+        )]);
+    }
+    postgres_client_migration::check_key_storage(&mut client, &layout)
+        .map_err(|err| err.to_string())?;
+    println!("The database schema is at version {}", version);
+
+    let program_id =
+        ParallelPostgresClient::parse_program_id(config).map_err(|err| err.to_string())?;
+    let gaps = postgres_client_backfill::open_gaps(&mut client, &layout, &program_id)
+        .map_err(|err| err.to_string())?;
+    if gaps.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(vec![format!(
+        "{} backfill gaps of {} slots in total are not filled yet, the first one from the slot {}",
+        gaps.len(),
+        gaps.iter().map(BackfillGap::slot_count).sum::<u64>(),
+        gaps[0].start_slot
+    )])
+}
+
+fn main() {
+    let matches = App::new("inscriptions-check-config")
+        .about(crate_description!())
+        .version(crate_version!())
+        .arg(
+            Arg::with_name("config")
+                .index(1)
+                .value_name("CONFIG")
+                .takes_value(true)
+                .required(true)
+                .help("The plugin configuration file"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("Only check the configuration file, without connecting to the database"),
+        )
+        .get_matches();
+
+    let config_file = matches.value_of("config").unwrap();
+    let config = GeyserPluginPostgresConfig::load(config_file).unwrap_or_else(|err| {
+        eprintln!("error: Failed to read {:?}: {}", config_file, err);
+        exit(1);
+    });
+
+    let report = config.validate();
+    let mut warnings = report.warnings;
+    let mut errors = report.errors;
+    // The grpc sink only reads the database along with the postgres sink.
+    let uses_database = config.sinks().contains(&SinkConfig::Postgres);
+    if errors.is_empty() && uses_database && !matches.is_present("offline") {
+        match check_database(&config) {
+            Ok(database_warnings) => warnings.extend(database_warnings),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+    if !errors.is_empty() {
+        exit(1);
+    }
+    println!("The configuration {:?} is valid", config_file);
+}
@@ -0,0 +1,230 @@
+/// Exports the inscription history of the PostgreSQL database configured in
+/// the plugin configuration file to Parquet files, in the layout written by
+/// the `parquet` sink.
+use {
+    clap::{crate_description, crate_version, value_t_or_exit, App, Arg},
+    domichain_geyser_plugin_inscriptions::{
+        geyser_plugin_postgres::GeyserPluginPostgresConfig,
+        inscription_sink::parquet_archive::{
+            InscriptionRow, PartitionedParquetWriter, DEFAULT_MAX_ROWS_PER_FILE,
+            DEFAULT_SLOTS_PER_PARTITION,
+        },
+        postgres_client::{
+            postgres_client_transaction::{DbKey, InscriptionEvent},
+            ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    domichain_sdk::pubkey::Pubkey,
+    log::*,
+    postgres::Client,
+    std::{path::PathBuf, process::exit},
+};
+
+/// The number of rows read per query.
+const PAGE_SIZE: i64 = 10_000;
+
+const EVENTS_QUERY: &str = "SELECT slot, signature, instruction_index, instruction_type, \
+        inscription_account, metadata_account, mint_account, authority, accounts, updated_on \
+    FROM {schema}.{prefix}inscription_events \
+    WHERE (slot, signature, instruction_index) > ($1, $2, $3) AND slot <= $4 \
+    ORDER BY slot, signature, instruction_index LIMIT $5";
+
+const INSCRIPTIONS_QUERY: &str = "SELECT slot, signature, account, mint_account, \
+        metadata_account, authority, updated_on \
+    FROM {schema}.{prefix}inscriptions \
+    WHERE account > $1 AND slot BETWEEN $2 AND $3 \
+    ORDER BY account LIMIT $4";
+
+fn export_events(
+    client: &mut Client,
+    layout: &SchemaLayout,
+    writer: &mut PartitionedParquetWriter,
+    program_id: Pubkey,
+    (start_slot, end_slot): (i64, i64),
+) -> Result<usize, String> {
+    let statement = client
+        .prepare(&layout.render(EVENTS_QUERY))
+        .map_err(|err| err.to_string())?;
+    let (mut slot, mut signature, mut instruction_index) =
+        (start_slot, DbKey::min(layout.key_storage()), -1);
+    let mut count = 0;
+    loop {
+        let rows = client
+            .query(
+                &statement,
+                &[&slot, &signature, &instruction_index, &end_slot, &PAGE_SIZE],
+            )
+            .map_err(|err| err.to_string())?;
+        for row in &rows {
+            let event = InscriptionEvent::from_row(row, program_id)?;
+            slot = row.get(0);
+            signature = row.get(1);
+            instruction_index = row.get(2);
+            writer.push_event(event, row.get(9))?;
+        }
+        count += rows.len();
+        info!("Exported {} events, up to slot {}", count, slot);
+
+        if (rows.len() as i64) < PAGE_SIZE {
+            return Ok(count);
+        }
+    }
+}
+
+fn export_inscriptions(
+    client: &mut Client,
+    layout: &SchemaLayout,
+    writer: &mut PartitionedParquetWriter,
+    (start_slot, end_slot): (i64, i64),
+) -> Result<usize, String> {
+    let statement = client
+        .prepare(&layout.render(INSCRIPTIONS_QUERY))
+        .map_err(|err| err.to_string())?;
+    let mut account = DbKey::min(layout.key_storage());
+    let mut count = 0;
+    loop {
+        let rows = client
+            .query(&statement, &[&account, &start_slot, &end_slot, &PAGE_SIZE])
+            .map_err(|err| err.to_string())?;
+
+        for row in &rows {
+            account = row.get(2);
+            let key = |index| {
+                row.get::<_, Option<DbKey>>(index)
+                    .map(|key| key.to_pubkey().map(|key| key.to_string()))
+                    .transpose()
+            };
+            let inscription = InscriptionRow {
+                slot: row.get(0),
+                signature: row.get::<_, DbKey>(1).to_signature()?.to_string(),
+                account: account.to_pubkey()?.to_string(),
+                mint_account: key(3)?,
+                metadata_account: key(4)?,
+                authority: key(5)?.unwrap_or_default(),
+            };
+            writer.push_inscription(inscription, row.get(6))?;
+        }
+        count += rows.len();
+        info!("Exported {} inscriptions", count);
+
+        if (rows.len() as i64) < PAGE_SIZE {
+            return Ok(count);
+        }
+    }
+}
+
+fn main() {
+    domichain_logger::setup_with_default("info");
+    let matches = App::new("inscriptions-export-parquet")
+        .about(crate_description!())
+        .version(crate_version!())
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .help("The plugin configuration file, for the database connection"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("DIR")
+                .takes_value(true)
+                .required(true)
+                .help("The directory the files are written to"),
+        )
+        .arg(
+            Arg::with_name("slots_per_partition")
+                .long("slots-per-partition")
+                .value_name("SLOTS")
+                .takes_value(true)
+                .help("The number of slots of a partition [default: 432000]"),
+        )
+        .arg(
+            Arg::with_name("max_rows_per_file")
+                .long("max-rows-per-file")
+                .value_name("ROWS")
+                .takes_value(true)
+                .help("The maximum number of rows of a file [default: 1000000]"),
+        )
+        .arg(
+            Arg::with_name("start_slot")
+                .long("start-slot")
+                .value_name("SLOT")
+                .takes_value(true)
+                .help("Export the rows from this slot on"),
+        )
+        .arg(
+            Arg::with_name("end_slot")
+                .long("end-slot")
+                .value_name("SLOT")
+                .takes_value(true)
+                .help("Export the rows up to this slot, included"),
+        )
+        .get_matches();
+
+    let config_file = matches.value_of("config").unwrap();
+    let config = GeyserPluginPostgresConfig::load(config_file).unwrap_or_else(|err| {
+        eprintln!("Failed to read {:?}: {}", config_file, err);
+        exit(1);
+    });
+    let slots_per_partition = if matches.is_present("slots_per_partition") {
+        value_t_or_exit!(matches, "slots_per_partition", u64)
+    } else {
+        DEFAULT_SLOTS_PER_PARTITION
+    };
+    let max_rows_per_file = if matches.is_present("max_rows_per_file") {
+        value_t_or_exit!(matches, "max_rows_per_file", usize)
+    } else {
+        DEFAULT_MAX_ROWS_PER_FILE
+    };
+    let start_slot = if matches.is_present("start_slot") {
+        value_t_or_exit!(matches, "start_slot", i64)
+    } else {
+        0
+    };
+    let end_slot = if matches.is_present("end_slot") {
+        value_t_or_exit!(matches, "end_slot", i64)
+    } else {
+        i64::MAX
+    };
+
+    let result = ParallelPostgresClient::parse_program_id(&config)
+        .and_then(|program_id| Ok((program_id, SchemaLayout::new(&config)?)))
+        .and_then(|(program_id, layout)| {
+            SimplePostgresClient::connect_to_db(&config).map(|client| (program_id, layout, client))
+        })
+        .map_err(|err| err.to_string())
+        .and_then(|(program_id, layout, mut client)| {
+            let mut writer = PartitionedParquetWriter::new(
+                PathBuf::from(matches.value_of("output").unwrap()),
+                slots_per_partition,
+                max_rows_per_file,
+            )?;
+            let events = export_events(
+                &mut client,
+                &layout,
+                &mut writer,
+                program_id,
+                (start_slot, end_slot),
+            )?;
+            let inscriptions =
+                export_inscriptions(&mut client, &layout, &mut writer, (start_slot, end_slot))?;
+            writer.flush()?;
+            Ok((events, inscriptions))
+        });
+
+    match result {
+        Ok((events, inscriptions)) => {
+            info!(
+                "Exported {} events and {} inscriptions",
+                events, inscriptions
+            );
+        }
+        Err(err) => {
+            eprintln!("Export failed: {}", err);
+            exit(1);
+        }
+    }
+}
@@ -1,5 +1,19 @@
 use {
-    crate::postgres_client::{ParallelPostgresClient, PostgresClientBuilder},
+    crate::{
+        health::{self, HealthThresholds},
+        http_server::HttpServer,
+        inscription_sink::{
+            grpc::GrpcSinkConfig, json_lines::JsonLinesSinkConfig,
+            parquet_archive::ParquetSinkConfig, sqlite::SqliteSinkConfig,
+            webhook::WebhookSinkConfig, websocket::WebsocketSinkConfig,
+        },
+        metrics::PluginMetrics,
+        postgres_client::{
+            postgres_client_partition, ParallelPostgresClient, PostgresClientBuilder, SchemaLayout,
+            SimplePostgresClient,
+        },
+        prometheus, secrets,
+    },
     bs58,
     domichain_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPlugin, GeyserPluginError, ReplicaAccountInfoVersions, ReplicaBlockInfoVersions,
@@ -11,14 +25,64 @@ use {
     log::*,
     serde_derive::{Deserialize, Serialize},
     serde_json,
-    std::{fs::File, io::Read},
+    std::{
+        fs::File,
+        io::Read,
+        net::SocketAddr,
+        path::Path,
+        sync::atomic::{AtomicBool, Ordering},
+    },
     thiserror::Error,
 };
 
+const DEFAULT_HEALTH_QUEUE_THRESHOLD: u64 = 20480;
+const DEFAULT_HEALTH_DB_ERROR_WINDOW_SECS: u64 = 60;
+
 #[derive(Default)]
 pub struct GeyserPluginPostgres {
     client: Option<ParallelPostgresClient>,
+    metrics_server: Option<HttpServer>,
+    health_server: Option<HttpServer>,
     config: GeyserPluginPostgresConfig,
+    slot_window: SlotWindow,
+}
+
+/// The `start_slot` and `end_slot` bounds of the recorded slots.
+#[derive(Debug, Default)]
+struct SlotWindow {
+    start_slot: u64,
+    end_slot: Option<u64>,
+    end_reported: AtomicBool,
+}
+
+impl SlotWindow {
+    fn new(config: &GeyserPluginPostgresConfig) -> Self {
+        Self {
+            start_slot: config.start_slot.unwrap_or_default(),
+            end_slot: config.end_slot,
+            end_reported: AtomicBool::default(),
+        }
+    }
+
+    fn contains(&self, slot: u64) -> bool {
+        slot >= self.start_slot && self.end_slot.map(|end| slot <= end).unwrap_or(true)
+    }
+
+    /// Report, once, that the window is complete when a slot at or beyond
+    /// `end_slot` is rooted: no update of the window can arrive anymore.
+    fn check_end(&self, slot: u64, status: SlotStatus, metrics: &PluginMetrics) {
+        let end_slot = match self.end_slot {
+            Some(end_slot) if slot >= end_slot && status == SlotStatus::Rooted => end_slot,
+            _ => return,
+        };
+        if !self.end_reported.swap(true, Ordering::Relaxed) {
+            metrics.end_slot_passed.store(true, Ordering::Relaxed);
+            info!(
+                "The slot {} is rooted, reaching \"end_slot\": {}. Nothing more will be recorded.",
+                slot, end_slot
+            );
+        }
+    }
 }
 
 impl std::fmt::Debug for GeyserPluginPostgres {
@@ -27,9 +91,14 @@ impl std::fmt::Debug for GeyserPluginPostgres {
     }
 }
 
-/// The Configuration for the PostgreSQL plugin
+/// The Configuration for the PostgreSQL plugin. Unknown fields are
+/// rejected, so that a misspelled option is not silently ignored.
 #[derive(Default, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GeyserPluginPostgresConfig {
+    /// The path of the plugin library, read by the validator.
+    pub libpath: Option<String>,
+
     /// The host name or IP of the PostgreSQL server
     pub host: Option<String>,
 
@@ -43,11 +112,36 @@ pub struct GeyserPluginPostgresConfig {
     /// `host`, `user` and `port` will be ignored.
     pub connection_str: Option<String>,
 
+    /// The file holding the password of the PostgreSQL user, used when the
+    /// connection string has none. Without it, `PGPASSWORD` and then the
+    /// `PGPASSFILE` (`~/.pgpass` by default) entries are used.
+    pub password_file: Option<String>,
+
     /// Controls the number of threads establishing connections to
     /// the PostgreSQL server. The default is 10.
     pub threads: Option<usize>,
 
-    /// Controls the batch size when bulk loading accounts.
+    /// Selects how items are written to the database: `threaded` runs
+    /// `threads` threads each holding a connection, `pipelined` drives
+    /// `connections` connections from a small tokio runtime with query
+    /// pipelining. The default is `threaded`.
+    pub writer: Option<WriterKind>,
+
+    /// Controls the number of connections used by the `pipelined` writer.
+    /// The default is 4.
+    pub connections: Option<usize>,
+
+    /// The minimum number of worker threads (connections for the
+    /// `pipelined` writer) which must be connected to the database, with
+    /// their statements prepared, before the plugin is considered loaded.
+    /// The default is 1.
+    pub min_connected_workers: Option<usize>,
+
+    /// The number of seconds to wait for `min_connected_workers` workers to
+    /// connect at load time before failing. The default is 30.
+    pub worker_startup_timeout_secs: Option<u64>,
+
+    /// Controls the maximum number of queued items a worker writes at once.
     /// The default is 10.
     pub batch_size: Option<usize>,
 
@@ -55,21 +149,421 @@ pub struct GeyserPluginPostgresConfig {
     /// writing to PostgreSQL server. The default is false
     pub panic_on_db_errors: Option<bool>,
 
+    /// Controls whether to apply the pending schema migrations at load time.
+    /// When false, the plugin refuses to start unless the `schema_version`
+    /// of the database is the one it requires. The default is false.
+    pub auto_migrate: Option<bool>,
+
+    /// The PostgreSQL schema the tables are created in and read from. It is
+    /// created by the first migration if missing. The default is "public".
+    pub schema: Option<String>,
+
+    /// The prefix of the names of the tables, and of their constraints and
+    /// indexes, e.g. "testnet_". Plugin instances with different schemas or
+    /// prefixes can share one database. The default is "".
+    pub table_prefix: Option<String>,
+
+    /// How the PostgreSQL sink stores pubkeys and signatures: `base58` as
+    /// TEXT, or `binary` as BYTEA, which halves their size. It is fixed
+    /// when the tables are created by the first migration. The default is
+    /// `base58`.
+    pub key_storage: Option<KeyStorage>,
+
+    /// The number of slots of each partition of the `inscription_events`
+    /// table created by the plugin. The default is 432000.
+    pub partition_slots: Option<u64>,
+
+    /// The number of partitions created ahead of the one holding the
+    /// latest slot. The default is 2.
+    pub partitions_ahead: Option<u64>,
+
+    /// Partitions whose slots are all more than this many slots older than
+    /// the latest rooted slot are detached from `inscription_events`. No
+    /// partition is detached when this is not set.
+    pub partition_retention_slots: Option<u64>,
+
+    /// The schema detached partitions are moved to. They are left in
+    /// `schema` when this is not set.
+    pub partition_archive_schema: Option<String>,
+
+    /// The channel the PostgreSQL sink announces every committed event on
+    /// with `pg_notify`, as `{"account": ..., "slot": ..., "event_type": ...}`.
+    /// No notification is sent when this is not set.
+    pub notify_channel: Option<String>,
+
     /// Controls whether to use SSL based connection to the database server.
+    /// Setting it to true without `ssl_mode` is the same as `verify-ca`.
     /// The default is false
     pub use_ssl: Option<bool>,
 
-    /// Specify the path to PostgreSQL server's certificate file
+    /// The TLS mode of the connections, named after the libpq `sslmode`:
+    /// `disable`, `require`, `verify-ca` or `verify-full`. It takes
+    /// precedence over `use_ssl`. The default is `disable`.
+    pub ssl_mode: Option<SslMode>,
+
+    /// Specify the path to PostgreSQL server's certificate file, the CA the
+    /// server certificate is verified against. The system trust store is
+    /// used when it is not set.
     pub server_ca: Option<String>,
 
-    /// Specify the path to the local client's certificate file
+    /// Specify the path to the local client's certificate file. Only needed
+    /// when the server requires client certificates, along with
+    /// `client_key`.
     pub client_cert: Option<String>,
 
     /// Specify the path to the local client's private PEM key file.
     pub client_key: Option<String>,
 
-    /// Program ID of the inscription program
+    /// The address, e.g. "127.0.0.1:9464", of the HTTP listener exposing the
+    /// plugin metrics in the Prometheus text format at `/metrics`. The
+    /// listener is not started when this is not set.
+    pub prometheus_listen_addr: Option<String>,
+
+    /// The address of the HTTP listener serving the `/health` and `/ready`
+    /// endpoints. The listener is not started when this is not set.
+    pub health_listen_addr: Option<String>,
+
+    /// The queue depth above which the health endpoint reports the indexer
+    /// as degraded. The default is 20480.
+    pub health_queue_threshold: Option<u64>,
+
+    /// For how many seconds a database error keeps the health endpoint
+    /// reporting the indexer as degraded. The default is 60.
+    pub health_db_error_window_secs: Option<u64>,
+
+    /// The first slot whose transactions and slot updates are recorded.
+    /// Earlier slots are ignored. The default is 0.
+    pub start_slot: Option<u64>,
+
+    /// The last slot, included, whose transactions and slot updates are
+    /// recorded. Later slots are ignored, and the plugin reports once a
+    /// slot at or beyond it is rooted. There is no upper bound by default.
+    pub end_slot: Option<u64>,
+
+    /// Program ID of the inscription program. The default is the
+    /// `mpl_inscription_program` ID.
     pub program_id: Option<String>,
+
+    /// The sinks the decoded inscription events are written to, e.g.
+    /// `[{"type": "postgres"}]`. The PostgreSQL connection is configured by
+    /// the fields above. The default is `[{"type": "postgres"}]`.
+    pub sinks: Option<Vec<SinkConfig>>,
+}
+
+impl GeyserPluginPostgresConfig {
+    /// Read the configuration from the JSON file `config_file`, replacing
+    /// the `${NAME}` references in its strings by the environment variables.
+    pub fn load(config_file: &str) -> Result<Self> {
+        let mut file = File::open(config_file)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let read_error = |err: serde_json::Error| GeyserPluginError::ConfigFileReadError {
+            msg: format!(
+                "The config file is not in the JSON format expected: {:?}",
+                err
+            ),
+        };
+        let mut value: serde_json::Value = serde_json::from_str(&contents).map_err(read_error)?;
+        secrets::interpolate_json(&mut value)
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        serde_json::from_value(value).map_err(read_error)
+    }
+
+    /// The effective TLS mode, from `ssl_mode` or else `use_ssl`.
+    pub fn ssl_mode(&self) -> SslMode {
+        match (self.ssl_mode, self.use_ssl) {
+            (Some(ssl_mode), _) => ssl_mode,
+            (None, Some(true)) => SslMode::VerifyCa,
+            (None, _) => SslMode::Disable,
+        }
+    }
+
+    /// The configured sinks, or the PostgreSQL sink when none are.
+    pub fn sinks(&self) -> Vec<SinkConfig> {
+        self.sinks
+            .clone()
+            .unwrap_or_else(|| vec![SinkConfig::Postgres])
+    }
+
+    /// Check the values of the options and their combinations, without
+    /// connecting to anything.
+    pub fn validate(&self) -> ConfigReport {
+        let mut report = ConfigReport::default();
+        report.check(ParallelPostgresClient::parse_program_id(self));
+        report.check(ParallelPostgresClient::worker_count(self));
+        if self.batch_size == Some(0) {
+            report.error("\"batch_size\" must be greater than 0");
+        }
+        if let (Some(start_slot), Some(end_slot)) = (self.start_slot, self.end_slot) {
+            if start_slot > end_slot {
+                report.error(format!(
+                    "\"start_slot\": {} must not be greater than \"end_slot\": {}",
+                    start_slot, end_slot
+                ));
+            }
+        }
+        match self.writer.unwrap_or_default() {
+            WriterKind::Threaded if self.connections.is_some() => {
+                report.warning("\"connections\" is ignored by the threaded writer")
+            }
+            WriterKind::Pipelined if self.threads.is_some() => {
+                report.warning("\"threads\" is ignored by the pipelined writer")
+            }
+            _ => {}
+        }
+        for (field, listen_addr) in [
+            ("prometheus_listen_addr", &self.prometheus_listen_addr),
+            ("health_listen_addr", &self.health_listen_addr),
+        ] {
+            if let Some(listen_addr) = listen_addr {
+                report.check(GeyserPluginPostgres::parse_listen_addr(field, listen_addr));
+            }
+        }
+
+        let sinks = self.sinks();
+        if sinks.is_empty() {
+            report.error("\"sinks\" must not be empty");
+        }
+        if sinks.contains(&SinkConfig::Postgres) {
+            self.validate_postgres(&mut report);
+        }
+        for (i, sink) in sinks.iter().enumerate() {
+            if sinks[..i].contains(sink) {
+                report.warning(format!("The sink {:?} is configured more than once", sink));
+            }
+            match sink {
+                SinkConfig::Postgres => {}
+                SinkConfig::JsonLines(config) => config.validate(&mut report),
+                SinkConfig::Sqlite(config) => config.validate(&mut report),
+                SinkConfig::Parquet(config) => config.validate(&mut report),
+                SinkConfig::Webhook(config) => config.validate(&mut report),
+                SinkConfig::Websocket(config) => config.validate(&mut report),
+                SinkConfig::Grpc(config) => config.validate(&mut report),
+            }
+        }
+        report
+    }
+
+    /// The checks of the options of the PostgreSQL sink. The TLS files and
+    /// the password file are read.
+    fn validate_postgres(&self, report: &mut ConfigReport) {
+        if self.connection_str.is_some()
+            && (self.host.is_some() || self.user.is_some() || self.port.is_some())
+        {
+            report.warning(
+                "\"host\", \"user\" and \"port\" are ignored when \"connection_str\" is set",
+            );
+        }
+        report.check(SimplePostgresClient::build_connection_config(self));
+        report.check(SchemaLayout::new(self));
+        if let Err(msg) = postgres_client_partition::check_config(self) {
+            report.error(msg);
+        }
+        if self.partition_archive_schema.is_some() && self.partition_retention_slots.is_none() {
+            report.warning(
+                "\"partition_archive_schema\" is ignored without \"partition_retention_slots\"",
+            );
+        }
+
+        if self.ssl_mode.is_some() && self.use_ssl.is_some() {
+            report.warning("\"use_ssl\" is ignored when \"ssl_mode\" is set");
+        }
+        if self.ssl_mode() == SslMode::Disable {
+            for (field, value) in [
+                ("server_ca", &self.server_ca),
+                ("client_cert", &self.client_cert),
+                ("client_key", &self.client_key),
+            ] {
+                if value.is_some() {
+                    report.warning(format!("\"{}\" is ignored without TLS", field));
+                }
+            }
+        } else {
+            let mut files_found = true;
+            for (field, path) in [
+                ("server_ca", &self.server_ca),
+                ("client_cert", &self.client_cert),
+                ("client_key", &self.client_key),
+            ] {
+                if let Some(path) = path {
+                    if !Path::new(path).is_file() {
+                        report.error(format!("\"{}\": {:?} is not a file", field, path));
+                        files_found = false;
+                    }
+                }
+            }
+            if self.ssl_mode() == SslMode::Require && self.server_ca.is_some() {
+                report.warning("\"server_ca\" is ignored by the \"require\" ssl_mode");
+            }
+            // Loads the files, checking they hold PEM certificates and keys.
+            if files_found {
+                report.check(SimplePostgresClient::build_tls_connector(self));
+            }
+        }
+    }
+}
+
+/// The problems found by `GeyserPluginPostgresConfig::validate`
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    /// The problems preventing the plugin from loading.
+    pub errors: Vec<String>,
+
+    /// The options which are ignored, or set to values likely unintended.
+    pub warnings: Vec<String>,
+}
+
+impl ConfigReport {
+    pub fn error(&mut self, msg: impl Into<String>) {
+        self.errors.push(msg.into());
+    }
+
+    pub fn warning(&mut self, msg: impl Into<String>) {
+        self.warnings.push(msg.into());
+    }
+
+    /// Record the error of `result`, keeping only the message of
+    /// configuration errors.
+    pub fn check<T>(&mut self, result: Result<T>) {
+        let msg = match result {
+            Ok(_) => return,
+            Err(GeyserPluginError::Custom(err)) => {
+                match err.downcast::<GeyserPluginPostgresError>() {
+                    Ok(err) => match *err {
+                        GeyserPluginPostgresError::ConfigurationError { msg } => msg,
+                        err => err.to_string(),
+                    },
+                    Err(err) => err.to_string(),
+                }
+            }
+            Err(err) => err.to_string(),
+        };
+        self.errors.push(msg);
+    }
+
+    /// Log the warnings, and fail with all the errors if there are any.
+    pub fn into_result(self) -> Result<()> {
+        for warning in &self.warnings {
+            warn!("Configuration: {}", warning);
+        }
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(GeyserPluginError::Custom(Box::new(
+            GeyserPluginPostgresError::ConfigurationError {
+                msg: self.errors.join("; "),
+            },
+        )))
+    }
+}
+
+/// A store the decoded inscription events are written to
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// The PostgreSQL database configured by `host`, `user`, `port` or
+    /// `connection_str`.
+    Postgres,
+
+    /// Rotating files with one JSON object per event.
+    JsonLines(JsonLinesSinkConfig),
+
+    /// A SQLite database file with the same tables as the PostgreSQL sink.
+    Sqlite(SqliteSinkConfig),
+
+    /// Parquet files partitioned by slot range.
+    Parquet(ParquetSinkConfig),
+
+    /// HTTP endpoints notified of the events matching their filters.
+    Webhook(WebhookSinkConfig),
+
+    /// A WebSocket server streaming the events to its subscribers.
+    Websocket(WebsocketSinkConfig),
+
+    /// A gRPC server streaming the events and answering lookups.
+    Grpc(GrpcSinkConfig),
+}
+
+impl SinkConfig {
+    /// The `type` of the sink in the configuration.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkConfig::Postgres => "postgres",
+            SinkConfig::JsonLines(_) => "json_lines",
+            SinkConfig::Sqlite(_) => "sqlite",
+            SinkConfig::Parquet(_) => "parquet",
+            SinkConfig::Webhook(_) => "webhook",
+            SinkConfig::Websocket(_) => "websocket",
+            SinkConfig::Grpc(_) => "grpc",
+        }
+    }
+
+    /// Whether the sink keeps the events, rather than passing them on to
+    /// clients or endpoints.
+    pub fn is_storage(&self) -> bool {
+        matches!(
+            self,
+            SinkConfig::Postgres
+                | SinkConfig::JsonLines(_)
+                | SinkConfig::Sqlite(_)
+                | SinkConfig::Parquet(_)
+        )
+    }
+}
+
+/// The database writer implementation
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriterKind {
+    /// A pool of threads, each holding a synchronous connection.
+    #[default]
+    Threaded,
+
+    /// A tokio runtime pipelining queries over a few connections.
+    Pipelined,
+}
+
+/// How pubkeys and signatures are stored in the PostgreSQL tables
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStorage {
+    /// Base58 encoded TEXT columns.
+    #[default]
+    Base58,
+
+    /// BYTEA columns of 32 byte pubkeys and 64 byte signatures.
+    Binary,
+}
+
+impl KeyStorage {
+    /// The type of the key columns.
+    pub fn column_type(&self) -> &'static str {
+        match self {
+            KeyStorage::Base58 => "TEXT",
+            KeyStorage::Binary => "BYTEA",
+        }
+    }
+}
+
+/// The TLS modes of the connections to PostgreSQL
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    /// Plain connections.
+    #[default]
+    Disable,
+
+    /// Encrypted connections, without verifying the server certificate.
+    Require,
+
+    /// Encrypted connections to a server whose certificate is signed by a
+    /// trusted CA.
+    VerifyCa,
+
+    /// As `verify-ca`, and the certificate must also be issued for the host
+    /// connected to.
+    VerifyFull,
 }
 
 #[derive(Error, Debug)]
@@ -85,6 +579,9 @@ pub enum GeyserPluginPostgresError {
 
     #[error("Replica account V0.0.1 not supported anymore")]
     ReplicaAccountV001NotSupported,
+
+    #[error("Error writing to a sink. Error message: ({msg})")]
+    SinkError { msg: String },
 }
 
 impl GeyserPlugin for GeyserPluginPostgres {
@@ -99,20 +596,20 @@ impl GeyserPlugin for GeyserPluginPostgres {
             self.name(),
             config_file
         );
-        let mut file = File::open(config_file)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-
-        self.config = serde_json::from_str(&contents).map_err(|err| {
-            GeyserPluginError::ConfigFileReadError {
-                msg: format!(
-                    "The config file is not in the JSON format expected: {:?}",
-                    err
-                ),
+        self.config = GeyserPluginPostgresConfig::load(config_file)?;
+        self.config.validate().into_result()?;
+        self.slot_window = SlotWindow::new(&self.config);
+
+        let mut client = PostgresClientBuilder::build_pararallel_postgres_client(&self.config)?;
+        // Neither the workers nor a listener already started may outlive a
+        // failed load.
+        if let Err(err) = self.start_servers(&client) {
+            self.stop_servers();
+            if let Err(err) = client.join() {
+                error!("Failed to stop the workers: {:?}", err);
             }
-        })?;
-
-        let client = PostgresClientBuilder::build_pararallel_postgres_client(&self.config)?;
+            return Err(err);
+        }
         self.client = Some(client);
 
         Ok(())
@@ -121,6 +618,8 @@ impl GeyserPlugin for GeyserPluginPostgres {
     fn on_unload(&mut self) {
         info!("Unloading plugin: {:?}", self.name());
 
+        self.stop_servers();
+
         match &mut self.client {
             None => {}
             Some(client) => {
@@ -129,6 +628,9 @@ impl GeyserPlugin for GeyserPluginPostgres {
         }
     }
 
+    /// Account notifications are disabled, see
+    /// `account_data_notifications_enabled`, so there is nothing to index
+    /// nor to hold to the slot window here.
     fn update_account(
         &self,
         _account: ReplicaAccountInfoVersions,
@@ -138,16 +640,33 @@ impl GeyserPlugin for GeyserPluginPostgres {
         Ok(())
     }
 
-    fn update_slot_status(
-        &self,
-        slot: u64,
-        _parent: Option<u64>,
-        _status: SlotStatus,
-    ) -> Result<()> {
+    fn update_slot_status(&self, slot: u64, parent: Option<u64>, status: SlotStatus) -> Result<()> {
+        if let Some(client) = &self.client {
+            self.slot_window.check_end(slot, status, client.metrics());
+            if self.slot_window.contains(slot) {
+                client.update_slot_status(slot, parent, status)?;
+                if status == SlotStatus::Processed {
+                    client
+                        .metrics()
+                        .latest_processed_slot
+                        .fetch_max(slot, Ordering::Relaxed);
+                }
+            }
+            // Slot updates only flow once the validator has loaded its
+            // snapshot, `notify_end_of_startup` is not delivered to plugins
+            // without account notifications.
+            if !client.is_startup_done().load(Ordering::Relaxed) {
+                client.notify_end_of_startup();
+            }
+            client.report_metrics();
+        }
         Ok(())
     }
 
     fn notify_end_of_startup(&self) -> Result<()> {
+        if let Some(client) = &self.client {
+            client.notify_end_of_startup();
+        }
         Ok(())
     }
 
@@ -166,7 +685,9 @@ impl GeyserPlugin for GeyserPluginPostgres {
             }
             Some(client) => match transaction_info {
                 ReplicaTransactionInfoVersions::V0_0_2(transaction_info) => {
-                    if transaction_info.transaction_status_meta.status.is_err() {
+                    if !self.slot_window.contains(slot)
+                        || transaction_info.transaction_status_meta.status.is_err()
+                    {
                         return Ok(());
                     }
 
@@ -175,7 +696,7 @@ impl GeyserPlugin for GeyserPluginPostgres {
                         .message()
                         .account_keys()
                         .iter()
-                        .any(|key| key == &mpl_inscription_program::ID)
+                        .any(|key| key == client.program_id())
                     {
                         return Ok(());
                     }
@@ -216,6 +737,68 @@ impl GeyserPluginPostgres {
     pub fn new() -> Self {
         Self::default()
     }
+
+    fn parse_listen_addr(field: &str, listen_addr: &str) -> Result<SocketAddr> {
+        listen_addr.parse::<SocketAddr>().map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+                msg: format!(
+                    "\"{}\": {:?} is not a valid address: ({})",
+                    field, listen_addr, err
+                ),
+            }))
+        })
+    }
+
+    /// Start the Prometheus and health listeners which are configured.
+    fn start_servers(&mut self, client: &ParallelPostgresClient) -> Result<()> {
+        if let Some(listen_addr) = &self.config.prometheus_listen_addr {
+            let addr = Self::parse_listen_addr("prometheus_listen_addr", listen_addr)?;
+            let server = prometheus::start_server(addr, client.metrics().clone())
+                .map_err(|err| Self::listener_error("Prometheus", addr, err))?;
+            self.metrics_server = Some(server);
+        }
+
+        if let Some(listen_addr) = &self.config.health_listen_addr {
+            let addr = Self::parse_listen_addr("health_listen_addr", listen_addr)?;
+            let thresholds = HealthThresholds {
+                queue_depth: self
+                    .config
+                    .health_queue_threshold
+                    .unwrap_or(DEFAULT_HEALTH_QUEUE_THRESHOLD),
+                db_error_window_secs: self
+                    .config
+                    .health_db_error_window_secs
+                    .unwrap_or(DEFAULT_HEALTH_DB_ERROR_WINDOW_SECS),
+            };
+            let server = health::start_server(
+                addr,
+                client.metrics().clone(),
+                client.is_startup_done().clone(),
+                thresholds,
+            )
+            .map_err(|err| Self::listener_error("health check", addr, err))?;
+            self.health_server = Some(server);
+        }
+        Ok(())
+    }
+
+    fn stop_servers(&mut self) {
+        for mut server in [self.metrics_server.take(), self.health_server.take()]
+            .into_iter()
+            .flatten()
+        {
+            server.join().unwrap();
+        }
+    }
+
+    fn listener_error(name: &str, addr: SocketAddr, err: std::io::Error) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+            msg: format!(
+                "Failed to start the {} listener on {}: ({})",
+                name, addr, err
+            ),
+        }))
+    }
 }
 
 #[no_mangle]
@@ -228,3 +811,177 @@ pub unsafe extern "C" fn _create_plugin() -> *mut dyn GeyserPlugin {
     let plugin: Box<dyn GeyserPlugin> = Box::new(plugin);
     Box::into_raw(plugin)
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn config(value: serde_json::Value) -> GeyserPluginPostgresConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_ssl_mode() {
+        assert_eq!(config(json!({})).ssl_mode(), SslMode::Disable);
+        assert_eq!(
+            config(json!({ "use_ssl": false })).ssl_mode(),
+            SslMode::Disable
+        );
+        // `use_ssl` verifies the certificate, but not the host name.
+        assert_eq!(
+            config(json!({ "use_ssl": true })).ssl_mode(),
+            SslMode::VerifyCa
+        );
+        for (name, ssl_mode) in [
+            ("disable", SslMode::Disable),
+            ("require", SslMode::Require),
+            ("verify-ca", SslMode::VerifyCa),
+            ("verify-full", SslMode::VerifyFull),
+        ] {
+            assert_eq!(config(json!({ "ssl_mode": name })).ssl_mode(), ssl_mode);
+            // `ssl_mode` takes precedence over `use_ssl`.
+            assert_eq!(
+                config(json!({ "ssl_mode": name, "use_ssl": true })).ssl_mode(),
+                ssl_mode
+            );
+        }
+        assert!(serde_json::from_value::<GeyserPluginPostgresConfig>(
+            json!({ "ssl_mode": "prefer" })
+        )
+        .is_err());
+    }
+
+    fn contains(messages: &[String], part: &str) -> bool {
+        messages.iter().any(|message| message.contains(part))
+    }
+
+    #[test]
+    fn test_validate() {
+        let report = config(json!({ "host": "localhost", "user": "postgres" })).validate();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let report = config(json!({
+            "batch_size": 0,
+            "threads": 0,
+            "start_slot": 10,
+            "end_slot": 5,
+            "prometheus_listen_addr": "localhost",
+            "program_id": "not a key",
+            "sinks": [{"type": "json_lines", "directory": ""}],
+        }))
+        .validate();
+        assert_eq!(report.errors.len(), 6, "{:?}", report.errors);
+        for part in [
+            "\"batch_size\" must be greater than 0",
+            "\"threads\" must be greater than 0",
+            "\"start_slot\": 10 must not be greater than \"end_slot\": 5",
+            "\"prometheus_listen_addr\"",
+            "not a key",
+            "\"directory\" of the json_lines sink",
+        ] {
+            assert!(
+                contains(&report.errors, part),
+                "{}: {:?}",
+                part,
+                report.errors
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_sinks() {
+        let report = config(json!({ "sinks": [] })).validate();
+        assert_eq!(report.errors, ["\"sinks\" must not be empty"]);
+
+        // The PostgreSQL options are only checked with the `postgres` sink.
+        let sinks = json!([
+            {"type": "json_lines", "directory": "/tmp/events"},
+            {"type": "json_lines", "directory": "/tmp/events"},
+        ]);
+        let report = config(json!({ "sinks": sinks })).validate();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(contains(&report.warnings, "configured more than once"));
+
+        let report = config(json!({ "sinks": [{"type": "postgres"}] })).validate();
+        assert!(contains(&report.errors, "\"connection_str\", or \"host\""));
+    }
+
+    #[test]
+    fn test_validate_postgres_warnings() {
+        let report = config(json!({
+            "connection_str": "host=localhost user=postgres",
+            "host": "localhost",
+            "writer": "threaded",
+            "connections": 4,
+            "use_ssl": false,
+            "ssl_mode": "disable",
+            "server_ca": "/etc/ssl/ca.pem",
+        }))
+        .validate();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        for part in [
+            "are ignored when \"connection_str\" is set",
+            "\"connections\" is ignored by the threaded writer",
+            "\"use_ssl\" is ignored",
+            "\"server_ca\" is ignored without TLS",
+        ] {
+            assert!(
+                contains(&report.warnings, part),
+                "{}: {:?}",
+                part,
+                report.warnings
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_tls_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let missing = directory.path().join("missing.pem");
+        let report = config(json!({
+            "host": "localhost",
+            "user": "postgres",
+            "ssl_mode": "verify-full",
+            "server_ca": missing,
+        }))
+        .validate();
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(contains(&report.errors, "\"server_ca\""));
+        assert!(contains(&report.errors, "is not a file"));
+    }
+
+    #[test]
+    fn test_slot_window() {
+        let window = SlotWindow::new(&config(json!({})));
+        assert!(window.contains(0));
+        assert!(window.contains(u64::MAX));
+
+        // Both bounds are included.
+        let window = SlotWindow::new(&config(json!({ "start_slot": 10, "end_slot": 20 })));
+        assert!(!window.contains(9));
+        assert!(window.contains(10));
+        assert!(window.contains(20));
+        assert!(!window.contains(21));
+    }
+
+    #[test]
+    fn test_slot_window_end() {
+        let metrics = PluginMetrics::new(1);
+        let window = SlotWindow::new(&config(json!({ "end_slot": 20 })));
+
+        // Only a rooted slot at or beyond the end completes the window.
+        window.check_end(19, SlotStatus::Rooted, &metrics);
+        window.check_end(25, SlotStatus::Confirmed, &metrics);
+        assert!(!metrics.end_slot_passed.load(Ordering::Relaxed));
+        window.check_end(20, SlotStatus::Rooted, &metrics);
+        assert!(metrics.end_slot_passed.load(Ordering::Relaxed));
+        assert!(window.end_reported.load(Ordering::Relaxed));
+
+        // Without an end, the window is never complete.
+        let metrics = PluginMetrics::new(1);
+        let window = SlotWindow::new(&config(json!({})));
+        window.check_end(u64::MAX, SlotStatus::Rooted, &metrics);
+        assert!(!metrics.end_slot_passed.load(Ordering::Relaxed));
+    }
+}
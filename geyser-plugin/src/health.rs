@@ -0,0 +1,266 @@
+/// Reports whether the indexer is keeping up, for orchestration probes.
+///
+/// * `ready`: every worker is connected, the startup is done, the queue is
+///   below the threshold and no database error happened recently.
+/// * `degraded`: at least one worker is connected but one of the above does
+///   not hold.
+/// * `failed`: no worker is connected.
+use {
+    crate::{
+        http_server::{HttpResponse, HttpServer},
+        metrics::{PluginMetrics, WorkerState},
+    },
+    serde_derive::Serialize,
+    std::{
+        io,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+const CONTENT_TYPE: &str = "application/json";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ready,
+    Degraded,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HealthThresholds {
+    /// Queue depth above which the indexer is degraded.
+    pub queue_depth: u64,
+    /// How long, in seconds, a database error keeps the indexer degraded.
+    pub db_error_window_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerHealth {
+    pub worker: usize,
+    pub state: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Why the indexer is not ready, empty when it is.
+    pub reasons: Vec<String>,
+    pub startup_done: bool,
+    pub queue_depth: u64,
+    pub last_indexed_slot: u64,
+    pub latest_processed_slot: u64,
+    pub slot_lag: u64,
+    pub committed_slot: u64,
+    /// The gaps waiting for a backfill, and the slots they hold.
+    pub open_backfill_gaps: u64,
+    pub open_backfill_gap_slots: u64,
+    /// Whether the `end_slot` of the configuration has been passed.
+    pub end_slot_passed: bool,
+    pub last_db_error_secs_ago: Option<u64>,
+    pub workers: Vec<WorkerHealth>,
+}
+
+pub fn check(
+    metrics: &PluginMetrics,
+    startup_done: bool,
+    thresholds: &HealthThresholds,
+) -> HealthReport {
+    let worker_states = metrics.worker_states();
+    let connected = worker_states
+        .iter()
+        .filter(|state| **state == WorkerState::Connected)
+        .count();
+    let queue_depth = metrics.queue_depth.load(Ordering::Relaxed);
+    let last_db_error = metrics.last_db_error_unix_secs.load(Ordering::Relaxed);
+    let last_db_error_secs_ago = (last_db_error > 0).then(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .saturating_sub(last_db_error)
+    });
+
+    let mut reasons = Vec::new();
+    if connected < worker_states.len() {
+        reasons.push(format!(
+            "{} of {} workers are connected",
+            connected,
+            worker_states.len()
+        ));
+    }
+    if !startup_done {
+        reasons.push("the validator startup is not done".to_string());
+    }
+    if queue_depth > thresholds.queue_depth {
+        reasons.push(format!(
+            "the queue depth {} is above the threshold {}",
+            queue_depth, thresholds.queue_depth
+        ));
+    }
+    if let Some(secs_ago) = last_db_error_secs_ago {
+        if secs_ago < thresholds.db_error_window_secs {
+            reasons.push(format!("a database error happened {}s ago", secs_ago));
+        }
+    }
+
+    let status = if connected == 0 {
+        HealthStatus::Failed
+    } else if reasons.is_empty() {
+        HealthStatus::Ready
+    } else {
+        HealthStatus::Degraded
+    };
+
+    HealthReport {
+        status,
+        reasons,
+        startup_done,
+        queue_depth,
+        last_indexed_slot: metrics.last_indexed_slot.load(Ordering::Relaxed),
+        latest_processed_slot: metrics.latest_processed_slot.load(Ordering::Relaxed),
+        slot_lag: metrics.slot_lag(),
+        committed_slot: metrics.committed_slot.load(Ordering::Relaxed),
+        open_backfill_gaps: metrics.open_backfill_gaps.load(Ordering::Relaxed),
+        open_backfill_gap_slots: metrics.open_backfill_gap_slots.load(Ordering::Relaxed),
+        end_slot_passed: metrics.end_slot_passed.load(Ordering::Relaxed),
+        last_db_error_secs_ago,
+        workers: worker_states
+            .iter()
+            .enumerate()
+            .map(|(worker, state)| WorkerHealth {
+                worker,
+                state: state.as_str(),
+            })
+            .collect(),
+    }
+}
+
+/// Start the health endpoints on `addr`. `/health` answers 503 only when the
+/// indexer has failed, `/ready` answers 503 unless it is ready. Both return
+/// the `HealthReport` as JSON.
+pub fn start_server(
+    addr: SocketAddr,
+    metrics: Arc<PluginMetrics>,
+    startup_done: Arc<AtomicBool>,
+    thresholds: HealthThresholds,
+) -> io::Result<HttpServer> {
+    HttpServer::start("health-check", addr, move |path| {
+        let healthy: fn(HealthStatus) -> bool = match path {
+            "/health" => |status| status != HealthStatus::Failed,
+            "/ready" => |status| status == HealthStatus::Ready,
+            _ => return HttpResponse::not_found(),
+        };
+        let report = check(&metrics, startup_done.load(Ordering::Relaxed), &thresholds);
+        let mut response = HttpResponse::ok(
+            CONTENT_TYPE,
+            serde_json::to_string(&report).unwrap_or_default(),
+        );
+        if !healthy(report.status) {
+            response.status = 503;
+        }
+        response
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: HealthThresholds = HealthThresholds {
+        queue_depth: 100,
+        db_error_window_secs: 60,
+    };
+
+    fn connected(worker_count: usize) -> PluginMetrics {
+        let metrics = PluginMetrics::new(worker_count);
+        for worker in 0..worker_count {
+            metrics.set_worker_state(worker, WorkerState::Connected);
+        }
+        metrics
+    }
+
+    #[test]
+    fn test_ready() {
+        let metrics = connected(2);
+        metrics.last_indexed_slot.store(90, Ordering::Relaxed);
+        metrics.latest_processed_slot.store(100, Ordering::Relaxed);
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Ready);
+        assert!(report.reasons.is_empty());
+        assert_eq!(report.slot_lag, 10);
+        assert_eq!(report.last_db_error_secs_ago, None);
+        assert_eq!(
+            report
+                .workers
+                .iter()
+                .map(|worker| worker.state)
+                .collect::<Vec<_>>(),
+            ["connected", "connected"]
+        );
+    }
+
+    #[test]
+    fn test_degraded() {
+        let metrics = connected(2);
+        metrics.set_worker_state(1, WorkerState::Disconnected);
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.reasons, ["1 of 2 workers are connected"]);
+
+        let report = check(&connected(1), false, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.reasons, ["the validator startup is not done"]);
+
+        let metrics = connected(1);
+        metrics.queue_depth.store(101, Ordering::Relaxed);
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(
+            report.reasons,
+            ["the queue depth 101 is above the threshold 100"]
+        );
+        // At the threshold, the queue is fine.
+        metrics.queue_depth.store(100, Ordering::Relaxed);
+        assert_eq!(
+            check(&metrics, true, &THRESHOLDS).status,
+            HealthStatus::Ready
+        );
+    }
+
+    #[test]
+    fn test_db_error_window() {
+        let metrics = connected(1);
+        metrics.record_db_error();
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert!(report.last_db_error_secs_ago.unwrap() < 60);
+
+        // An error older than the window no longer counts.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        metrics
+            .last_db_error_unix_secs
+            .store(now - 120, Ordering::Relaxed);
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Ready);
+        assert!(report.last_db_error_secs_ago.unwrap() >= 120);
+    }
+
+    #[test]
+    fn test_failed() {
+        let metrics = PluginMetrics::new(2);
+        metrics.set_worker_state(0, WorkerState::Failed);
+        metrics.set_worker_state(1, WorkerState::Stopped);
+        let report = check(&metrics, true, &THRESHOLDS);
+        assert_eq!(report.status, HealthStatus::Failed);
+        assert_eq!(report.reasons, ["0 of 2 workers are connected"]);
+    }
+}
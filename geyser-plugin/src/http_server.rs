@@ -0,0 +1,238 @@
+/// A minimal HTTP/1.1 server answering `GET` requests, each connection on a
+/// thread of its own so that a slow client does not hold up the others. It
+/// is meant for scrapes and probes issued every few seconds, not for general
+/// traffic.
+use {
+    log::*,
+    std::{
+        io::{self, BufRead, BufReader, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread::{self, sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connections beyond this many being served at once are closed right away.
+const MAX_CONCURRENT_CONNECTIONS: usize = 16;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            content_type: "text/plain; charset=utf-8",
+            body: "Not Found\n".to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+pub struct HttpServer {
+    local_addr: SocketAddr,
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Bind `addr` and serve every request with `handler`, which receives
+    /// the request path.
+    pub fn start<F>(name: &str, addr: SocketAddr, handler: F) -> io::Result<Self>
+    where
+        F: Fn(&str) -> HttpResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let exit = Arc::new(AtomicBool::new(false));
+        let exit_clone = exit.clone();
+        let handler = Arc::new(handler);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let connection_thread_name = format!("{}-conn", name);
+        let thread = Builder::new().name(name.to_string()).spawn(move || {
+            while !exit_clone.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, peer_addr)) => Self::spawn_connection(
+                        &connection_thread_name,
+                        stream,
+                        peer_addr,
+                        &handler,
+                        &connections,
+                    ),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                    Err(err) => {
+                        warn!("Error in accepting an HTTP connection: ({})", err);
+                        sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                }
+            }
+        })?;
+
+        info!("Started {} listening on {}", name, local_addr);
+        Ok(Self {
+            local_addr,
+            exit,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Serve `stream` on a thread of its own, unless
+    /// `MAX_CONCURRENT_CONNECTIONS` connections are being served already.
+    fn spawn_connection<F>(
+        name: &str,
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+        handler: &Arc<F>,
+        connections: &Arc<AtomicUsize>,
+    ) where
+        F: Fn(&str) -> HttpResponse + Send + Sync + 'static,
+    {
+        if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONCURRENT_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            debug!(
+                "Too many HTTP connections, closing the one of {}",
+                peer_addr
+            );
+            return;
+        }
+        let handler = handler.clone();
+        let connections_clone = connections.clone();
+        let result = Builder::new().name(name.to_string()).spawn(move || {
+            if let Err(err) = Self::serve(stream, handler.as_ref()) {
+                debug!("Error in serving an HTTP request: ({})", err);
+            }
+            connections_clone.fetch_sub(1, Ordering::Relaxed);
+        });
+        if let Err(err) = result {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            warn!("Failed to start an HTTP connection thread: ({})", err);
+        }
+    }
+
+    fn serve<F>(stream: TcpStream, handler: &F) -> io::Result<()>
+    where
+        F: Fn(&str) -> HttpResponse,
+    {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Drain the headers, the body of a GET request is ignored.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let path = target.split('?').next().unwrap_or_default();
+
+        let response = if method == "GET" {
+            handler(path)
+        } else {
+            HttpResponse {
+                status: 405,
+                content_type: "text/plain; charset=utf-8",
+                body: "Method Not Allowed\n".to_string(),
+            }
+        };
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.reason(),
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(response.body.as_bytes())?;
+        stream.flush()
+    }
+
+    /// Stop accepting connections and wait for the accepting thread to exit.
+    /// Connections being served finish within `REQUEST_TIMEOUT`.
+    pub fn join(&mut self) -> thread::Result<()> {
+        self.exit.store(true, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => thread.join(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{io::Read, net::Ipv4Addr},
+    };
+
+    #[test]
+    fn test_idle_connection_does_not_block_requests() {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let mut server = HttpServer::start("http-test", addr, |path| match path {
+            "/ready" => HttpResponse::ok("text/plain", "ready\n".to_string()),
+            _ => HttpResponse::not_found(),
+        })
+        .unwrap();
+
+        // Opened without sending anything, it holds its connection thread
+        // until the request timeout.
+        let _idle = TcpStream::connect(server.local_addr()).unwrap();
+        sleep(ACCEPT_POLL_INTERVAL * 2);
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT / 2)).unwrap();
+        stream.write_all(b"GET /ready HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("ready\n"));
+
+        server.join().unwrap();
+    }
+}
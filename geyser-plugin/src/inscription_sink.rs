@@ -0,0 +1,455 @@
+pub mod grpc;
+pub mod json_lines;
+pub mod parquet_archive;
+pub mod sqlite;
+pub mod webhook;
+pub mod websocket;
+
+/// The backend-agnostic interface the workers write decoded inscription
+/// events through. Every store the plugin can index into implements
+/// `InscriptionSink`; the sinks used are listed in the `sinks` field of the
+/// configuration.
+use {
+    crate::{
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, SinkConfig},
+        metrics::PluginMetrics,
+        postgres_client::{
+            postgres_client_transaction::{InscriptionEvent, InscriptionInstructionType},
+            SimplePostgresClient,
+        },
+    },
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    domichain_sdk::pubkey::Pubkey,
+    grpc::GrpcSink,
+    json_lines::JsonLinesSink,
+    log::*,
+    parquet_archive::ParquetSink,
+    serde_derive::{Deserialize, Serialize},
+    sqlite::SqliteSink,
+    std::{
+        collections::{BTreeMap, HashSet},
+        str::FromStr,
+        sync::{Arc, Mutex},
+    },
+    webhook::WebhookSink,
+    websocket::WebsocketSink,
+};
+
+/// The JSON representation of an `InscriptionEvent`, with the keys and the
+/// signature in base58.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonInscriptionEvent {
+    pub slot: u64,
+    pub signature: String,
+    pub instruction_index: u32,
+    pub instruction_type: InscriptionInstructionType,
+    pub program_id: String,
+    pub inscription_account: Option<String>,
+    pub metadata_account: Option<String>,
+    pub mint_account: Option<String>,
+    pub authority: Option<String>,
+    pub accounts: Vec<String>,
+}
+
+impl From<&InscriptionEvent> for JsonInscriptionEvent {
+    fn from(event: &InscriptionEvent) -> Self {
+        Self {
+            slot: event.slot,
+            signature: event.signature.to_string(),
+            instruction_index: event.instruction_index,
+            instruction_type: event.instruction_type,
+            program_id: event.program_id.to_string(),
+            inscription_account: event.inscription_account.map(|key| key.to_string()),
+            metadata_account: event.metadata_account.map(|key| key.to_string()),
+            mint_account: event.mint_account.map(|key| key.to_string()),
+            authority: event.authority.map(|key| key.to_string()),
+            accounts: event.accounts.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+}
+
+/// Selects events by their keys and instruction type. Every field left
+/// unset matches all events.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EventFilterConfig {
+    pub program_ids: Option<Vec<String>>,
+    pub authorities: Option<Vec<String>>,
+    pub mints: Option<Vec<String>>,
+    /// Inscription accounts.
+    pub accounts: Option<Vec<String>>,
+    pub event_types: Option<Vec<InscriptionInstructionType>>,
+}
+
+/// A parsed `EventFilterConfig`.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    program_ids: Option<HashSet<Pubkey>>,
+    authorities: Option<HashSet<Pubkey>>,
+    mints: Option<HashSet<Pubkey>>,
+    accounts: Option<HashSet<Pubkey>>,
+    event_types: Option<HashSet<InscriptionInstructionType>>,
+}
+
+impl EventFilter {
+    pub fn new(config: &EventFilterConfig) -> Result<Self, String> {
+        let parse_keys = |field: &str, keys: &Option<Vec<String>>| {
+            keys.as_ref()
+                .map(|keys| {
+                    keys.iter()
+                        .map(|key| {
+                            Pubkey::from_str(key).map_err(|err| {
+                                format!(
+                                    "\"{}\": {:?} is not a valid public key: ({})",
+                                    field, key, err
+                                )
+                            })
+                        })
+                        .collect::<Result<HashSet<_>, _>>()
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            program_ids: parse_keys("program_ids", &config.program_ids)?,
+            authorities: parse_keys("authorities", &config.authorities)?,
+            mints: parse_keys("mints", &config.mints)?,
+            accounts: parse_keys("accounts", &config.accounts)?,
+            event_types: config
+                .event_types
+                .as_ref()
+                .map(|types| types.iter().copied().collect()),
+        })
+    }
+
+    pub fn matches(&self, event: &InscriptionEvent) -> bool {
+        let matches_key = |keys: &Option<HashSet<Pubkey>>, key: Option<Pubkey>| match keys {
+            None => true,
+            Some(keys) => key.map(|key| keys.contains(&key)).unwrap_or_default(),
+        };
+
+        matches_key(&self.program_ids, Some(event.program_id))
+            && matches_key(&self.authorities, event.authority)
+            && matches_key(&self.mints, event.mint_account)
+            && matches_key(&self.accounts, event.inscription_account)
+            && self
+                .event_types
+                .as_ref()
+                .map(|types| types.contains(&event.instruction_type))
+                .unwrap_or(true)
+    }
+}
+
+/// The number of slots whose events `RootedEvents` holds back. Older slots
+/// are dropped when a slot beyond it arrives.
+const MAX_UNROOTED_SLOTS: usize = 1024;
+
+/// The stage of the slot an event is streamed at.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    /// As soon as the event is decoded.
+    #[default]
+    Processed,
+    /// Once the slot of the event is rooted.
+    Rooted,
+}
+
+/// Holds events back until their slot is rooted, for the sinks streaming
+/// rooted events. Events of slots still held when a later slot is rooted
+/// were on an abandoned fork and are dropped.
+#[derive(Default)]
+pub struct RootedEvents {
+    pending: BTreeMap<u64, Vec<InscriptionEvent>>,
+    last_rooted_slot: Option<u64>,
+}
+
+impl RootedEvents {
+    /// Return the events whose slot is already rooted and hold the others.
+    pub fn push(&mut self, events: &[InscriptionEvent]) -> Vec<InscriptionEvent> {
+        let mut rooted = Vec::new();
+        for event in events {
+            if Some(event.slot) <= self.last_rooted_slot {
+                rooted.push(event.clone());
+            } else {
+                self.pending
+                    .entry(event.slot)
+                    .or_default()
+                    .push(event.clone());
+            }
+        }
+
+        while self.pending.len() > MAX_UNROOTED_SLOTS {
+            if let Some((slot, events)) = self.pending.pop_first() {
+                warn!(
+                    "Dropping {} events of slot {} which was not rooted in time",
+                    events.len(),
+                    slot
+                );
+            }
+        }
+        rooted
+    }
+
+    /// Record that `slot` was rooted and return the events held for it.
+    pub fn root(&mut self, slot: u64) -> Vec<InscriptionEvent> {
+        self.last_rooted_slot = self.last_rooted_slot.max(Some(slot));
+        let later = self.pending.split_off(&(slot + 1));
+        let mut earlier = std::mem::replace(&mut self.pending, later);
+        earlier.remove(&slot).unwrap_or_default()
+    }
+}
+
+pub trait InscriptionSink: Send {
+    /// The name used for the sink in logs.
+    fn name(&self) -> &'static str;
+
+    /// Re-establish the connection to the store if it was lost. Returns true
+    /// when a new connection was made.
+    fn ensure_connected(&mut self) -> Result<bool, GeyserPluginError> {
+        Ok(false)
+    }
+
+    /// Record that the events of every rooted slot up to `slot` are written
+    /// once those of the current batch are. Called before the events of the
+    /// batch; sinks keeping a watermark persist it along with them.
+    fn set_committed_slot(&mut self, _slot: u64) {}
+
+    /// Whether the sink persists the watermark of `set_committed_slot`.
+    /// Only the events these sinks fail to write hold the watermark back.
+    fn keeps_watermark(&self) -> bool {
+        false
+    }
+
+    /// Whether the sink keeps events in memory beyond `flush`, to write
+    /// them in larger chunks later.
+    fn buffers_events(&self) -> bool {
+        false
+    }
+
+    /// The lowest slot of the events a buffering sink holds in memory. The
+    /// watermark stays below it, so that these events are replayed after a
+    /// crash.
+    fn buffered_slot(&self) -> Option<u64> {
+        None
+    }
+
+    /// Persist a batch of events, in the order they were decoded.
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError>;
+
+    /// Persist a slot status change. Called after the events of the same
+    /// batch have been written.
+    fn update_slot_status(
+        &mut self,
+        _slot: u64,
+        _parent: Option<u64>,
+        _status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        Ok(())
+    }
+
+    /// Make everything written since the last call durable. Called once per
+    /// batch, after its events and slot updates.
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        Ok(())
+    }
+}
+
+/// A sink used by every worker at once, for stores which cannot be written
+/// from several connections, such as files.
+pub type SharedSink = Arc<Mutex<dyn InscriptionSink>>;
+
+impl InscriptionSink for SharedSink {
+    fn name(&self) -> &'static str {
+        self.lock().unwrap().name()
+    }
+
+    fn ensure_connected(&mut self) -> Result<bool, GeyserPluginError> {
+        self.lock().unwrap().ensure_connected()
+    }
+
+    fn set_committed_slot(&mut self, slot: u64) {
+        self.lock().unwrap().set_committed_slot(slot)
+    }
+
+    fn keeps_watermark(&self) -> bool {
+        self.lock().unwrap().keeps_watermark()
+    }
+
+    fn buffers_events(&self) -> bool {
+        self.lock().unwrap().buffers_events()
+    }
+
+    fn buffered_slot(&self) -> Option<u64> {
+        self.lock().unwrap().buffered_slot()
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        self.lock().unwrap().write_events(events)
+    }
+
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        self.lock()
+            .unwrap()
+            .update_slot_status(slot, parent, status)
+    }
+
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        self.lock().unwrap().flush()
+    }
+}
+
+/// Create the sinks shared by all workers, in the order they are configured.
+/// `Postgres` is skipped: every worker opens its own connections.
+pub fn build_shared_sinks(
+    config: &GeyserPluginPostgresConfig,
+    metrics: &Arc<PluginMetrics>,
+) -> Result<Vec<SharedSink>, GeyserPluginError> {
+    config
+        .sinks()
+        .iter()
+        .filter_map(|sink| match sink {
+            SinkConfig::Postgres => None,
+            SinkConfig::JsonLines(config) => Some(
+                JsonLinesSink::new(config).map(|sink| Arc::new(Mutex::new(sink)) as SharedSink),
+            ),
+            SinkConfig::Sqlite(config) => {
+                Some(SqliteSink::new(config).map(|sink| Arc::new(Mutex::new(sink)) as SharedSink))
+            }
+            SinkConfig::Webhook(config) => Some(
+                WebhookSink::new(config, metrics.clone())
+                    .map(|sink| Arc::new(Mutex::new(sink)) as SharedSink),
+            ),
+            SinkConfig::Websocket(config) => Some(
+                WebsocketSink::new(config).map(|sink| Arc::new(Mutex::new(sink)) as SharedSink),
+            ),
+            SinkConfig::Parquet(config) => {
+                Some(ParquetSink::new(config).map(|sink| Arc::new(Mutex::new(sink)) as SharedSink))
+            }
+            SinkConfig::Grpc(grpc_config) => Some(
+                GrpcSink::new(grpc_config, config)
+                    .map(|sink| Arc::new(Mutex::new(sink)) as SharedSink),
+            ),
+        })
+        .collect()
+}
+
+/// Create the sinks of one threaded worker: its own PostgreSQL connection
+/// when the `Postgres` sink is configured, followed by the shared sinks.
+pub fn build_worker_sinks(
+    config: &GeyserPluginPostgresConfig,
+    shared_sinks: &[SharedSink],
+) -> Result<Vec<Box<dyn InscriptionSink>>, GeyserPluginError> {
+    let mut sinks: Vec<Box<dyn InscriptionSink>> = Vec::new();
+    if config.sinks().contains(&SinkConfig::Postgres) {
+        sinks.push(Box::new(SimplePostgresClient::new(config)?));
+    }
+    sinks.extend(
+        shared_sinks
+            .iter()
+            .map(|sink| Box::new(sink.clone()) as Box<dyn InscriptionSink>),
+    );
+    Ok(sinks)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, domichain_sdk::signature::Signature};
+
+    fn event(slot: u64, instruction_type: InscriptionInstructionType) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: Some(Pubkey::new_unique()),
+            mint_account: None,
+            authority: Some(Pubkey::new_unique()),
+            accounts: vec![],
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let event = event(1, InscriptionInstructionType::WriteData);
+        assert!(EventFilter::new(&EventFilterConfig::default())
+            .unwrap()
+            .matches(&event));
+
+        let filter = EventFilter::new(&EventFilterConfig {
+            authorities: Some(vec![event.authority.unwrap().to_string()]),
+            accounts: Some(vec![
+                Pubkey::new_unique().to_string(),
+                event.inscription_account.unwrap().to_string(),
+            ]),
+            event_types: Some(vec![InscriptionInstructionType::WriteData]),
+            ..EventFilterConfig::default()
+        })
+        .unwrap();
+        assert!(filter.matches(&event));
+
+        // Every set field must match.
+        let filter = EventFilter::new(&EventFilterConfig {
+            authorities: Some(vec![event.authority.unwrap().to_string()]),
+            event_types: Some(vec![InscriptionInstructionType::Close]),
+            ..EventFilterConfig::default()
+        })
+        .unwrap();
+        assert!(!filter.matches(&event));
+
+        // An event without the key does not match a filter on it.
+        let filter = EventFilter::new(&EventFilterConfig {
+            mints: Some(vec![Pubkey::new_unique().to_string()]),
+            ..EventFilterConfig::default()
+        })
+        .unwrap();
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn test_event_filter_invalid_key() {
+        let err = EventFilter::new(&EventFilterConfig {
+            program_ids: Some(vec!["not a key".to_string()]),
+            ..EventFilterConfig::default()
+        })
+        .unwrap_err();
+        assert!(err.starts_with("\"program_ids\": \"not a key\""));
+    }
+
+    #[test]
+    fn test_rooted_events() {
+        let mut rooted_events = RootedEvents::default();
+        let events = [
+            event(10, InscriptionInstructionType::Initialize),
+            event(11, InscriptionInstructionType::WriteData),
+            event(12, InscriptionInstructionType::WriteData),
+        ];
+        // Nothing is rooted yet.
+        assert!(rooted_events.push(&events).is_empty());
+
+        assert_eq!(rooted_events.root(10), vec![events[0].clone()]);
+        // 11 was on an abandoned fork.
+        assert_eq!(rooted_events.root(12), vec![events[2].clone()]);
+        assert!(rooted_events.root(11).is_empty());
+
+        // Events of rooted slots are returned right away.
+        let late = event(12, InscriptionInstructionType::Close);
+        assert_eq!(rooted_events.push(&[late.clone()]), vec![late]);
+    }
+
+    #[test]
+    fn test_rooted_events_drops_oldest_slots() {
+        let mut rooted_events = RootedEvents::default();
+        for slot in 0..=MAX_UNROOTED_SLOTS as u64 {
+            rooted_events.push(&[event(slot, InscriptionInstructionType::WriteData)]);
+        }
+        assert!(rooted_events.root(0).is_empty());
+        assert_eq!(rooted_events.root(1).len(), 1);
+    }
+}
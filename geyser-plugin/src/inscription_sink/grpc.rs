@@ -0,0 +1,1082 @@
+#![allow(clippy::result_large_err)]
+
+/// The types and the service generated from `proto/inscriptions.proto`.
+pub mod proto {
+    tonic::include_proto!("inscriptions.v1");
+}
+
+/// A sink serving the events over gRPC. Subscriptions can start at a past
+/// slot: recent events are replayed from an in-memory journal and older ones
+/// from the PostgreSQL database, when the `postgres` sink is configured.
+/// Lookups are answered from the database, or from the journal without it.
+use {
+    crate::{
+        geyser_plugin_postgres::{
+            ConfigReport, GeyserPluginPostgresConfig, GeyserPluginPostgresError, SinkConfig,
+        },
+        inscription_sink::{
+            Commitment, EventFilter, EventFilterConfig, InscriptionSink, RootedEvents,
+        },
+        postgres_client::{
+            postgres_client_transaction::{
+                DbInscription, DbKey, InscriptionEvent, InscriptionInstructionType,
+            },
+            ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    domichain_sdk::{pubkey::Pubkey, signature::Signature},
+    log::*,
+    proto::{
+        inscriptions_server::{Inscriptions, InscriptionsServer},
+        list_events_request::Key,
+        GetInscriptionRequest, Inscription, ListEventsRequest, ListEventsResponse,
+        SubscribeRequest, SubscribeResponse,
+    },
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::{HashMap, VecDeque},
+        net::{SocketAddr, ToSocketAddrs},
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::{
+        net::TcpListener,
+        runtime::{Builder, Runtime},
+        sync::{broadcast, mpsc, oneshot},
+    },
+    tokio_postgres::{Client, Row},
+    tokio_stream::wrappers::{ReceiverStream, TcpListenerStream},
+    tonic::{transport::Server, Request, Response, Status},
+};
+
+const DEFAULT_JOURNAL_SIZE: usize = 100_000;
+const DEFAULT_MAX_QUEUED_EVENTS: usize = 10_000;
+const DEFAULT_LIST_LIMIT: u32 = 100;
+const MAX_LIST_LIMIT: u32 = 1000;
+/// The number of events read from the database per replay query.
+const REPLAY_PAGE_SIZE: i64 = 1000;
+/// Events replayed from the database within this many slots of the last one
+/// may also be in the journal or arrive live, where they are skipped.
+const REPLAY_OVERLAP_SLOTS: u64 = 150;
+/// The number of responses buffered between a subscription task and the
+/// connection.
+const SUBSCRIPTION_BUFFER_SIZE: usize = 1024;
+const SERVER_THREADS: usize = 2;
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const REPLAY_QUERY: &str = "SELECT slot, signature, instruction_index, instruction_type, \
+        inscription_account, metadata_account, mint_account, authority, accounts \
+    FROM {schema}.{prefix}inscription_events e \
+    WHERE (slot, signature, instruction_index) > ($1, $2, $3) AND slot < $4 \
+        AND (NOT $5 OR EXISTS \
+            (SELECT 1 FROM {schema}.{prefix}slots s WHERE s.slot = e.slot AND s.status = 'rooted')) \
+    ORDER BY slot, signature, instruction_index LIMIT $6";
+
+const EVENTS_BY_ACCOUNT_QUERY: &str = "SELECT slot, signature, instruction_index, \
+        instruction_type, inscription_account, metadata_account, mint_account, authority, \
+        accounts \
+    FROM {schema}.{prefix}inscription_events WHERE inscription_account = $1 \
+    ORDER BY slot DESC, signature DESC, instruction_index DESC LIMIT $2";
+
+const EVENTS_BY_SIGNATURE_QUERY: &str = "SELECT slot, signature, instruction_index, \
+        instruction_type, inscription_account, metadata_account, mint_account, authority, \
+        accounts \
+    FROM {schema}.{prefix}inscription_events WHERE signature = $1 \
+    ORDER BY instruction_index DESC LIMIT $2";
+
+const INSCRIPTION_QUERY: &str = "SELECT slot, signature, account, mint_account, \
+        metadata_account, authority \
+    FROM {schema}.{prefix}inscriptions WHERE account = $1";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcSinkConfig {
+    /// The address the server listens on, e.g. "0.0.0.0:10000".
+    pub listen_addr: String,
+
+    /// The number of recent events of each commitment kept in memory to
+    /// replay subscriptions from. The default is 100000.
+    pub journal_size: Option<usize>,
+
+    /// The number of live events queued for a subscription before it is
+    /// ended as too slow. The default is 10000. While a subscription replays
+    /// from `from_slot`, its live events are buffered separately, up to
+    /// `journal_size` of them.
+    pub max_queued_events: Option<usize>,
+}
+
+impl GrpcSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if let Err(err) = self.listen_addr.to_socket_addrs() {
+            report.error(format!(
+                "\"listen_addr\" of the grpc sink: {:?} is not a valid address: ({})",
+                self.listen_addr, err
+            ));
+        }
+        if self.max_queued_events == Some(0) {
+            report.error("\"max_queued_events\" of the grpc sink must be greater than 0");
+        }
+    }
+}
+
+struct JournalEntry {
+    commitment: Commitment,
+    event: InscriptionEvent,
+}
+
+/// The most recent events, in the order they were streamed.
+#[derive(Default)]
+struct Journal {
+    processed: VecDeque<Arc<JournalEntry>>,
+    rooted: VecDeque<Arc<JournalEntry>>,
+}
+
+impl Journal {
+    fn entries(&self, commitment: Commitment) -> &VecDeque<Arc<JournalEntry>> {
+        match commitment {
+            Commitment::Processed => &self.processed,
+            Commitment::Rooted => &self.rooted,
+        }
+    }
+
+    fn entries_mut(&mut self, commitment: Commitment) -> &mut VecDeque<Arc<JournalEntry>> {
+        match commitment {
+            Commitment::Processed => &mut self.processed,
+            Commitment::Rooted => &mut self.rooted,
+        }
+    }
+}
+
+/// The PostgreSQL database written by the `postgres` sink, connected on
+/// first use.
+struct Database {
+    config: GeyserPluginPostgresConfig,
+    layout: SchemaLayout,
+    client: tokio::sync::Mutex<Option<Arc<Client>>>,
+}
+
+impl Database {
+    async fn client(&self) -> Result<Arc<Client>, Status> {
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref().filter(|client| !client.is_closed()) {
+            return Ok(client.clone());
+        }
+        let new_client = SimplePostgresClient::connect_to_db_async(&self.config)
+            .await
+            .map(Arc::new)
+            .map_err(|err| Status::unavailable(err.to_string()))?;
+        *client = Some(new_client.clone());
+        Ok(new_client)
+    }
+}
+
+struct GrpcState {
+    /// Locked while an entry is journaled and broadcast, so that a new
+    /// subscription sees every entry either in the journal or live.
+    journal: Mutex<Journal>,
+    journal_size: usize,
+    sender: broadcast::Sender<Arc<JournalEntry>>,
+    database: Option<Database>,
+    /// The program the events of the database were decoded from.
+    program_id: Pubkey,
+}
+
+/// A subscription as of the moment it was made.
+struct Subscription {
+    filter: EventFilter,
+    commitment: Commitment,
+    from_slot: Option<u64>,
+    live: broadcast::Receiver<Arc<JournalEntry>>,
+    /// The journaled entries from `from_slot` on.
+    journal: Vec<Arc<JournalEntry>>,
+    journal_first_slot: Option<u64>,
+}
+
+type EventKey = (Signature, u32);
+
+impl GrpcState {
+    fn subscribe(
+        &self,
+        filter: EventFilter,
+        commitment: Commitment,
+        from_slot: Option<u64>,
+    ) -> Subscription {
+        let journal = self.journal.lock().unwrap();
+        let live = self.sender.subscribe();
+        let entries = journal.entries(commitment);
+        Subscription {
+            filter,
+            commitment,
+            from_slot,
+            live,
+            journal: from_slot
+                .map(|from_slot| {
+                    entries
+                        .iter()
+                        .filter(|entry| entry.event.slot >= from_slot)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default(),
+            journal_first_slot: entries.front().map(|entry| entry.event.slot),
+        }
+    }
+
+    /// Send `event` to the client. Returns false once the client is gone.
+    async fn send(
+        sender: &mpsc::Sender<Result<SubscribeResponse, Status>>,
+        commitment: Commitment,
+        event: &InscriptionEvent,
+    ) -> bool {
+        let response = SubscribeResponse {
+            commitment: proto::Commitment::from(commitment) as i32,
+            event: Some(event.into()),
+        };
+        sender.send(Ok(response)).await.is_ok()
+    }
+
+    /// Replay, then stream live, the events of `subscription` until the
+    /// client goes away.
+    async fn run_subscription(
+        &self,
+        subscription: Subscription,
+        sender: &mpsc::Sender<Result<SubscribeResponse, Status>>,
+    ) -> Result<(), Status> {
+        let Subscription {
+            filter,
+            commitment,
+            from_slot,
+            mut live,
+            journal,
+            journal_first_slot,
+        } = subscription;
+        let mut replayed = HashMap::new();
+        // The live events matching the subscription, taken off the broadcast
+        // channel while replaying so that a long replay does not make it lag.
+        let mut pending = VecDeque::new();
+
+        if let Some(from_slot) = from_slot {
+            let replay = self.replay(
+                &filter,
+                commitment,
+                (from_slot, journal_first_slot),
+                journal,
+                &mut replayed,
+                sender,
+            );
+            tokio::pin!(replay);
+            let mut live_open = true;
+            let connected = loop {
+                tokio::select! {
+                    connected = &mut replay => break connected?,
+                    entry = live.recv(), if live_open => match entry {
+                        Ok(entry) if entry.commitment == commitment && filter.matches(&entry.event) => {
+                            if pending.len() >= self.journal_size {
+                                return Err(Status::resource_exhausted(format!(
+                                    "The subscription fell behind by more than {} events while replaying",
+                                    self.journal_size
+                                )));
+                            }
+                            pending.push_back(entry);
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(count)) => {
+                            return Err(Status::resource_exhausted(format!(
+                                "The subscription fell behind by {} events",
+                                count
+                            )));
+                        }
+                        Err(broadcast::error::RecvError::Closed) => live_open = false,
+                    },
+                }
+            };
+            if !connected {
+                return Ok(());
+            }
+        }
+
+        for entry in pending {
+            let event = &entry.event;
+            if !replayed.contains_key(&(event.signature, event.instruction_index))
+                && !Self::send(sender, commitment, event).await
+            {
+                return Ok(());
+            }
+        }
+
+        loop {
+            let entry = tokio::select! {
+                entry = live.recv() => entry,
+                () = sender.closed() => return Ok(()),
+            };
+            match entry {
+                Ok(entry) => {
+                    let event = &entry.event;
+                    if entry.commitment == commitment
+                        && filter.matches(event)
+                        && !replayed.contains_key(&(event.signature, event.instruction_index))
+                        && !Self::send(sender, commitment, event).await
+                    {
+                        return Ok(());
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    return Err(Status::resource_exhausted(format!(
+                        "The subscription fell behind by {} events",
+                        count
+                    )));
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    /// Send the events from `from_slot` on, from the database up to the
+    /// first slot of the journal, then from `journal`. Returns false once
+    /// the client is gone.
+    async fn replay(
+        &self,
+        filter: &EventFilter,
+        commitment: Commitment,
+        (from_slot, journal_first_slot): (u64, Option<u64>),
+        journal: Vec<Arc<JournalEntry>>,
+        replayed: &mut HashMap<EventKey, u64>,
+        sender: &mpsc::Sender<Result<SubscribeResponse, Status>>,
+    ) -> Result<bool, Status> {
+        let end_slot = journal_first_slot.unwrap_or(u64::MAX);
+        if from_slot < end_slot {
+            match &self.database {
+                Some(database) => {
+                    let connected = self
+                        .replay_database(
+                            database,
+                            filter,
+                            commitment,
+                            (from_slot, end_slot),
+                            replayed,
+                            sender,
+                        )
+                        .await?;
+                    if !connected {
+                        return Ok(false);
+                    }
+                }
+                None if journal_first_slot.is_some() => {
+                    return Err(Status::out_of_range(format!(
+                        "Slot {} is older than the journal, which starts at slot {}, and no database is configured",
+                        from_slot, end_slot
+                    )));
+                }
+                None => {}
+            }
+        }
+
+        for entry in journal {
+            let event = &entry.event;
+            if filter.matches(event)
+                && !replayed.contains_key(&(event.signature, event.instruction_index))
+                && !Self::send(sender, commitment, event).await
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Send the events of the database in `slots`, remembering the last
+    /// ones in `replayed`. Returns false once the client is gone.
+    async fn replay_database(
+        &self,
+        database: &Database,
+        filter: &EventFilter,
+        commitment: Commitment,
+        (from_slot, end_slot): (u64, u64),
+        replayed: &mut HashMap<EventKey, u64>,
+        sender: &mpsc::Sender<Result<SubscribeResponse, Status>>,
+    ) -> Result<bool, Status> {
+        let client = database.client().await?;
+        let statement = client
+            .prepare(&database.layout.render(REPLAY_QUERY))
+            .await
+            .map_err(database_error)?;
+        let end_slot = end_slot.min(i64::MAX as u64) as i64;
+        let rooted = commitment == Commitment::Rooted;
+        let (mut slot, mut signature, mut instruction_index) = (
+            from_slot as i64,
+            DbKey::min(database.layout.key_storage()),
+            -1,
+        );
+
+        loop {
+            let rows = client
+                .query(
+                    &statement,
+                    &[
+                        &slot,
+                        &signature,
+                        &instruction_index,
+                        &end_slot,
+                        &rooted,
+                        &REPLAY_PAGE_SIZE,
+                    ],
+                )
+                .await
+                .map_err(database_error)?;
+
+            for row in &rows {
+                let event = self.event_from_row(row)?;
+                slot = event.slot as i64;
+                signature = row.get(1);
+                instruction_index = event.instruction_index as i32;
+                replayed.insert((event.signature, event.instruction_index), event.slot);
+                if filter.matches(&event) && !Self::send(sender, commitment, &event).await {
+                    return Ok(false);
+                }
+            }
+
+            let last_slot = slot as u64;
+            replayed.retain(|_, slot| *slot + REPLAY_OVERLAP_SLOTS >= last_slot);
+            if (rows.len() as i64) < REPLAY_PAGE_SIZE {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn event_from_row(&self, row: &Row) -> Result<InscriptionEvent, Status> {
+        InscriptionEvent::from_row(row, self.program_id).map_err(Status::internal)
+    }
+
+    /// The most recent processed events of the journal matching
+    /// `predicate`.
+    fn journaled_events(
+        &self,
+        limit: usize,
+        predicate: impl Fn(&InscriptionEvent) -> bool,
+    ) -> Vec<proto::InscriptionEvent> {
+        let journal = self.journal.lock().unwrap();
+        journal
+            .processed
+            .iter()
+            .rev()
+            .map(|entry| &entry.event)
+            .filter(|event| predicate(event))
+            .take(limit)
+            .map(Into::into)
+            .collect()
+    }
+}
+
+struct InscriptionsService {
+    state: Arc<GrpcState>,
+}
+
+#[tonic::async_trait]
+impl Inscriptions for InscriptionsService {
+    type SubscribeStream = ReceiverStream<Result<SubscribeResponse, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let filter = EventFilter::new(&filter_config(request.filter.unwrap_or_default())?)
+            .map_err(Status::invalid_argument)?;
+        let commitment = match proto::Commitment::from_i32(request.commitment) {
+            Some(proto::Commitment::Processed) => Commitment::Processed,
+            Some(proto::Commitment::Rooted) => Commitment::Rooted,
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown commitment {}",
+                    request.commitment
+                )))
+            }
+        };
+
+        let subscription = self.state.subscribe(filter, commitment, request.from_slot);
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            if let Err(status) = state.run_subscription(subscription, &sender).await {
+                debug!("Ending a gRPC subscription: ({})", status);
+                let _ = sender.send(Err(status)).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_inscription(
+        &self,
+        request: Request<GetInscriptionRequest>,
+    ) -> Result<Response<Inscription>, Status> {
+        let account = parse_key("account", &request.into_inner().account)?;
+
+        let inscription = match &self.state.database {
+            Some(database) => {
+                let client = database.client().await?;
+                client
+                    .query_opt(
+                        &database.layout.render(INSCRIPTION_QUERY),
+                        &[&DbKey::pubkey(&account, database.layout.key_storage())],
+                    )
+                    .await
+                    .map_err(database_error)?
+                    .map(|row| inscription_from_row(&row))
+                    .transpose()
+                    .map_err(Status::internal)?
+            }
+            None => {
+                let journal = self.state.journal.lock().unwrap();
+                journal
+                    .processed
+                    .iter()
+                    .rev()
+                    .filter(|entry| entry.event.inscription_account == Some(account))
+                    .find_map(|entry| DbInscription::from_event(&entry.event))
+                    .map(Into::into)
+            }
+        };
+
+        inscription
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("No inscription was created at {}", account)))
+    }
+
+    async fn list_events(
+        &self,
+        request: Request<ListEventsRequest>,
+    ) -> Result<Response<ListEventsResponse>, Status> {
+        let request = request.into_inner();
+        let limit = request
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .min(MAX_LIST_LIMIT);
+        let key = request.key.ok_or_else(|| {
+            Status::invalid_argument("Either \"inscription_account\" or \"signature\" must be set")
+        })?;
+
+        let events = match (&self.state.database, key) {
+            (Some(database), key) => {
+                let key_storage = database.layout.key_storage();
+                let (query, key) = match key {
+                    Key::InscriptionAccount(account) => (
+                        EVENTS_BY_ACCOUNT_QUERY,
+                        DbKey::pubkey(&parse_key("inscription_account", &account)?, key_storage),
+                    ),
+                    Key::Signature(signature) => (
+                        EVENTS_BY_SIGNATURE_QUERY,
+                        DbKey::signature(&parse_signature(&signature)?, key_storage),
+                    ),
+                };
+                let client = database.client().await?;
+                client
+                    .query(&database.layout.render(query), &[&key, &(limit as i64)])
+                    .await
+                    .map_err(database_error)?
+                    .iter()
+                    .map(|row| self.state.event_from_row(row).map(|event| (&event).into()))
+                    .collect::<Result<_, _>>()?
+            }
+            (None, Key::InscriptionAccount(account)) => {
+                let account = parse_key("inscription_account", &account)?;
+                self.state.journaled_events(limit as usize, |event| {
+                    event.inscription_account == Some(account)
+                })
+            }
+            (None, Key::Signature(signature)) => {
+                let signature = parse_signature(&signature)?;
+                self.state
+                    .journaled_events(limit as usize, |event| event.signature == signature)
+            }
+        };
+        Ok(Response::new(ListEventsResponse { events }))
+    }
+}
+
+/// Read an `Inscription` from the `INSCRIPTION_QUERY` columns.
+fn inscription_from_row(row: &Row) -> Result<Inscription, String> {
+    let key = |index| {
+        row.get::<_, Option<DbKey>>(index)
+            .map(|key| key.to_pubkey().map(|key| key.to_string()))
+            .transpose()
+    };
+    Ok(Inscription {
+        slot: row.get::<_, i64>(0) as u64,
+        signature: row.get::<_, DbKey>(1).to_signature()?.to_string(),
+        account: key(2)?.unwrap_or_default(),
+        mint_account: key(3)?,
+        metadata_account: key(4)?,
+        authority: key(5)?.unwrap_or_default(),
+    })
+}
+
+fn database_error(err: tokio_postgres::Error) -> Status {
+    Status::unavailable(format!(
+        "Failed to query the PostgreSQL database: ({})",
+        err
+    ))
+}
+
+fn parse_key(field: &str, key: &str) -> Result<Pubkey, Status> {
+    Pubkey::from_str(key).map_err(|err| {
+        Status::invalid_argument(format!(
+            "\"{}\": {:?} is not a valid public key: ({})",
+            field, key, err
+        ))
+    })
+}
+
+fn parse_signature(signature: &str) -> Result<Signature, Status> {
+    Signature::from_str(signature).map_err(|err| {
+        Status::invalid_argument(format!(
+            "\"signature\": {:?} is not a valid signature: ({})",
+            signature, err
+        ))
+    })
+}
+
+/// Convert a request filter, where an empty list matches all events.
+fn filter_config(filter: proto::EventFilter) -> Result<EventFilterConfig, Status> {
+    let non_empty = |keys: Vec<String>| (!keys.is_empty()).then_some(keys);
+    let event_types = filter
+        .event_types
+        .iter()
+        .map(|event_type| {
+            proto::InstructionType::from_i32(*event_type)
+                .map(InscriptionInstructionType::from)
+                .ok_or_else(|| {
+                    Status::invalid_argument(format!("Unknown instruction type {}", event_type))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(EventFilterConfig {
+        program_ids: non_empty(filter.program_ids),
+        authorities: non_empty(filter.authorities),
+        mints: non_empty(filter.mints),
+        accounts: non_empty(filter.accounts),
+        event_types: (!event_types.is_empty()).then_some(event_types),
+    })
+}
+
+impl From<Commitment> for proto::Commitment {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => proto::Commitment::Processed,
+            Commitment::Rooted => proto::Commitment::Rooted,
+        }
+    }
+}
+
+impl From<InscriptionInstructionType> for proto::InstructionType {
+    fn from(instruction_type: InscriptionInstructionType) -> Self {
+        match instruction_type {
+            InscriptionInstructionType::Initialize => proto::InstructionType::Initialize,
+            InscriptionInstructionType::InitializeFromMint => {
+                proto::InstructionType::InitializeFromMint
+            }
+            InscriptionInstructionType::WriteData => proto::InstructionType::WriteData,
+            InscriptionInstructionType::Close => proto::InstructionType::Close,
+            InscriptionInstructionType::Other => proto::InstructionType::Other,
+        }
+    }
+}
+
+impl From<proto::InstructionType> for InscriptionInstructionType {
+    fn from(instruction_type: proto::InstructionType) -> Self {
+        match instruction_type {
+            proto::InstructionType::Initialize => InscriptionInstructionType::Initialize,
+            proto::InstructionType::InitializeFromMint => {
+                InscriptionInstructionType::InitializeFromMint
+            }
+            proto::InstructionType::WriteData => InscriptionInstructionType::WriteData,
+            proto::InstructionType::Close => InscriptionInstructionType::Close,
+            proto::InstructionType::Other => InscriptionInstructionType::Other,
+        }
+    }
+}
+
+impl From<&InscriptionEvent> for proto::InscriptionEvent {
+    fn from(event: &InscriptionEvent) -> Self {
+        Self {
+            slot: event.slot,
+            signature: event.signature.to_string(),
+            instruction_index: event.instruction_index,
+            instruction_type: proto::InstructionType::from(event.instruction_type) as i32,
+            program_id: event.program_id.to_string(),
+            inscription_account: event.inscription_account.map(|key| key.to_string()),
+            metadata_account: event.metadata_account.map(|key| key.to_string()),
+            mint_account: event.mint_account.map(|key| key.to_string()),
+            authority: event.authority.map(|key| key.to_string()),
+            accounts: event.accounts.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+}
+
+impl From<DbInscription> for Inscription {
+    fn from(inscription: DbInscription) -> Self {
+        Self {
+            slot: inscription.slot as u64,
+            signature: inscription.signature,
+            account: inscription.account,
+            mint_account: inscription.mint_account,
+            metadata_account: Some(inscription.metadata_account),
+            authority: inscription.authority,
+        }
+    }
+}
+
+pub struct GrpcSink {
+    local_addr: SocketAddr,
+    state: Arc<GrpcState>,
+    rooted_events: RootedEvents,
+    runtime: Option<Runtime>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl GrpcSink {
+    pub fn new(
+        grpc_config: &GrpcSinkConfig,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Self, GeyserPluginError> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(SERVER_THREADS)
+            .thread_name("grpc-server")
+            .enable_all()
+            .build()
+            .map_err(|err| Self::error(format!("Failed to create the tokio runtime: ({})", err)))?;
+        let listener = runtime
+            .block_on(TcpListener::bind(&grpc_config.listen_addr))
+            .and_then(|listener| listener.local_addr().map(|addr| (listener, addr)));
+        let (listener, local_addr) = listener.map_err(|err| {
+            Self::error(format!(
+                "Failed to listen for gRPC connections on {:?}: ({})",
+                grpc_config.listen_addr, err
+            ))
+        })?;
+
+        let layout = SchemaLayout::new(config)?;
+        let database = config
+            .sinks()
+            .contains(&SinkConfig::Postgres)
+            .then(|| Database {
+                config: config.clone(),
+                layout,
+                client: tokio::sync::Mutex::new(None),
+            });
+        let (sender, _) = broadcast::channel(
+            grpc_config
+                .max_queued_events
+                .unwrap_or(DEFAULT_MAX_QUEUED_EVENTS)
+                .max(1),
+        );
+        let state = Arc::new(GrpcState {
+            journal: Mutex::new(Journal::default()),
+            journal_size: grpc_config
+                .journal_size
+                .unwrap_or(DEFAULT_JOURNAL_SIZE)
+                .max(1),
+            sender,
+            database,
+            program_id: ParallelPostgresClient::parse_program_id(config)?,
+        });
+
+        let (shutdown, shutdown_receiver) = oneshot::channel::<()>();
+        let server = Server::builder()
+            .add_service(InscriptionsServer::new(InscriptionsService {
+                state: state.clone(),
+            }))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                let _ = shutdown_receiver.await;
+            });
+        runtime.spawn(async move {
+            if let Err(err) = server.await {
+                error!("The gRPC server has failed: ({})", err);
+            }
+        });
+
+        info!("Serving inscription events over gRPC on {}", local_addr);
+        Ok(Self {
+            local_addr,
+            state,
+            rooted_events: RootedEvents::default(),
+            runtime: Some(runtime),
+            shutdown: Some(shutdown),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+
+    /// Journal `events` at `commitment` and stream them to the
+    /// subscriptions.
+    fn publish(&self, commitment: Commitment, events: &[InscriptionEvent]) {
+        let mut journal = self.state.journal.lock().unwrap();
+        let entries = journal.entries_mut(commitment);
+        for event in events {
+            let entry = Arc::new(JournalEntry {
+                commitment,
+                event: event.clone(),
+            });
+            if entries.len() >= self.state.journal_size {
+                entries.pop_front();
+            }
+            entries.push_back(entry.clone());
+            // Fails only when there is no subscription.
+            let _ = self.state.sender.send(entry);
+        }
+    }
+}
+
+impl InscriptionSink for GrpcSink {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        self.publish(Commitment::Processed, events);
+        let rooted = self.rooted_events.push(events);
+        self.publish(Commitment::Rooted, &rooted);
+        Ok(())
+    }
+
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        _parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        if *status == SlotStatus::Rooted {
+            let rooted = self.rooted_events.root(slot);
+            self.publish(Commitment::Rooted, &rooted);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GrpcSink {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        // Subscriptions never end by themselves, so they are cut short.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::time::timeout};
+
+    fn event(slot: u64, authority: Pubkey) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::new_unique(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: Some(authority),
+            accounts: vec![],
+        }
+    }
+
+    fn state(journal_size: usize) -> Arc<GrpcState> {
+        Arc::new(GrpcState {
+            journal: Mutex::new(Journal::default()),
+            journal_size,
+            sender: broadcast::channel(16).0,
+            database: None,
+            program_id: Pubkey::new_unique(),
+        })
+    }
+
+    /// Journal and broadcast `events` as `GrpcSink::publish` does.
+    fn publish(state: &GrpcState, commitment: Commitment, events: &[InscriptionEvent]) {
+        let mut journal = state.journal.lock().unwrap();
+        for event in events {
+            let entry = Arc::new(JournalEntry {
+                commitment,
+                event: event.clone(),
+            });
+            journal.entries_mut(commitment).push_back(entry.clone());
+            let _ = state.sender.send(entry);
+        }
+    }
+
+    /// Run a subscription and return the slots of the first `count` events
+    /// it sends, along with the task running it.
+    async fn receive(
+        state: &Arc<GrpcState>,
+        subscription: Subscription,
+        count: usize,
+        live: &[InscriptionEvent],
+    ) -> (
+        Vec<u64>,
+        mpsc::Receiver<Result<SubscribeResponse, Status>>,
+        tokio::task::JoinHandle<Result<(), Status>>,
+    ) {
+        let (sender, mut receiver) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move { state.run_subscription(subscription, &sender).await }
+        });
+        publish(state, Commitment::Processed, live);
+
+        let mut slots = Vec::new();
+        while slots.len() < count {
+            let response = timeout(Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            slots.push(response.event.unwrap().slot);
+        }
+        (slots, receiver, task)
+    }
+
+    #[test]
+    fn test_subscribe_snapshot() {
+        let state = state(10);
+        let authority = Pubkey::new_unique();
+        publish(
+            &state,
+            Commitment::Processed,
+            &[
+                event(1, authority),
+                event(2, authority),
+                event(3, authority),
+            ],
+        );
+        publish(&state, Commitment::Rooted, &[event(1, authority)]);
+
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Processed, Some(2));
+        let slots: Vec<_> = subscription
+            .journal
+            .iter()
+            .map(|entry| entry.event.slot)
+            .collect();
+        assert_eq!(slots, [2, 3]);
+        assert_eq!(subscription.journal_first_slot, Some(1));
+
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Rooted, Some(2));
+        assert!(subscription.journal.is_empty());
+        assert_eq!(subscription.journal_first_slot, Some(1));
+
+        // Without a start slot, only live events are streamed.
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Processed, None);
+        assert!(subscription.journal.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_journal_then_live() {
+        let state = state(10);
+        let authority = Pubkey::new_unique();
+        publish(
+            &state,
+            Commitment::Processed,
+            &[
+                event(1, authority),
+                event(2, authority),
+                event(2, Pubkey::new_unique()),
+                event(3, authority),
+            ],
+        );
+        let filter = EventFilter::new(&EventFilterConfig {
+            authorities: Some(vec![authority.to_string()]),
+            ..EventFilterConfig::default()
+        })
+        .unwrap();
+
+        let subscription = state.subscribe(filter, Commitment::Processed, Some(2));
+        let (slots, receiver, task) = receive(
+            &state,
+            subscription,
+            3,
+            &[event(4, Pubkey::new_unique()), event(4, authority)],
+        )
+        .await;
+        assert_eq!(slots, [2, 3, 4]);
+
+        // The subscription ends once the client goes away.
+        drop(receiver);
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_live_only() {
+        let state = state(10);
+        let authority = Pubkey::new_unique();
+        publish(&state, Commitment::Processed, &[event(1, authority)]);
+
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Processed, None);
+        let (slots, receiver, task) =
+            receive(&state, subscription, 1, &[event(2, authority)]).await;
+        assert_eq!(slots, [2]);
+        drop(receiver);
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_replay_older_than_journal_without_database() {
+        let state = state(10);
+        publish(
+            &state,
+            Commitment::Processed,
+            &[event(5, Pubkey::new_unique())],
+        );
+
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Processed, Some(2));
+        let (sender, _receiver) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let status = state
+            .run_subscription(subscription, &sender)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+        // With an empty journal, there is nothing older to miss.
+        let state = self::state(10);
+        let subscription = state.subscribe(EventFilter::default(), Commitment::Processed, Some(2));
+        let (slots, receiver, task) =
+            receive(&state, subscription, 1, &[event(7, Pubkey::new_unique())]).await;
+        assert_eq!(slots, [7]);
+        drop(receiver);
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_filter_config() {
+        let authority = Pubkey::new_unique().to_string();
+        let config = filter_config(proto::EventFilter {
+            authorities: vec![authority.clone()],
+            event_types: vec![proto::InstructionType::Close as i32],
+            ..proto::EventFilter::default()
+        })
+        .unwrap();
+        assert_eq!(
+            config,
+            EventFilterConfig {
+                authorities: Some(vec![authority]),
+                event_types: Some(vec![InscriptionInstructionType::Close]),
+                ..EventFilterConfig::default()
+            }
+        );
+
+        let status = filter_config(proto::EventFilter {
+            event_types: vec![100],
+            ..proto::EventFilter::default()
+        })
+        .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,378 @@
+/// A sink writing every event as one JSON object per line. The file is
+/// rotated once it reaches `max_file_size_bytes` or is older than
+/// `rotate_interval_secs`, and can be gzip compressed.
+use {
+    crate::{
+        geyser_plugin_postgres::{ConfigReport, GeyserPluginPostgresError},
+        inscription_sink::{InscriptionSink, JsonInscriptionEvent},
+        postgres_client::postgres_client_transaction::InscriptionEvent,
+    },
+    chrono::Utc,
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    flate2::{write::GzEncoder, Compression},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        fs::{self, File},
+        io::{self, BufWriter, Write},
+        path::PathBuf,
+        time::{Duration, Instant},
+    },
+};
+
+const DEFAULT_FILE_PREFIX: &str = "inscriptions";
+const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_ROTATE_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonLinesSinkConfig {
+    /// The directory the files are written to. It is created if missing.
+    pub directory: String,
+
+    /// The prefix of the file names, followed by the time the file was
+    /// opened. The default is "inscriptions".
+    pub file_prefix: Option<String>,
+
+    /// The number of uncompressed bytes after which the file is rotated.
+    /// The default is 104857600 (100 MiB).
+    pub max_file_size_bytes: Option<u64>,
+
+    /// The number of seconds after which the file is rotated, 0 to rotate
+    /// by size only. The default is 3600.
+    pub rotate_interval_secs: Option<u64>,
+
+    /// Controls whether the files are gzip compressed. The default is false.
+    pub gzip: Option<bool>,
+}
+
+impl JsonLinesSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if self.directory.is_empty() {
+            report.error("\"directory\" of the json_lines sink must not be empty");
+        }
+        if self.max_file_size_bytes == Some(0) {
+            report.error("\"max_file_size_bytes\" of the json_lines sink must be greater than 0");
+        }
+    }
+}
+
+enum JsonLinesWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl JsonLinesWriter {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            JsonLinesWriter::Plain(writer) => writer,
+            JsonLinesWriter::Gzip(writer) => writer,
+        }
+    }
+
+    /// Flush and, for gzip, write the trailer.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            JsonLinesWriter::Plain(mut writer) => writer.flush(),
+            JsonLinesWriter::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: JsonLinesWriter,
+    opened_at: Instant,
+    /// Uncompressed bytes written so far.
+    size: u64,
+}
+
+pub struct JsonLinesSink {
+    directory: PathBuf,
+    file_prefix: String,
+    max_file_size_bytes: u64,
+    rotate_interval: Option<Duration>,
+    gzip: bool,
+    file: Option<OpenFile>,
+    /// Distinguishes files opened within the same second.
+    sequence: u64,
+}
+
+impl JsonLinesSink {
+    pub fn new(config: &JsonLinesSinkConfig) -> Result<Self, GeyserPluginError> {
+        let directory = PathBuf::from(&config.directory);
+        fs::create_dir_all(&directory).map_err(|err| {
+            Self::error(format!(
+                "Failed to create the JSON Lines directory {:?}: ({})",
+                directory, err
+            ))
+        })?;
+
+        let rotate_interval_secs = config
+            .rotate_interval_secs
+            .unwrap_or(DEFAULT_ROTATE_INTERVAL_SECS);
+        let mut sink = Self {
+            directory,
+            file_prefix: config
+                .file_prefix
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FILE_PREFIX.to_string()),
+            max_file_size_bytes: config
+                .max_file_size_bytes
+                .unwrap_or(DEFAULT_MAX_FILE_SIZE_BYTES),
+            rotate_interval: (rotate_interval_secs > 0)
+                .then(|| Duration::from_secs(rotate_interval_secs)),
+            gzip: config.gzip.unwrap_or_default(),
+            file: None,
+            sequence: 0,
+        };
+        // Open the first file right away so that a bad directory fails the
+        // plugin load rather than the first write.
+        sink.open_file()?;
+        Ok(sink)
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+
+    fn open_file(&mut self) -> Result<(), GeyserPluginError> {
+        let extension = if self.gzip { "jsonl.gz" } else { "jsonl" };
+        let path = self.directory.join(format!(
+            "{}-{}-{}.{}",
+            self.file_prefix,
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            self.sequence,
+            extension
+        ));
+        self.sequence += 1;
+
+        let file = File::create(&path).map_err(|err| {
+            Self::error(format!(
+                "Failed to create the JSON Lines file {:?}: ({})",
+                path, err
+            ))
+        })?;
+        let writer = BufWriter::new(file);
+        let writer = if self.gzip {
+            JsonLinesWriter::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            JsonLinesWriter::Plain(writer)
+        };
+
+        info!("Writing inscription events to {:?}", path);
+        self.file = Some(OpenFile {
+            path,
+            writer,
+            opened_at: Instant::now(),
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<(), GeyserPluginError> {
+        if let Some(file) = self.file.take() {
+            file.writer.finish().map_err(|err| {
+                Self::error(format!(
+                    "Failed to close the JSON Lines file {:?}: ({})",
+                    file.path, err
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn needs_rotation(&self) -> bool {
+        match &self.file {
+            None => true,
+            Some(file) => {
+                file.size >= self.max_file_size_bytes
+                    || self
+                        .rotate_interval
+                        .map(|interval| file.opened_at.elapsed() >= interval)
+                        .unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl InscriptionSink for JsonLinesSink {
+    fn name(&self) -> &'static str {
+        "json_lines"
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        if self.needs_rotation() {
+            self.close_file()?;
+            self.open_file()?;
+        }
+
+        let file = self.file.as_mut().unwrap();
+        let mut lines = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut lines, &JsonInscriptionEvent::from(event))
+                .map_err(|err| Self::error(format!("Failed to serialize an event: ({})", err)))?;
+            lines.push(b'\n');
+        }
+
+        file.writer.writer().write_all(&lines).map_err(|err| {
+            Self::error(format!(
+                "Failed to write to the JSON Lines file {:?}: ({})",
+                file.path, err
+            ))
+        })?;
+        file.size += lines.len() as u64;
+        Ok(())
+    }
+
+    /// Plain files are flushed after every batch. Gzip files are only once
+    /// rotated, as flushing the encoder ends a deflate block each time, which
+    /// compresses poorly; a file due for rotation is rotated here, so that
+    /// its events reach the disk even when no batch follows.
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        if self.gzip {
+            let written = self.file.as_ref().map_or(false, |file| file.size > 0);
+            if written && self.needs_rotation() {
+                self.close_file()?;
+                self.open_file()?;
+            }
+            return Ok(());
+        }
+        if let Some(file) = &mut self.file {
+            file.writer.writer().flush().map_err(|err| {
+                Self::error(format!(
+                    "Failed to flush the JSON Lines file {:?}: ({})",
+                    file.path, err
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JsonLinesSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.close_file() {
+            error!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::postgres_client::postgres_client_transaction::InscriptionInstructionType,
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+        flate2::read::GzDecoder,
+        std::io::Read,
+    };
+
+    fn event(slot: u64) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: Some(Pubkey::new_unique()),
+            accounts: vec![Pubkey::new_unique()],
+        }
+    }
+
+    fn sink(directory: &tempfile::TempDir, max_file_size_bytes: u64, gzip: bool) -> JsonLinesSink {
+        JsonLinesSink::new(&JsonLinesSinkConfig {
+            directory: directory.path().to_str().unwrap().to_string(),
+            file_prefix: None,
+            max_file_size_bytes: Some(max_file_size_bytes),
+            rotate_interval_secs: Some(0),
+            gzip: Some(gzip),
+        })
+        .unwrap()
+    }
+
+    /// The files of `directory`, in the order they were opened.
+    fn files(directory: &tempfile::TempDir) -> Vec<PathBuf> {
+        let mut files = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// The slots of the events of a file.
+    fn slots(contents: &str) -> Vec<u64> {
+        contents
+            .lines()
+            .map(|line| {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                event["slot"].as_u64().unwrap()
+            })
+            .collect()
+    }
+
+    fn gunzip(path: &std::path::Path) -> String {
+        let mut contents = String::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_rotates_by_size() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory, 1, false);
+        sink.write_events(&[event(1), event(2)]).unwrap();
+        sink.flush().unwrap();
+        // Flushed after the batch, without closing the file.
+        let first = files(&directory);
+        assert_eq!(first.len(), 1);
+        assert_eq!(slots(&fs::read_to_string(&first[0]).unwrap()), [1, 2]);
+
+        sink.write_events(&[event(3)]).unwrap();
+        sink.flush().unwrap();
+        let files = files(&directory);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0], first[0]);
+        assert!(files[1].to_str().unwrap().ends_with("-1.jsonl"));
+        assert_eq!(slots(&fs::read_to_string(&files[1]).unwrap()), [3]);
+    }
+
+    #[test]
+    fn test_gzip_file_completed_on_rotation() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory, 1, true);
+        sink.write_events(&[event(1), event(2)]).unwrap();
+        // The batch filled the file, which is rotated on flush.
+        sink.flush().unwrap();
+        let files = files(&directory);
+        assert_eq!(files.len(), 2);
+        assert!(files[0].to_str().unwrap().ends_with("-0.jsonl.gz"));
+        assert_eq!(slots(&gunzip(&files[0])), [1, 2]);
+
+        // Nothing is written to the new file until it is rotated or closed.
+        sink.write_events(&[event(3)]).unwrap();
+        drop(sink);
+        assert_eq!(slots(&gunzip(&files[1])), [3]);
+    }
+
+    #[test]
+    fn test_gzip_not_rotated_before_due() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory, DEFAULT_MAX_FILE_SIZE_BYTES, true);
+        sink.write_events(&[event(1)]).unwrap();
+        sink.flush().unwrap();
+        sink.write_events(&[event(2)]).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert_eq!(slots(&gunzip(&files[0])), [1, 2]);
+    }
+}
@@ -0,0 +1,626 @@
+/// Writes inscription events, and the `inscriptions` rows they create, to
+/// Parquet files partitioned by slot range, in the Hive layout:
+///
+/// ```text
+/// <directory>/inscription_events/slot_start=250000000/part-20240131T120000-0.parquet
+/// <directory>/inscriptions/slot_start=250000000/part-20240131T120000-1.parquet
+/// ```
+///
+/// Rows are buffered per partition and a file is written once a partition
+/// holds `max_rows_per_file` rows or on `flush`. Files are written under a
+/// temporary name and renamed once complete, so readers never see a partial
+/// file. The same writer backs the `parquet` sink and the
+/// `inscriptions-export-parquet` command.
+use {
+    crate::{
+        geyser_plugin_postgres::{ConfigReport, GeyserPluginPostgresError},
+        inscription_sink::InscriptionSink,
+        postgres_client::postgres_client_transaction::{DbInscription, InscriptionEvent},
+    },
+    arrow_array::{
+        builder::{ListBuilder, StringBuilder},
+        ArrayRef, Int32Array, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+    },
+    arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    chrono::{NaiveDateTime, Utc},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    log::*,
+    parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties},
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        fs::{self, File},
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+};
+
+/// The number of slots of an epoch.
+pub const DEFAULT_SLOTS_PER_PARTITION: u64 = 432_000;
+pub const DEFAULT_MAX_ROWS_PER_FILE: usize = 1_000_000;
+const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 300;
+
+const EVENTS_TABLE: &str = "inscription_events";
+const INSCRIPTIONS_TABLE: &str = "inscriptions";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetSinkConfig {
+    /// The directory the files are written to. It is created if missing.
+    pub directory: String,
+
+    /// The number of slots of a partition, named after the first one, e.g.
+    /// `slot_start=250000000`. The default is 432000, the slots of an epoch.
+    pub slots_per_partition: Option<u64>,
+
+    /// The number of rows after which a partition is written to a file.
+    /// The default is 1000000.
+    pub max_rows_per_file: Option<usize>,
+
+    /// The number of seconds after which buffered rows are written to a
+    /// file even if there are fewer than `max_rows_per_file`. The default is
+    /// 300.
+    pub flush_interval_secs: Option<u64>,
+}
+
+impl ParquetSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if self.directory.is_empty() {
+            report.error("\"directory\" of the parquet sink must not be empty");
+        }
+        if self.slots_per_partition == Some(0) {
+            report.error("\"slots_per_partition\" of the parquet sink must be greater than 0");
+        }
+        if self.max_rows_per_file == Some(0) {
+            report.error("\"max_rows_per_file\" of the parquet sink must be greater than 0");
+        }
+    }
+}
+
+/// The schema of the `inscription_events` files.
+pub fn events_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("slot", DataType::Int64, false),
+        Field::new("signature", DataType::Utf8, false),
+        Field::new("instruction_index", DataType::Int32, false),
+        Field::new("instruction_type", DataType::Utf8, false),
+        Field::new("program_id", DataType::Utf8, false),
+        Field::new("inscription_account", DataType::Utf8, true),
+        Field::new("metadata_account", DataType::Utf8, true),
+        Field::new("mint_account", DataType::Utf8, true),
+        Field::new("authority", DataType::Utf8, true),
+        Field::new(
+            "accounts",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        Field::new(
+            "updated_on",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+    ]))
+}
+
+/// The schema of the `inscriptions` files.
+pub fn inscriptions_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("slot", DataType::Int64, false),
+        Field::new("signature", DataType::Utf8, false),
+        Field::new("account", DataType::Utf8, false),
+        Field::new("mint_account", DataType::Utf8, true),
+        Field::new("metadata_account", DataType::Utf8, true),
+        Field::new("authority", DataType::Utf8, false),
+        Field::new(
+            "updated_on",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+    ]))
+}
+
+/// A row of the `inscriptions` files. `metadata_account` is optional, as in
+/// the `inscriptions` table.
+pub struct InscriptionRow {
+    pub slot: i64,
+    pub signature: String,
+    pub account: String,
+    pub mint_account: Option<String>,
+    pub metadata_account: Option<String>,
+    pub authority: String,
+}
+
+impl From<DbInscription> for InscriptionRow {
+    fn from(inscription: DbInscription) -> Self {
+        Self {
+            slot: inscription.slot,
+            signature: inscription.signature,
+            account: inscription.account,
+            mint_account: inscription.mint_account,
+            metadata_account: Some(inscription.metadata_account),
+            authority: inscription.authority,
+        }
+    }
+}
+
+pub struct PartitionedParquetWriter {
+    directory: PathBuf,
+    slots_per_partition: u64,
+    max_rows_per_file: usize,
+    events: BTreeMap<String, Vec<(InscriptionEvent, NaiveDateTime)>>,
+    inscriptions: BTreeMap<String, Vec<(InscriptionRow, NaiveDateTime)>>,
+    /// The lowest slot of the buffered rows, until they are all written.
+    buffered_slot: Option<u64>,
+    /// Distinguishes files written within the same second.
+    sequence: u64,
+}
+
+impl PartitionedParquetWriter {
+    pub fn new(
+        directory: PathBuf,
+        slots_per_partition: u64,
+        max_rows_per_file: usize,
+    ) -> Result<Self, String> {
+        fs::create_dir_all(&directory).map_err(|err| {
+            format!(
+                "Failed to create the Parquet directory {:?}: ({})",
+                directory, err
+            )
+        })?;
+        Ok(Self {
+            directory,
+            slots_per_partition: slots_per_partition.max(1),
+            max_rows_per_file: max_rows_per_file.max(1),
+            events: BTreeMap::new(),
+            inscriptions: BTreeMap::new(),
+            buffered_slot: None,
+            sequence: 0,
+        })
+    }
+
+    fn partition(&self, slot: u64) -> String {
+        format!("slot_start={}", slot - slot % self.slots_per_partition)
+    }
+
+    /// Buffer `event`, writing its partition once full. The row is buffered
+    /// even when the write fails: the rows of the partition are kept for the
+    /// next attempt.
+    pub fn push_event(
+        &mut self,
+        event: InscriptionEvent,
+        updated_on: NaiveDateTime,
+    ) -> Result<(), String> {
+        let partition = self.partition(event.slot);
+        self.buffer_slot(event.slot);
+        let rows = self.events.entry(partition.clone()).or_default();
+        rows.push((event, updated_on));
+        if rows.len() >= self.max_rows_per_file {
+            let rows = self.events.remove(&partition).unwrap_or_default();
+            if let Err(err) = self.write_events(&partition, &rows) {
+                self.events.insert(partition, rows);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffer `inscription`, writing its partition once full, as
+    /// `push_event` does.
+    pub fn push_inscription(
+        &mut self,
+        inscription: InscriptionRow,
+        updated_on: NaiveDateTime,
+    ) -> Result<(), String> {
+        let partition = self.partition(inscription.slot as u64);
+        self.buffer_slot(inscription.slot as u64);
+        let rows = self.inscriptions.entry(partition.clone()).or_default();
+        rows.push((inscription, updated_on));
+        if rows.len() >= self.max_rows_per_file {
+            let rows = self.inscriptions.remove(&partition).unwrap_or_default();
+            if let Err(err) = self.write_inscriptions(&partition, &rows) {
+                self.inscriptions.insert(partition, rows);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn buffer_slot(&mut self, slot: u64) {
+        self.buffered_slot = Some(
+            self.buffered_slot
+                .map_or(slot, |buffered| buffered.min(slot)),
+        );
+    }
+
+    /// The lowest slot of the rows not written to a file yet. It may be
+    /// lower once a partition holding it was written on its own.
+    pub fn buffered_slot(&self) -> Option<u64> {
+        self.buffered_slot
+    }
+
+    /// Write every buffered row. The rows of a file which fails to be
+    /// written are kept for the next call.
+    pub fn flush(&mut self) -> Result<(), String> {
+        while let Some((partition, rows)) = self.events.pop_first() {
+            if let Err(err) = self.write_events(&partition, &rows) {
+                self.events.insert(partition, rows);
+                return Err(err);
+            }
+        }
+        while let Some((partition, rows)) = self.inscriptions.pop_first() {
+            if let Err(err) = self.write_inscriptions(&partition, &rows) {
+                self.inscriptions.insert(partition, rows);
+                return Err(err);
+            }
+        }
+        self.buffered_slot = None;
+        Ok(())
+    }
+
+    fn write_events(
+        &mut self,
+        partition: &str,
+        rows: &[(InscriptionEvent, NaiveDateTime)],
+    ) -> Result<(), String> {
+        let keys = |key: fn(&InscriptionEvent) -> Option<String>| {
+            Arc::new(StringArray::from(
+                rows.iter().map(|(event, _)| key(event)).collect::<Vec<_>>(),
+            )) as ArrayRef
+        };
+        let mut accounts = ListBuilder::new(StringBuilder::new());
+        for (event, _) in rows {
+            for key in &event.accounts {
+                accounts.values().append_value(key.to_string());
+            }
+            accounts.append(true);
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from_iter_values(
+                rows.iter().map(|(event, _)| event.slot as i64),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|(event, _)| event.signature.to_string()),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                rows.iter().map(|(event, _)| event.instruction_index as i32),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter()
+                    .map(|(event, _)| event.instruction_type.as_str()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|(event, _)| event.program_id.to_string()),
+            )),
+            keys(|event| event.inscription_account.map(|key| key.to_string())),
+            keys(|event| event.metadata_account.map(|key| key.to_string())),
+            keys(|event| event.mint_account.map(|key| key.to_string())),
+            keys(|event| event.authority.map(|key| key.to_string())),
+            Arc::new(accounts.finish()),
+            Self::timestamps(rows.iter().map(|(_, updated_on)| updated_on)),
+        ];
+        self.write_file(EVENTS_TABLE, partition, events_schema(), columns)
+    }
+
+    fn write_inscriptions(
+        &mut self,
+        partition: &str,
+        rows: &[(InscriptionRow, NaiveDateTime)],
+    ) -> Result<(), String> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from_iter_values(
+                rows.iter().map(|(inscription, _)| inscription.slot),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter()
+                    .map(|(inscription, _)| inscription.signature.as_str()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter()
+                    .map(|(inscription, _)| inscription.account.as_str()),
+            )),
+            Arc::new(StringArray::from(
+                rows.iter()
+                    .map(|(inscription, _)| inscription.mint_account.as_deref())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                rows.iter()
+                    .map(|(inscription, _)| inscription.metadata_account.as_deref())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter()
+                    .map(|(inscription, _)| inscription.authority.as_str()),
+            )),
+            Self::timestamps(rows.iter().map(|(_, updated_on)| updated_on)),
+        ];
+        self.write_file(
+            INSCRIPTIONS_TABLE,
+            partition,
+            inscriptions_schema(),
+            columns,
+        )
+    }
+
+    fn timestamps<'a>(updated_on: impl Iterator<Item = &'a NaiveDateTime>) -> ArrayRef {
+        Arc::new(TimestampMicrosecondArray::from_iter_values(
+            updated_on.map(|updated_on| updated_on.and_utc().timestamp_micros()),
+        ))
+    }
+
+    fn write_file(
+        &mut self,
+        table: &str,
+        partition: &str,
+        schema: SchemaRef,
+        columns: Vec<ArrayRef>,
+    ) -> Result<(), String> {
+        let directory = self.directory.join(table).join(partition);
+        let name = format!(
+            "part-{}-{}.parquet",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            self.sequence
+        );
+        self.sequence += 1;
+        let path = directory.join(&name);
+        let temporary_path = directory.join(format!(".{}.tmp", name));
+
+        let batch = RecordBatch::try_new(schema.clone(), columns)
+            .map_err(|err| format!("Failed to build the rows of {:?}: ({})", path, err))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        fs::create_dir_all(&directory)
+            .map_err(|err| err.to_string())
+            .and_then(|()| File::create(&temporary_path).map_err(|err| err.to_string()))
+            .and_then(|file| {
+                ArrowWriter::try_new(file, schema, Some(properties)).map_err(|err| err.to_string())
+            })
+            .and_then(|mut writer| {
+                writer.write(&batch).map_err(|err| err.to_string())?;
+                writer.close().map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&temporary_path, &path).map_err(|err| err.to_string()))
+            .map_err(|err| format!("Failed to write the Parquet file {:?}: ({})", path, err))?;
+
+        debug!("Wrote {} rows to {:?}", batch.num_rows(), path);
+        Ok(())
+    }
+}
+
+/// A sink archiving the events to Parquet files.
+pub struct ParquetSink {
+    writer: PartitionedParquetWriter,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl ParquetSink {
+    pub fn new(config: &ParquetSinkConfig) -> Result<Self, GeyserPluginError> {
+        let writer = PartitionedParquetWriter::new(
+            PathBuf::from(&config.directory),
+            config
+                .slots_per_partition
+                .unwrap_or(DEFAULT_SLOTS_PER_PARTITION),
+            config
+                .max_rows_per_file
+                .unwrap_or(DEFAULT_MAX_ROWS_PER_FILE),
+        )
+        .map_err(Self::error)?;
+
+        info!(
+            "Archiving inscription events to Parquet in {:?}",
+            config.directory
+        );
+        Ok(Self {
+            writer,
+            flush_interval: Duration::from_secs(
+                config
+                    .flush_interval_secs
+                    .unwrap_or(DEFAULT_FLUSH_INTERVAL_SECS),
+            ),
+            last_flush: Instant::now(),
+        })
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+}
+
+impl InscriptionSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    /// Every event is buffered, even when writing a full partition fails:
+    /// its rows stay buffered, holding the watermark back, until a later
+    /// write succeeds. Failing the batch would buffer its events twice when
+    /// it is retried, so the failure is only logged.
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        let updated_on = Utc::now().naive_utc();
+        let mut failure = None;
+        for event in events {
+            if let Some(inscription) = DbInscription::from_event(event) {
+                if let Err(err) = self.writer.push_inscription(inscription.into(), updated_on) {
+                    failure = Some(err);
+                }
+            }
+            if let Err(err) = self.writer.push_event(event.clone(), updated_on) {
+                failure = Some(err);
+            }
+        }
+        if let Some(err) = failure {
+            error!("{}", err);
+        }
+        Ok(())
+    }
+
+    fn buffers_events(&self) -> bool {
+        true
+    }
+
+    fn buffered_slot(&self) -> Option<u64> {
+        self.writer.buffered_slot()
+    }
+
+    /// Files are written every `flush_interval_secs` rather than per batch,
+    /// which would make for many small files. Until then, the buffered rows
+    /// hold the watermark back. The rows of a failed write are kept for the
+    /// next interval, so the failure is only logged, as in `write_events`.
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        if self.last_flush.elapsed() < self.flush_interval {
+            return Ok(());
+        }
+        self.last_flush = Instant::now();
+        if let Err(err) = self.writer.flush() {
+            error!("{}", err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            error!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::postgres_client::postgres_client_transaction::InscriptionInstructionType,
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+    };
+
+    fn event(slot: u64) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: Some(Pubkey::new_unique()),
+            accounts: vec![Pubkey::new_unique()],
+        }
+    }
+
+    fn files(directory: &std::path::Path) -> Vec<String> {
+        let mut files = fs::read_dir(directory)
+            .map(|entries| {
+                entries
+                    .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_partitions_by_slot_range() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(&ParquetSinkConfig {
+            directory: directory.path().to_str().unwrap().to_string(),
+            slots_per_partition: Some(100),
+            max_rows_per_file: None,
+            flush_interval_secs: Some(3600),
+        })
+        .unwrap();
+
+        sink.write_events(&[event(250), event(120)]).unwrap();
+        sink.flush().unwrap();
+        // The rows stay buffered until the flush interval, holding the
+        // watermark back.
+        assert!(sink.buffers_events());
+        assert_eq!(sink.buffered_slot(), Some(120));
+        assert!(files(&directory.path().join(EVENTS_TABLE)).is_empty());
+
+        sink.writer.flush().unwrap();
+        assert_eq!(sink.buffered_slot(), None);
+        let events = directory.path().join(EVENTS_TABLE);
+        assert_eq!(files(&events), ["slot_start=100", "slot_start=200"]);
+        for partition in files(&events) {
+            let partition_files = files(&events.join(&partition));
+            assert_eq!(partition_files.len(), 1);
+            assert!(partition_files[0].ends_with(".parquet"));
+        }
+    }
+
+    #[test]
+    fn test_full_partition_is_written_right_away() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut writer =
+            PartitionedParquetWriter::new(directory.path().to_path_buf(), 100, 2).unwrap();
+        let updated_on = Utc::now().naive_utc();
+        writer.push_event(event(110), updated_on).unwrap();
+        writer.push_event(event(300), updated_on).unwrap();
+        writer.push_event(event(120), updated_on).unwrap();
+
+        let events = directory.path().join(EVENTS_TABLE);
+        assert_eq!(files(&events), ["slot_start=100"]);
+        // Conservatively kept until everything is written.
+        assert_eq!(writer.buffered_slot(), Some(110));
+        writer.flush().unwrap();
+        assert_eq!(writer.buffered_slot(), None);
+        assert_eq!(files(&events), ["slot_start=100", "slot_start=300"]);
+    }
+
+    #[test]
+    fn test_failed_write_keeps_rows() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut writer =
+            PartitionedParquetWriter::new(directory.path().to_path_buf(), 100, 2).unwrap();
+        // A file where the events directory should be makes every write fail.
+        File::create(directory.path().join(EVENTS_TABLE)).unwrap();
+        let updated_on = Utc::now().naive_utc();
+        writer.push_event(event(110), updated_on).unwrap();
+        assert!(writer.push_event(event(120), updated_on).is_err());
+        assert!(writer.push_event(event(130), updated_on).is_err());
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.buffered_slot(), Some(110));
+        assert_eq!(writer.events["slot_start=100"].len(), 3);
+
+        fs::remove_file(directory.path().join(EVENTS_TABLE)).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.buffered_slot(), None);
+        assert!(writer.events.is_empty());
+        let partition = directory.path().join(EVENTS_TABLE).join("slot_start=100");
+        assert_eq!(files(&partition).len(), 1);
+    }
+
+    #[test]
+    fn test_sink_keeps_rows_of_failed_writes() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = ParquetSink::new(&ParquetSinkConfig {
+            directory: directory.path().to_str().unwrap().to_string(),
+            slots_per_partition: Some(100),
+            max_rows_per_file: Some(1),
+            flush_interval_secs: Some(0),
+        })
+        .unwrap();
+        File::create(directory.path().join(EVENTS_TABLE)).unwrap();
+
+        // Failing the batch would have it retried and buffered twice.
+        sink.write_events(&[event(150), event(250)]).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(sink.buffered_slot(), Some(150));
+        assert_eq!(sink.writer.events.values().map(Vec::len).sum::<usize>(), 2);
+
+        fs::remove_file(directory.path().join(EVENTS_TABLE)).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(sink.buffered_slot(), None);
+        assert_eq!(
+            files(&directory.path().join(EVENTS_TABLE)),
+            ["slot_start=100", "slot_start=200"]
+        );
+    }
+}
@@ -0,0 +1,368 @@
+/// A sink writing to a SQLite database file with the tables of the
+/// PostgreSQL schema: `inscriptions`, `inscription_events` and `slots`. The
+/// database runs in WAL mode; the events of a batch are committed in one
+/// transaction and slot updates are buffered until the end of the batch.
+use {
+    crate::{
+        geyser_plugin_postgres::{ConfigReport, GeyserPluginPostgresError},
+        inscription_sink::InscriptionSink,
+        postgres_client::postgres_client_transaction::{DbInscription, InscriptionEvent},
+    },
+    chrono::Utc,
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    log::*,
+    rusqlite::{params, Connection},
+    serde_derive::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+const DEFAULT_BUSY_TIMEOUT_MS: u64 = 5000;
+
+const CREATE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS inscriptions (
+    slot INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    account TEXT NOT NULL PRIMARY KEY,
+    mint_account TEXT,
+    metadata_account TEXT,
+    authority TEXT NOT NULL,
+    updated_on TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS inscription_events (
+    slot INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    instruction_type TEXT NOT NULL,
+    inscription_account TEXT,
+    metadata_account TEXT,
+    mint_account TEXT,
+    authority TEXT,
+    accounts TEXT NOT NULL,
+    updated_on TEXT NOT NULL,
+    PRIMARY KEY (slot, signature, instruction_index)
+);
+
+CREATE INDEX IF NOT EXISTS inscription_events_inscription_account
+    ON inscription_events (inscription_account);
+CREATE INDEX IF NOT EXISTS inscription_events_slot ON inscription_events (slot);
+
+CREATE TABLE IF NOT EXISTS slots (
+    slot INTEGER NOT NULL PRIMARY KEY,
+    parent INTEGER,
+    status TEXT NOT NULL,
+    updated_on TEXT NOT NULL
+);
+";
+
+/// As for PostgreSQL, a row is never replaced by one of an earlier slot, so
+/// that a batch retried or written out of order does not move it back.
+const INSCRIPTION_UPSERT_STMT: &str =
+    "INSERT INTO inscriptions (slot, signature, account, mint_account, metadata_account, \
+        authority, updated_on) \
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT (account) DO UPDATE SET \
+        slot=excluded.slot, \
+        signature=excluded.signature, \
+        mint_account=excluded.mint_account, \
+        metadata_account=excluded.metadata_account, \
+        authority=excluded.authority, \
+        updated_on=excluded.updated_on \
+    WHERE inscriptions.slot <= excluded.slot";
+
+const INSCRIPTION_EVENT_INSERT_STMT: &str =
+    "INSERT INTO inscription_events (slot, signature, instruction_index, instruction_type, \
+        inscription_account, metadata_account, mint_account, authority, accounts, updated_on) \
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) \
+    ON CONFLICT (slot, signature, instruction_index) DO NOTHING";
+
+const SLOT_UPSERT_STMT: &str = "INSERT INTO slots (slot, parent, status, updated_on) \
+    VALUES (?1, ?2, ?3, ?4) ON CONFLICT (slot) DO UPDATE SET \
+        parent=COALESCE(excluded.parent, slots.parent), \
+        status=excluded.status, \
+        updated_on=excluded.updated_on";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteSinkConfig {
+    /// The path of the database file. It is created, along with the tables,
+    /// if missing.
+    pub path: String,
+
+    /// How long, in milliseconds, to wait for a lock held by another
+    /// connection, e.g. a reader. The default is 5000.
+    pub busy_timeout_ms: Option<u64>,
+}
+
+impl SqliteSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if self.path.is_empty() {
+            report.error("\"path\" of the sqlite sink must not be empty");
+        }
+    }
+}
+
+pub struct SqliteSink {
+    path: String,
+    connection: Connection,
+    /// Slot updates waiting for the end of the batch.
+    pending_slots: Vec<(u64, Option<u64>, SlotStatus)>,
+}
+
+impl SqliteSink {
+    pub fn new(config: &SqliteSinkConfig) -> Result<Self, GeyserPluginError> {
+        let connection = Connection::open(&config.path).map_err(|err| {
+            Self::error(format!(
+                "Failed to open the SQLite database {:?}: ({})",
+                config.path, err
+            ))
+        })?;
+
+        let busy_timeout =
+            Duration::from_millis(config.busy_timeout_ms.unwrap_or(DEFAULT_BUSY_TIMEOUT_MS));
+        connection
+            .busy_timeout(busy_timeout)
+            .and_then(|()| {
+                connection.query_row("PRAGMA journal_mode = WAL", [], |row| {
+                    row.get::<_, String>(0)
+                })
+            })
+            .and_then(|_| connection.execute_batch("PRAGMA synchronous = NORMAL;"))
+            .and_then(|()| connection.execute_batch(CREATE_SCHEMA))
+            .map_err(|err| {
+                Self::error(format!(
+                    "Failed to prepare the SQLite database {:?}: ({})",
+                    config.path, err
+                ))
+            })?;
+
+        info!("Writing inscription events to SQLite {:?}", config.path);
+        Ok(Self {
+            path: config.path.clone(),
+            connection,
+            pending_slots: Vec::new(),
+        })
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+
+    fn write_events_impl(&mut self, events: &[InscriptionEvent]) -> rusqlite::Result<()> {
+        let updated_on = Utc::now().naive_utc().to_string();
+        let transaction = self.connection.transaction()?;
+        let mut insert_event = transaction.prepare_cached(INSCRIPTION_EVENT_INSERT_STMT)?;
+        let mut upsert_inscription = transaction.prepare_cached(INSCRIPTION_UPSERT_STMT)?;
+
+        for event in events {
+            let accounts = event
+                .accounts
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>();
+            insert_event.execute(params![
+                event.slot as i64,
+                event.signature.to_string(),
+                event.instruction_index,
+                event.instruction_type.as_str(),
+                event.inscription_account.map(|key| key.to_string()),
+                event.metadata_account.map(|key| key.to_string()),
+                event.mint_account.map(|key| key.to_string()),
+                event.authority.map(|key| key.to_string()),
+                serde_json::to_string(&accounts).unwrap_or_default(),
+                updated_on,
+            ])?;
+
+            if let Some(inscription_info) = DbInscription::from_event(event) {
+                upsert_inscription.execute(params![
+                    inscription_info.slot,
+                    inscription_info.signature,
+                    inscription_info.account,
+                    inscription_info.mint_account,
+                    inscription_info.metadata_account,
+                    inscription_info.authority,
+                    updated_on,
+                ])?;
+            }
+        }
+        drop(insert_event);
+        drop(upsert_inscription);
+        transaction.commit()
+    }
+
+    fn write_slots(&mut self, slots: &[(u64, Option<u64>, SlotStatus)]) -> rusqlite::Result<()> {
+        let updated_on = Utc::now().naive_utc().to_string();
+        let transaction = self.connection.transaction()?;
+        let mut upsert_slot = transaction.prepare_cached(SLOT_UPSERT_STMT)?;
+        for (slot, parent, status) in slots {
+            upsert_slot.execute(params![
+                *slot as i64,
+                parent.map(|parent| parent as i64),
+                status.as_str(),
+                updated_on,
+            ])?;
+        }
+        drop(upsert_slot);
+        transaction.commit()
+    }
+}
+
+impl InscriptionSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        self.write_events_impl(events).map_err(|err| {
+            Self::error(format!(
+                "Failed to write {} events to the SQLite database {:?}: ({})",
+                events.len(),
+                self.path,
+                err
+            ))
+        })
+    }
+
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        self.pending_slots.push((slot, parent, *status));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        if self.pending_slots.is_empty() {
+            return Ok(());
+        }
+        let slots = std::mem::take(&mut self.pending_slots);
+        self.write_slots(&slots).map_err(|err| {
+            Self::error(format!(
+                "Failed to update {} slots in the SQLite database {:?}: ({})",
+                slots.len(),
+                self.path,
+                err
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::postgres_client::postgres_client_transaction::InscriptionInstructionType,
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+    };
+
+    fn sink(directory: &tempfile::TempDir) -> SqliteSink {
+        SqliteSink::new(&SqliteSinkConfig {
+            path: directory
+                .path()
+                .join("inscriptions.db")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            busy_timeout_ms: None,
+        })
+        .unwrap()
+    }
+
+    fn initialize(slot: u64, account: Pubkey, authority: Pubkey) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::new_unique(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::Initialize,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(account),
+            metadata_account: Some(Pubkey::new_unique()),
+            mint_account: None,
+            authority: Some(authority),
+            accounts: vec![account, authority],
+        }
+    }
+
+    fn inscription(sink: &SqliteSink, account: &Pubkey) -> (i64, String, String) {
+        sink.connection
+            .query_row(
+                "SELECT slot, signature, authority FROM inscriptions WHERE account = ?1",
+                [account.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap()
+    }
+
+    fn count(sink: &SqliteSink, table: &str) -> i64 {
+        sink.connection
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_inscription_never_moves_back() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory);
+        let account = Pubkey::new_unique();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let later = initialize(20, account, second);
+        sink.write_events(&[later.clone()]).unwrap();
+        // An earlier event written after, e.g. by a retried batch.
+        sink.write_events(&[initialize(10, account, first)])
+            .unwrap();
+        assert_eq!(
+            inscription(&sink, &account),
+            (20, later.signature.to_string(), second.to_string())
+        );
+
+        let latest = initialize(30, account, first);
+        sink.write_events(&[latest.clone()]).unwrap();
+        assert_eq!(
+            inscription(&sink, &account),
+            (30, latest.signature.to_string(), first.to_string())
+        );
+        assert_eq!(count(&sink, "inscription_events"), 3);
+    }
+
+    #[test]
+    fn test_events_written_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory);
+        let event = initialize(10, Pubkey::new_unique(), Pubkey::new_unique());
+        sink.write_events(&[event.clone()]).unwrap();
+        sink.write_events(&[event.clone()]).unwrap();
+        assert_eq!(count(&sink, "inscription_events"), 1);
+
+        // Keyed by slot as in PostgreSQL, where the slot is the partition key.
+        let replayed = InscriptionEvent { slot: 11, ..event };
+        sink.write_events(&[replayed]).unwrap();
+        assert_eq!(count(&sink, "inscription_events"), 2);
+    }
+
+    #[test]
+    fn test_slots_written_on_flush() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(&directory);
+        sink.update_slot_status(10, Some(9), &SlotStatus::Processed)
+            .unwrap();
+        sink.update_slot_status(10, None, &SlotStatus::Rooted)
+            .unwrap();
+        assert_eq!(count(&sink, "slots"), 0);
+
+        sink.flush().unwrap();
+        let (parent, status): (Option<i64>, String) = sink
+            .connection
+            .query_row(
+                "SELECT parent, status FROM slots WHERE slot = 10",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(parent, Some(9));
+        assert_eq!(status, SlotStatus::Rooted.as_str());
+    }
+}
@@ -0,0 +1,1046 @@
+/// A sink posting the events to HTTP endpoints. Each endpoint receives the
+/// events of a batch matching its filters as one JSON payload, optionally
+/// signed with HMAC-SHA256. Every endpoint has its own queue and delivery
+/// thread, so a slow endpoint neither holds up the workers nor the other
+/// endpoints: once its queue is full, payloads are spilled to
+/// `retry_queue_dir`, or dropped without it. Failed deliveries are retried
+/// with exponential backoff and, when `retry_queue_dir` is set, survive
+/// restarts.
+use {
+    crate::{
+        geyser_plugin_postgres::{ConfigReport, GeyserPluginPostgresError},
+        inscription_sink::{EventFilter, EventFilterConfig, InscriptionSink, JsonInscriptionEvent},
+        metrics::PluginMetrics,
+        postgres_client::postgres_client_transaction::{
+            InscriptionEvent, InscriptionInstructionType,
+        },
+    },
+    crossbeam_channel::{bounded, unbounded, Receiver, Sender, TryRecvError, TrySendError},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    hmac::{Hmac, Mac},
+    log::*,
+    reqwest::{blocking::Client, Url},
+    serde_derive::{Deserialize, Serialize},
+    sha2::Sha256,
+    std::{
+        fmt::Write,
+        fs,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 300_000;
+/// Payloads of one endpoint waiting in memory for their first attempt.
+/// Beyond it they are spilled to the retry queue directory, or dropped.
+const MAX_PENDING_DELIVERIES: usize = 10_000;
+const DELIVERY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const SIGNATURE_HEADER: &str = "X-Inscription-Signature";
+const TIMESTAMP_HEADER: &str = "X-Inscription-Timestamp";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpointConfig {
+    /// The URL the payloads are posted to.
+    pub url: String,
+
+    /// The key payloads are signed with. Payloads are not signed when this
+    /// is not set.
+    pub secret: Option<String>,
+
+    /// Only deliver events of these programs. All events by default.
+    pub program_ids: Option<Vec<String>>,
+
+    /// Only deliver events with one of these authorities. All events by
+    /// default.
+    pub authorities: Option<Vec<String>>,
+
+    /// Only deliver events with one of these mints. All events by default.
+    pub mints: Option<Vec<String>>,
+
+    /// Only deliver events of these inscription accounts. All events by
+    /// default.
+    pub accounts: Option<Vec<String>>,
+
+    /// Only deliver events of these instruction types, e.g.
+    /// `["initialize", "close"]`. All events by default.
+    pub event_types: Option<Vec<InscriptionInstructionType>>,
+}
+
+impl WebhookEndpointConfig {
+    /// Check the URL and parse the filter of the endpoint.
+    fn filter(&self) -> Result<EventFilter, String> {
+        if let Err(err) = Url::parse(&self.url) {
+            return Err(format!(
+                "The webhook \"url\": {:?} is not valid: ({})",
+                self.url, err
+            ));
+        }
+        EventFilter::new(&EventFilterConfig {
+            program_ids: self.program_ids.clone(),
+            authorities: self.authorities.clone(),
+            mints: self.mints.clone(),
+            accounts: self.accounts.clone(),
+            event_types: self.event_types.clone(),
+        })
+        .map_err(|err| format!("The webhook {:?}: {}", self.url, err))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookSinkConfig {
+    pub endpoints: Vec<WebhookEndpointConfig>,
+
+    /// The timeout, in milliseconds, of one delivery attempt. The default
+    /// is 5000.
+    pub timeout_ms: Option<u64>,
+
+    /// The number of attempts after which a payload is dropped. The default
+    /// is 10.
+    pub max_attempts: Option<u32>,
+
+    /// The delay, in milliseconds, before the first retry. It doubles with
+    /// every attempt. The default is 1000.
+    pub initial_backoff_ms: Option<u64>,
+
+    /// The maximum delay, in milliseconds, between two attempts. The
+    /// default is 300000.
+    pub max_backoff_ms: Option<u64>,
+
+    /// The directory payloads waiting for a retry are kept in, so they are
+    /// retried after a restart, along with the payloads of an endpoint
+    /// whose queue is full. They are only kept in memory when this is not
+    /// set, and the payloads of a full queue are dropped.
+    pub retry_queue_dir: Option<String>,
+}
+
+impl WebhookSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if self.endpoints.is_empty() {
+            report.error("\"endpoints\" of the webhook sink must not be empty");
+        }
+        for endpoint in self.endpoints.iter() {
+            if let Err(msg) = endpoint.filter() {
+                report.error(msg);
+            }
+        }
+        if self.timeout_ms == Some(0) {
+            report.error("\"timeout_ms\" of the webhook sink must be greater than 0");
+        }
+        if self.max_attempts == Some(0) {
+            report.error("\"max_attempts\" of the webhook sink must be greater than 0");
+        }
+        if self
+            .initial_backoff_ms
+            .unwrap_or(DEFAULT_INITIAL_BACKOFF_MS)
+            > self.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS)
+        {
+            report.warning(
+                "\"initial_backoff_ms\" of the webhook sink is greater than \"max_backoff_ms\"",
+            );
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    events: &'a [JsonInscriptionEvent],
+}
+
+/// One payload for one endpoint, as kept in the retry queue.
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    body: String,
+    attempts: u32,
+}
+
+struct ScheduledDelivery {
+    delivery: Delivery,
+    due: Instant,
+    /// The file of the delivery in the retry queue directory.
+    path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff_ms: u64,
+    max_backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    /// The delay before the attempt following `attempts` failed ones.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u64 << attempts.saturating_sub(1).min(32);
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+/// The directory payloads are persisted in, shared by the sink and the
+/// delivery threads.
+#[derive(Clone)]
+struct RetryQueueDir {
+    dir: PathBuf,
+    /// Distinguishes the files created within the same millisecond.
+    sequence: Arc<AtomicU64>,
+}
+
+impl RetryQueueDir {
+    fn read(path: &Path) -> Result<Delivery, String> {
+        fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| {
+                serde_json::from_slice::<Delivery>(&data).map_err(|err| err.to_string())
+            })
+    }
+
+    /// Read the payloads left by a previous run, with their file. Those of
+    /// endpoints no longer configured, and unreadable ones, are removed.
+    fn load(&self, is_configured: impl Fn(&str) -> bool) -> Vec<(Delivery, PathBuf)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!(
+                    "Failed to read the webhook retry queue {:?}: ({})",
+                    self.dir, err
+                );
+                return Vec::new();
+            }
+        };
+
+        let mut deliveries = Vec::new();
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if path.extension().map(|ext| ext != "json").unwrap_or(true) {
+                continue;
+            }
+            match Self::read(&path) {
+                Ok(delivery) if is_configured(&delivery.url) => deliveries.push((delivery, path)),
+                Ok(delivery) => {
+                    warn!(
+                        "Dropping the queued webhook payload {:?} for {:?}, which is no longer configured",
+                        path, delivery.url
+                    );
+                    let _ = fs::remove_file(&path);
+                }
+                Err(err) => {
+                    error!(
+                        "Dropping the unreadable webhook payload {:?}: ({})",
+                        path, err
+                    );
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        if !deliveries.is_empty() {
+            info!(
+                "Loaded {} webhook payloads from the retry queue",
+                deliveries.len()
+            );
+        }
+        deliveries
+    }
+
+    /// Write the delivery to `path`, or to a new file. Returns the path of
+    /// the file.
+    fn persist(&self, delivery: &Delivery, path: Option<PathBuf>) -> Option<PathBuf> {
+        let path = path.unwrap_or_else(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+            self.dir.join(format!("{}-{}.json", now, sequence))
+        });
+        let tmp_path = path.with_extension("tmp");
+        let result = serde_json::to_vec(delivery)
+            .map_err(|err| err.to_string())
+            .and_then(|data| fs::write(&tmp_path, data).map_err(|err| err.to_string()))
+            .and_then(|()| fs::rename(&tmp_path, &path).map_err(|err| err.to_string()));
+        match result {
+            Ok(()) => Some(path),
+            Err(err) => {
+                error!(
+                    "Failed to write the webhook payload to the retry queue {:?}: ({})",
+                    path, err
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Runs on the delivery thread of one endpoint: posts new payloads and
+/// retries failed ones.
+struct Deliverer {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+    retry_policy: RetryPolicy,
+    retry_queue_dir: Option<RetryQueueDir>,
+    retry_queue: Vec<ScheduledDelivery>,
+    metrics: Arc<PluginMetrics>,
+    /// The share of `retry_queue_depth` added by this deliverer.
+    reported_queue_depth: u64,
+}
+
+impl Deliverer {
+    fn update_queue_depth(&mut self) {
+        let depth = self.retry_queue.len() as u64;
+        let retry_queue_depth = &self.metrics.webhook_deliveries.retry_queue_depth;
+        if depth > self.reported_queue_depth {
+            retry_queue_depth.fetch_add(depth - self.reported_queue_depth, Ordering::Relaxed);
+        } else {
+            retry_queue_depth.fetch_sub(self.reported_queue_depth - depth, Ordering::Relaxed);
+        }
+        self.reported_queue_depth = depth;
+    }
+
+    fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        let mut signature = String::from("sha256=");
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(signature, "{:02x}", byte);
+        }
+        signature
+    }
+
+    fn post(&self, delivery: &Delivery) -> Result<(), String> {
+        let mut request = self
+            .client
+            .post(&delivery.url)
+            .header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            request = request.header(TIMESTAMP_HEADER, timestamp).header(
+                SIGNATURE_HEADER,
+                Self::sign(secret, timestamp, &delivery.body),
+            );
+        }
+
+        let response = request
+            .body(delivery.body.clone())
+            .send()
+            .map_err(|err| err.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("the endpoint answered {}", response.status()))
+        }
+    }
+
+    fn attempt(&mut self, mut delivery: Delivery, path: Option<PathBuf>) {
+        let result = self.post(&delivery);
+        delivery.attempts += 1;
+        match result {
+            Ok(()) => {
+                self.metrics
+                    .webhook_deliveries
+                    .delivered
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(path) = path {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            Err(err) if delivery.attempts >= self.retry_policy.max_attempts => {
+                error!(
+                    "Dropping the webhook payload for {:?} after {} attempts: ({})",
+                    delivery.url, delivery.attempts, err
+                );
+                self.metrics
+                    .webhook_deliveries
+                    .dropped
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(path) = path {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            Err(err) => {
+                let backoff = self.retry_policy.backoff(delivery.attempts);
+                warn!(
+                    "Failed to deliver the webhook payload to {:?}, attempt {}, retrying in {:?}: ({})",
+                    delivery.url, delivery.attempts, backoff, err
+                );
+                self.metrics
+                    .webhook_deliveries
+                    .retried
+                    .fetch_add(1, Ordering::Relaxed);
+                self.schedule(delivery, Instant::now() + backoff, path);
+            }
+        }
+    }
+
+    fn schedule(&mut self, delivery: Delivery, due: Instant, path: Option<PathBuf>) {
+        let path = self
+            .retry_queue_dir
+            .as_ref()
+            .and_then(|retry_queue_dir| retry_queue_dir.persist(&delivery, path));
+        self.retry_queue.push(ScheduledDelivery {
+            delivery,
+            due,
+            path,
+        });
+        self.update_queue_depth();
+    }
+
+    fn retry_due(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.retry_queue)
+            .into_iter()
+            .partition::<Vec<_>, _>(|scheduled| scheduled.due <= now);
+        self.retry_queue = pending;
+        for scheduled in due {
+            self.attempt(scheduled.delivery, scheduled.path);
+        }
+        self.update_queue_depth();
+    }
+
+    /// The next payload to attempt: a queued one, else one spilled to the
+    /// retry queue directory, waiting up to `DELIVERY_POLL_INTERVAL` for
+    /// one to be queued.
+    fn next_delivery(
+        receiver: &Receiver<Delivery>,
+        spilled: &Receiver<PathBuf>,
+    ) -> Option<(Delivery, Option<PathBuf>)> {
+        match receiver.try_recv() {
+            Ok(delivery) => return Some((delivery, None)),
+            Err(TryRecvError::Disconnected) => return None,
+            Err(TryRecvError::Empty) => {}
+        }
+        while let Ok(path) = spilled.try_recv() {
+            match RetryQueueDir::read(&path) {
+                Ok(delivery) => return Some((delivery, Some(path))),
+                Err(err) => error!(
+                    "Dropping the unreadable webhook payload {:?}: ({})",
+                    path, err
+                ),
+            }
+        }
+        receiver
+            .recv_timeout(DELIVERY_POLL_INTERVAL)
+            .ok()
+            .map(|delivery| (delivery, None))
+    }
+
+    fn run(
+        mut self,
+        receiver: Receiver<Delivery>,
+        spilled: Receiver<PathBuf>,
+        exit: Arc<AtomicBool>,
+    ) {
+        self.update_queue_depth();
+        while !exit.load(Ordering::Relaxed) {
+            if let Some((delivery, path)) = Self::next_delivery(&receiver, &spilled) {
+                self.attempt(delivery, path);
+            }
+            self.retry_due();
+        }
+
+        // Keep what was not attempted yet for the next run. Spilled payloads
+        // are already in the directory.
+        if let Some(retry_queue_dir) = &self.retry_queue_dir {
+            for delivery in receiver.try_iter() {
+                retry_queue_dir.persist(&delivery, None);
+            }
+        }
+        self.retry_queue.clear();
+        self.update_queue_depth();
+    }
+}
+
+/// An endpoint, with the queue of its delivery thread.
+struct Endpoint {
+    url: String,
+    filter: EventFilter,
+    sender: Sender<Delivery>,
+    /// The files of the payloads spilled to the retry queue directory when
+    /// the queue was full.
+    spilled: Sender<PathBuf>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub struct WebhookSink {
+    endpoints: Vec<Endpoint>,
+    retry_queue_dir: Option<RetryQueueDir>,
+    metrics: Arc<PluginMetrics>,
+    exit: Arc<AtomicBool>,
+}
+
+impl WebhookSink {
+    pub fn new(
+        config: &WebhookSinkConfig,
+        metrics: Arc<PluginMetrics>,
+    ) -> Result<Self, GeyserPluginError> {
+        Self::with_queue_capacity(config, metrics, MAX_PENDING_DELIVERIES)
+    }
+
+    fn with_queue_capacity(
+        config: &WebhookSinkConfig,
+        metrics: Arc<PluginMetrics>,
+        queue_capacity: usize,
+    ) -> Result<Self, GeyserPluginError> {
+        if config.endpoints.is_empty() {
+            return Err(Self::config_error(
+                "\"endpoints\" of the webhook sink must not be empty".to_string(),
+            ));
+        }
+        let filters = config
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.filter().map_err(Self::config_error))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let retry_queue_dir = match &config.retry_queue_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir).map_err(|err| {
+                    Self::error(format!(
+                        "Failed to create the webhook retry queue {:?}: ({})",
+                        dir, err
+                    ))
+                })?;
+                Some(RetryQueueDir {
+                    dir: PathBuf::from(dir),
+                    sequence: Arc::default(),
+                })
+            }
+            None => None,
+        };
+        // The payloads of an URL configured twice go to its first endpoint.
+        let mut queued = vec![Vec::new(); config.endpoints.len()];
+        if let Some(retry_queue_dir) = &retry_queue_dir {
+            let now = Instant::now();
+            for (delivery, path) in retry_queue_dir
+                .load(|url| config.endpoints.iter().any(|endpoint| endpoint.url == url))
+            {
+                let index = config
+                    .endpoints
+                    .iter()
+                    .position(|endpoint| endpoint.url == delivery.url)
+                    .unwrap_or_default();
+                queued[index].push(ScheduledDelivery {
+                    delivery,
+                    due: now,
+                    path: Some(path),
+                });
+            }
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_millis(
+                config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS),
+            ))
+            .build()
+            .map_err(|err| Self::error(format!("Failed to create the HTTP client: ({})", err)))?;
+        let retry_policy = RetryPolicy {
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            initial_backoff_ms: config
+                .initial_backoff_ms
+                .unwrap_or(DEFAULT_INITIAL_BACKOFF_MS),
+            max_backoff_ms: config.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS),
+        };
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let mut sink = Self {
+            endpoints: Vec::with_capacity(config.endpoints.len()),
+            retry_queue_dir: retry_queue_dir.clone(),
+            metrics: metrics.clone(),
+            exit: exit.clone(),
+        };
+        for (i, ((endpoint, filter), retry_queue)) in
+            config.endpoints.iter().zip(filters).zip(queued).enumerate()
+        {
+            let deliverer = Deliverer {
+                client: client.clone(),
+                url: endpoint.url.clone(),
+                secret: endpoint.secret.clone(),
+                retry_policy,
+                retry_queue_dir: retry_queue_dir.clone(),
+                retry_queue,
+                metrics: metrics.clone(),
+                reported_queue_depth: 0,
+            };
+            let (sender, receiver) = bounded(queue_capacity);
+            let (spilled_sender, spilled_receiver) = unbounded();
+            let exit = exit.clone();
+            // Dropping the sink on error stops the threads already started.
+            let thread = Builder::new()
+                .name(format!("webhook-{}", i))
+                .spawn(move || deliverer.run(receiver, spilled_receiver, exit))
+                .map_err(|err| {
+                    Self::error(format!(
+                        "Failed to start the webhook delivery thread: ({})",
+                        err
+                    ))
+                })?;
+            sink.endpoints.push(Endpoint {
+                url: endpoint.url.clone(),
+                filter,
+                sender,
+                spilled: spilled_sender,
+                thread: Some(thread),
+            });
+        }
+
+        info!(
+            "Delivering inscription events to {} webhooks",
+            sink.endpoints.len()
+        );
+        Ok(sink)
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+
+    fn config_error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+            msg,
+        }))
+    }
+}
+
+impl InscriptionSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    /// Hand the payloads over to the delivery threads, without waiting for
+    /// them. Failing deliveries are retried there and never fail the batch.
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        for endpoint in self.endpoints.iter() {
+            let matching = events
+                .iter()
+                .filter(|event| endpoint.filter.matches(event))
+                .map(JsonInscriptionEvent::from)
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                continue;
+            }
+
+            let body = serde_json::to_string(&WebhookPayload { events: &matching })
+                .map_err(|err| Self::error(format!("Failed to serialize the events: ({})", err)))?;
+            let delivery = Delivery {
+                url: endpoint.url.clone(),
+                body,
+                attempts: 0,
+            };
+            let delivery = match endpoint.sender.try_send(delivery) {
+                Ok(()) => continue,
+                Err(TrySendError::Full(delivery)) => delivery,
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(Self::error(
+                        "The webhook delivery thread has exited".to_string(),
+                    ));
+                }
+            };
+
+            // The endpoint is not keeping up: keep the payload on disk for
+            // its thread to pick up once its queue drains, or lose it.
+            let path = self
+                .retry_queue_dir
+                .as_ref()
+                .and_then(|retry_queue_dir| retry_queue_dir.persist(&delivery, None));
+            match path {
+                Some(path) if endpoint.spilled.send(path).is_ok() => {
+                    self.metrics
+                        .webhook_deliveries
+                        .spilled
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {
+                    warn!(
+                        "The webhook delivery queue of {:?} is full, dropping a payload of {} events",
+                        endpoint.url,
+                        matching.len()
+                    );
+                    self.metrics
+                        .webhook_deliveries
+                        .dropped
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        for endpoint in self.endpoints.iter_mut() {
+            if let Some(thread) = endpoint.thread.take() {
+                if thread.join().is_err() {
+                    error!(
+                        "The webhook delivery thread of {:?} has panicked",
+                        endpoint.url
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+        std::{
+            collections::HashMap,
+            io::{BufRead, BufReader, Read, Write as _},
+            net::{Ipv4Addr, SocketAddr, TcpListener},
+            thread::sleep,
+        },
+    };
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    struct Request {
+        headers: HashMap<String, String>,
+        body: String,
+        received: Instant,
+    }
+
+    fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    /// Answer the requests with `statuses` in turn, then with 200, and pass
+    /// them on to the returned receiver.
+    fn serve(listener: TcpListener, statuses: Vec<u16>) -> Receiver<Request> {
+        let (sender, receiver) = unbounded();
+        Builder::new()
+            .name("webhook-test".to_string())
+            .spawn(move || {
+                let mut statuses = statuses.into_iter();
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let mut headers = HashMap::new();
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        match line.trim_end().split_once(": ") {
+                            Some((name, value)) => {
+                                headers.insert(name.to_lowercase(), value.to_string())
+                            }
+                            None => break,
+                        };
+                    }
+                    let length = headers["content-length"].parse().unwrap();
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+
+                    let request = Request {
+                        headers,
+                        body: String::from_utf8(body).unwrap(),
+                        received: Instant::now(),
+                    };
+                    let status = statuses.next().unwrap_or(200);
+                    write!(
+                        stream,
+                        "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .unwrap();
+                    if sender.send(request).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        receiver
+    }
+
+    fn config(url: &str, secret: Option<&str>) -> WebhookSinkConfig {
+        WebhookSinkConfig {
+            endpoints: vec![WebhookEndpointConfig {
+                url: url.to_string(),
+                secret: secret.map(str::to_string),
+                program_ids: None,
+                authorities: None,
+                mints: None,
+                accounts: None,
+                event_types: None,
+            }],
+            timeout_ms: None,
+            max_attempts: None,
+            initial_backoff_ms: None,
+            max_backoff_ms: None,
+            retry_queue_dir: None,
+        }
+    }
+
+    fn events() -> Vec<InscriptionEvent> {
+        vec![InscriptionEvent {
+            slot: 1,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: Some(Pubkey::new_unique()),
+            accounts: vec![],
+        }]
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < TEST_TIMEOUT, "timed out");
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn queued_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .collect()
+    }
+
+    #[test]
+    fn test_signed_delivery() {
+        let (signed_listener, signed_url) = listen();
+        let (unsigned_listener, unsigned_url) = listen();
+        let signed = serve(signed_listener, vec![]);
+        let unsigned = serve(unsigned_listener, vec![]);
+        let mut config = config(&signed_url, Some("secret"));
+        config
+            .endpoints
+            .extend(self::config(&unsigned_url, None).endpoints);
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let mut sink = WebhookSink::new(&config, metrics.clone()).unwrap();
+
+        let events = events();
+        sink.write_events(&events).unwrap();
+
+        let request = signed.recv_timeout(TEST_TIMEOUT).unwrap();
+        let timestamp: u64 = request.headers[&TIMESTAMP_HEADER.to_lowercase()]
+            .parse()
+            .unwrap();
+        assert_eq!(
+            request.headers[&SIGNATURE_HEADER.to_lowercase()],
+            Deliverer::sign("secret", timestamp, &request.body)
+        );
+        assert_ne!(
+            request.headers[&SIGNATURE_HEADER.to_lowercase()],
+            Deliverer::sign("other secret", timestamp, &request.body)
+        );
+        assert_eq!(request.headers["content-type"], "application/json");
+        let payload: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(
+            payload["events"][0]["authority"],
+            events[0].authority.unwrap().to_string()
+        );
+
+        let request = unsigned.recv_timeout(TEST_TIMEOUT).unwrap();
+        assert!(!request
+            .headers
+            .contains_key(&SIGNATURE_HEADER.to_lowercase()));
+        assert!(!request
+            .headers
+            .contains_key(&TIMESTAMP_HEADER.to_lowercase()));
+
+        wait_for(|| metrics.webhook_deliveries.delivered.load(Ordering::Relaxed) == 2);
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let (listener, url) = listen();
+        let requests = serve(listener, vec![500, 503]);
+        let mut config = config(&url, None);
+        config.initial_backoff_ms = Some(100);
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let mut sink = WebhookSink::new(&config, metrics.clone()).unwrap();
+
+        sink.write_events(&events()).unwrap();
+        let first = requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        let second = requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        let third = requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        assert_eq!(first.body, second.body);
+        assert_eq!(first.body, third.body);
+        // The delay doubles with every attempt.
+        assert!(second.received - first.received >= Duration::from_millis(100));
+        assert!(third.received - second.received >= Duration::from_millis(200));
+
+        wait_for(|| metrics.webhook_deliveries.delivered.load(Ordering::Relaxed) == 1);
+        assert_eq!(
+            metrics.webhook_deliveries.retried.load(Ordering::Relaxed),
+            2
+        );
+        assert_eq!(
+            metrics
+                .webhook_deliveries
+                .retry_queue_depth
+                .load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_give_up_after_max_attempts() {
+        let (listener, url) = listen();
+        let requests = serve(listener, vec![500, 500]);
+        let mut config = config(&url, None);
+        config.initial_backoff_ms = Some(10);
+        config.max_attempts = Some(2);
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let mut sink = WebhookSink::new(&config, metrics.clone()).unwrap();
+
+        sink.write_events(&events()).unwrap();
+        requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        wait_for(|| metrics.webhook_deliveries.dropped.load(Ordering::Relaxed) == 1);
+        assert_eq!(
+            metrics.webhook_deliveries.delivered.load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_retry_queue_survives_restart() {
+        let retry_queue_dir = tempfile::tempdir().unwrap();
+        let (listener, url) = listen();
+        let requests = serve(listener, vec![503]);
+        let mut config = config(&url, None);
+        config.initial_backoff_ms = Some(3_600_000);
+        config.max_backoff_ms = Some(3_600_000);
+        config.retry_queue_dir = Some(retry_queue_dir.path().to_str().unwrap().to_string());
+
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let mut sink = WebhookSink::new(&config, metrics.clone()).unwrap();
+        sink.write_events(&events()).unwrap();
+        let failed = requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        wait_for(|| {
+            metrics
+                .webhook_deliveries
+                .retry_queue_depth
+                .load(Ordering::Relaxed)
+                == 1
+        });
+        drop(sink);
+        assert_eq!(
+            metrics
+                .webhook_deliveries
+                .retry_queue_depth
+                .load(Ordering::Relaxed),
+            0
+        );
+
+        let files = queued_files(retry_queue_dir.path());
+        assert_eq!(files.len(), 1);
+        let delivery = RetryQueueDir::read(&files[0]).unwrap();
+        assert_eq!(delivery.url, url);
+        assert_eq!(delivery.body, failed.body);
+        assert_eq!(delivery.attempts, 1);
+
+        // The payload is retried right away after the restart, and removed
+        // from the directory once delivered.
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let _sink = WebhookSink::new(&config, metrics.clone()).unwrap();
+        let retried = requests.recv_timeout(TEST_TIMEOUT).unwrap();
+        assert_eq!(retried.body, failed.body);
+        wait_for(|| metrics.webhook_deliveries.delivered.load(Ordering::Relaxed) == 1);
+        assert!(queued_files(retry_queue_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_retry_queue_drops_unconfigured_endpoints() {
+        let retry_queue_dir = tempfile::tempdir().unwrap();
+        let retry_queue = RetryQueueDir {
+            dir: retry_queue_dir.path().to_path_buf(),
+            sequence: Arc::default(),
+        };
+        let delivery = |url: &str| Delivery {
+            url: url.to_string(),
+            body: "{}".to_string(),
+            attempts: 1,
+        };
+        retry_queue.persist(&delivery("http://127.0.0.1:1/kept"), None);
+        retry_queue.persist(&delivery("http://127.0.0.1:1/removed"), None);
+        fs::write(retry_queue_dir.path().join("garbage.json"), "not json").unwrap();
+
+        let loaded = retry_queue.load(|url| url.ends_with("/kept"));
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0.url, "http://127.0.0.1:1/kept");
+        assert_eq!(
+            queued_files(retry_queue_dir.path()),
+            vec![loaded[0].1.clone()]
+        );
+    }
+
+    /// A listener which never answers keeps the delivery thread busy until
+    /// `timeout_ms`, so the queue of capacity 1 fills up.
+    fn write_to_full_queue(retry_queue_dir: Option<&Path>) -> Arc<PluginMetrics> {
+        let (_listener, url) = listen();
+        let mut config = config(&url, None);
+        config.timeout_ms = Some(1000);
+        config.retry_queue_dir = retry_queue_dir.map(|dir| dir.to_str().unwrap().to_string());
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let mut sink = WebhookSink::with_queue_capacity(&config, metrics.clone(), 1).unwrap();
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            sink.write_events(&events()).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+        metrics
+    }
+
+    #[test]
+    fn test_full_queue_drops_without_retry_queue() {
+        let metrics = write_to_full_queue(None);
+        assert!(metrics.webhook_deliveries.dropped.load(Ordering::Relaxed) >= 3);
+        assert_eq!(
+            metrics.webhook_deliveries.spilled.load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_full_queue_spills_to_retry_queue() {
+        let retry_queue_dir = tempfile::tempdir().unwrap();
+        let metrics = write_to_full_queue(Some(retry_queue_dir.path()));
+        let spilled = metrics.webhook_deliveries.spilled.load(Ordering::Relaxed);
+        assert!(spilled >= 3);
+        assert_eq!(
+            metrics.webhook_deliveries.dropped.load(Ordering::Relaxed),
+            0
+        );
+        // Every payload is kept for the next run: the spilled ones, the
+        // queued one and the one which timed out.
+        assert_eq!(queued_files(retry_queue_dir.path()).len(), 5);
+    }
+}
@@ -0,0 +1,597 @@
+/// A sink streaming the events to WebSocket clients. A client subscribes
+/// with filters and a commitment: "processed" events are sent as soon as
+/// they are decoded, "rooted" events once their slot is rooted. Events of
+/// slots that are never rooted are dropped. Every connection is served by
+/// its own thread from a bounded queue, and a client falling too far behind
+/// is disconnected rather than holding up the workers.
+use {
+    crate::{
+        geyser_plugin_postgres::{ConfigReport, GeyserPluginPostgresError},
+        inscription_sink::{
+            Commitment, EventFilter, EventFilterConfig, InscriptionSink, JsonInscriptionEvent,
+            RootedEvents,
+        },
+        postgres_client::postgres_client_transaction::InscriptionEvent,
+    },
+    crossbeam_channel::{bounded, Receiver, Sender, TryRecvError, TrySendError},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        io,
+        net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message, WebSocket,
+    },
+};
+
+const DEFAULT_MAX_CONNECTIONS: usize = 100;
+const DEFAULT_MAX_QUEUED_MESSAGES: usize = 10_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebsocketSinkConfig {
+    /// The address the server listens on, e.g. "0.0.0.0:8900".
+    pub listen_addr: String,
+
+    /// The maximum number of simultaneous connections. The default is 100.
+    pub max_connections: Option<usize>,
+
+    /// The number of messages queued for a connection before it is closed
+    /// as too slow. The default is 10000.
+    pub max_queued_messages: Option<usize>,
+}
+
+impl WebsocketSinkConfig {
+    pub(crate) fn validate(&self, report: &mut ConfigReport) {
+        if let Err(err) = self.listen_addr.to_socket_addrs() {
+            report.error(format!(
+                "\"listen_addr\" of the websocket sink: {:?} is not a valid address: ({})",
+                self.listen_addr, err
+            ));
+        }
+        if self.max_connections == Some(0) {
+            report.error("\"max_connections\" of the websocket sink must be greater than 0");
+        }
+        if self.max_queued_messages == Some(0) {
+            report.error("\"max_queued_messages\" of the websocket sink must be greater than 0");
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum ClientRequest {
+    Subscribe(SubscribeParams),
+    Unsubscribe,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    #[serde(default)]
+    commitment: Commitment,
+    #[serde(flatten)]
+    filter: EventFilterConfig,
+}
+
+#[derive(Serialize)]
+struct EventMessage<'a> {
+    commitment: Commitment,
+    event: &'a JsonInscriptionEvent,
+}
+
+struct Subscriber {
+    /// Set once the client has subscribed.
+    subscription: Option<(Commitment, EventFilter)>,
+    sender: Sender<String>,
+}
+
+type Subscribers = Arc<Mutex<HashMap<u64, Subscriber>>>;
+
+pub struct WebsocketSink {
+    local_addr: SocketAddr,
+    subscribers: Subscribers,
+    rooted_events: RootedEvents,
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WebsocketSink {
+    pub fn new(config: &WebsocketSinkConfig) -> Result<Self, GeyserPluginError> {
+        let listener = TcpListener::bind(&config.listen_addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+            .and_then(|listener| listener.local_addr().map(|addr| (listener, addr)));
+        let (listener, local_addr) = listener.map_err(|err| {
+            Self::error(format!(
+                "Failed to listen for WebSocket connections on {:?}: ({})",
+                config.listen_addr, err
+            ))
+        })?;
+
+        let subscribers = Subscribers::default();
+        let exit = Arc::new(AtomicBool::new(false));
+        let server = WebsocketServer {
+            listener,
+            subscribers: subscribers.clone(),
+            exit: exit.clone(),
+            max_connections: config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            max_queued_messages: config
+                .max_queued_messages
+                .unwrap_or(DEFAULT_MAX_QUEUED_MESSAGES)
+                .max(1),
+        };
+        let thread = Builder::new()
+            .name("websocket-accept".to_string())
+            .spawn(move || server.run())
+            .map_err(|err| {
+                Self::error(format!("Failed to start the WebSocket server: ({})", err))
+            })?;
+
+        info!(
+            "Streaming inscription events over WebSocket on {}",
+            local_addr
+        );
+        Ok(Self {
+            local_addr,
+            subscribers,
+            rooted_events: RootedEvents::default(),
+            exit,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn error(msg: String) -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::SinkError { msg }))
+    }
+
+    /// Queue `events` to the subscribers at `commitment` whose filter they
+    /// match. Subscribers whose queue is full are disconnected.
+    fn publish<'a>(
+        &self,
+        commitment: Commitment,
+        events: impl IntoIterator<Item = &'a InscriptionEvent>,
+    ) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let mut slow_subscribers = Vec::new();
+        for event in events {
+            let mut message = None;
+            for (id, subscriber) in subscribers.iter() {
+                let matches = match &subscriber.subscription {
+                    Some((subscribed, filter)) => {
+                        *subscribed == commitment && filter.matches(event)
+                    }
+                    None => false,
+                };
+                if !matches || slow_subscribers.contains(id) {
+                    continue;
+                }
+
+                let message = message.get_or_insert_with(|| {
+                    serde_json::to_string(&EventMessage {
+                        commitment,
+                        event: &JsonInscriptionEvent::from(event),
+                    })
+                    .unwrap_or_default()
+                });
+                match subscriber.sender.try_send(message.clone()) {
+                    Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+                    Err(TrySendError::Full(_)) => slow_subscribers.push(*id),
+                }
+            }
+        }
+
+        for id in slow_subscribers {
+            warn!("Disconnecting WebSocket subscriber {} as too slow", id);
+            // Dropping the sender makes the connection thread close the
+            // connection.
+            subscribers.remove(&id);
+        }
+    }
+}
+
+impl InscriptionSink for WebsocketSink {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        self.publish(Commitment::Processed, events);
+        let rooted = self.rooted_events.push(events);
+        self.publish(Commitment::Rooted, &rooted);
+        Ok(())
+    }
+
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        _parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        if *status == SlotStatus::Rooted {
+            let rooted = self.rooted_events.root(slot);
+            self.publish(Commitment::Rooted, &rooted);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WebsocketSink {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The WebSocket server thread panicked");
+            }
+        }
+    }
+}
+
+struct WebsocketServer {
+    listener: TcpListener,
+    subscribers: Subscribers,
+    exit: Arc<AtomicBool>,
+    max_connections: usize,
+    max_queued_messages: usize,
+}
+
+impl WebsocketServer {
+    fn run(self) {
+        let mut connections: Vec<JoinHandle<()>> = Vec::new();
+        let mut next_id = 0u64;
+        while !self.exit.load(Ordering::Relaxed) {
+            connections.retain(|connection| !connection.is_finished());
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if connections.len() >= self.max_connections {
+                        warn!(
+                            "Refusing the WebSocket connection from {}: the limit of {} connections is reached",
+                            peer, self.max_connections
+                        );
+                        continue;
+                    }
+
+                    let id = next_id;
+                    next_id += 1;
+                    let (sender, receiver) = bounded(self.max_queued_messages);
+                    self.subscribers.lock().unwrap().insert(
+                        id,
+                        Subscriber {
+                            subscription: None,
+                            sender,
+                        },
+                    );
+                    let connection = Connection {
+                        id,
+                        subscribers: self.subscribers.clone(),
+                        receiver,
+                        exit: self.exit.clone(),
+                    };
+                    match Builder::new()
+                        .name(format!("websocket-{}", id))
+                        .spawn(move || connection.run(stream))
+                    {
+                        Ok(thread) => connections.push(thread),
+                        Err(err) => {
+                            error!("Failed to start a WebSocket connection thread: ({})", err);
+                            self.subscribers.lock().unwrap().remove(&id);
+                        }
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    sleep(POLL_INTERVAL);
+                }
+                Err(err) => {
+                    warn!("Error in accepting a WebSocket connection: ({})", err);
+                    sleep(POLL_INTERVAL);
+                }
+            }
+        }
+
+        for connection in connections {
+            let _ = connection.join();
+        }
+    }
+}
+
+struct Connection {
+    id: u64,
+    subscribers: Subscribers,
+    receiver: Receiver<String>,
+    exit: Arc<AtomicBool>,
+}
+
+impl Connection {
+    fn run(self, stream: TcpStream) {
+        let peer = stream.peer_addr().ok();
+        if let Err(err) = self.serve(stream) {
+            debug!("The WebSocket connection from {:?} ended: ({})", peer, err);
+        }
+        self.subscribers.lock().unwrap().remove(&self.id);
+    }
+
+    fn serve(&self, stream: TcpStream) -> Result<(), String> {
+        stream
+            .set_nonblocking(false)
+            .and_then(|()| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+            .and_then(|()| stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT)))
+            .map_err(|err| err.to_string())?;
+        let mut socket = tungstenite::accept(stream).map_err(|err| err.to_string())?;
+        // Poll the socket and the queue in turn from here on.
+        socket
+            .get_mut()
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|err| err.to_string())?;
+
+        while !self.exit.load(Ordering::Relaxed) {
+            loop {
+                match self.receiver.try_recv() {
+                    Ok(message) => socket
+                        .write(Message::Text(message))
+                        .map_err(|err| err.to_string())?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        return Self::close(&mut socket, CloseCode::Policy, "too slow");
+                    }
+                }
+            }
+            socket.flush().map_err(|err| err.to_string())?;
+
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    let reply = self.handle_request(&text);
+                    socket
+                        .send(Message::Text(reply))
+                        .map_err(|err| err.to_string())?;
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                // Pings are answered by tungstenite on the next flush.
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Self::close(&mut socket, CloseCode::Away, "shutting down")
+    }
+
+    fn close(
+        socket: &mut WebSocket<TcpStream>,
+        code: CloseCode,
+        reason: &'static str,
+    ) -> Result<(), String> {
+        socket
+            .close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            }))
+            .map_err(|err| err.to_string())?;
+        socket.flush().map_err(|err| err.to_string())
+    }
+
+    /// Apply a request and return the reply to it.
+    fn handle_request(&self, text: &str) -> String {
+        let result = serde_json::from_str::<ClientRequest>(text)
+            .map_err(|err| format!("Invalid request: ({})", err))
+            .and_then(|request| match request {
+                ClientRequest::Subscribe(params) => EventFilter::new(&params.filter)
+                    .map(|filter| (Some((params.commitment, filter)), "subscribed")),
+                ClientRequest::Unsubscribe => Ok((None, "unsubscribed")),
+            });
+
+        match result {
+            Ok((subscription, result)) => {
+                if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&self.id) {
+                    subscriber.subscription = subscription;
+                }
+                serde_json::json!({ "result": result }).to_string()
+            }
+            Err(err) => serde_json::json!({ "error": err }).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::postgres_client::postgres_client_transaction::InscriptionInstructionType,
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+    };
+
+    fn event(slot: u64, authority: Pubkey) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: Some(authority),
+            accounts: vec![],
+        }
+    }
+
+    fn sink() -> WebsocketSink {
+        WebsocketSink::new(&WebsocketSinkConfig {
+            listen_addr: "127.0.0.1:0".to_string(),
+            max_connections: None,
+            max_queued_messages: None,
+        })
+        .unwrap()
+    }
+
+    fn subscribe(
+        sink: &WebsocketSink,
+        id: u64,
+        commitment: Commitment,
+        filter: EventFilterConfig,
+        max_queued_messages: usize,
+    ) -> Receiver<String> {
+        let (sender, receiver) = bounded(max_queued_messages);
+        sink.subscribers.lock().unwrap().insert(
+            id,
+            Subscriber {
+                subscription: Some((commitment, EventFilter::new(&filter).unwrap())),
+                sender,
+            },
+        );
+        receiver
+    }
+
+    fn received_slots(receiver: &Receiver<String>) -> Vec<(String, u64)> {
+        receiver
+            .try_iter()
+            .map(|message| {
+                let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+                (
+                    message["commitment"].as_str().unwrap().to_string(),
+                    message["event"]["slot"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_publish_by_commitment_and_filter() {
+        let mut sink = sink();
+        let authority = Pubkey::new_unique();
+        let filter = EventFilterConfig {
+            authorities: Some(vec![authority.to_string()]),
+            ..EventFilterConfig::default()
+        };
+        let processed = subscribe(&sink, 0, Commitment::Processed, filter.clone(), 10);
+        let rooted = subscribe(&sink, 1, Commitment::Rooted, filter, 10);
+
+        sink.write_events(&[event(5, authority), event(5, Pubkey::new_unique())])
+            .unwrap();
+        assert_eq!(received_slots(&processed), [("processed".to_string(), 5)]);
+        assert!(received_slots(&rooted).is_empty());
+
+        sink.update_slot_status(5, None, &SlotStatus::Confirmed)
+            .unwrap();
+        assert!(received_slots(&rooted).is_empty());
+        sink.update_slot_status(5, None, &SlotStatus::Rooted)
+            .unwrap();
+        assert_eq!(received_slots(&rooted), [("rooted".to_string(), 5)]);
+
+        // Events of a slot already rooted are sent at both commitments.
+        sink.write_events(&[event(4, authority)]).unwrap();
+        assert_eq!(received_slots(&processed), [("processed".to_string(), 4)]);
+        assert_eq!(received_slots(&rooted), [("rooted".to_string(), 4)]);
+    }
+
+    #[test]
+    fn test_slow_subscriber_disconnected() {
+        let mut sink = sink();
+        let authority = Pubkey::new_unique();
+        let _slow = subscribe(
+            &sink,
+            0,
+            Commitment::Processed,
+            EventFilterConfig::default(),
+            1,
+        );
+        let fast = subscribe(
+            &sink,
+            1,
+            Commitment::Processed,
+            EventFilterConfig::default(),
+            10,
+        );
+
+        sink.write_events(&[event(1, authority), event(1, authority)])
+            .unwrap();
+        let subscribers = sink.subscribers.lock().unwrap();
+        assert!(!subscribers.contains_key(&0));
+        assert!(subscribers.contains_key(&1));
+        assert_eq!(fast.len(), 2);
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let subscribers = Subscribers::default();
+        let (sender, receiver) = bounded(1);
+        subscribers.lock().unwrap().insert(
+            7,
+            Subscriber {
+                subscription: None,
+                sender,
+            },
+        );
+        let connection = Connection {
+            id: 7,
+            subscribers: subscribers.clone(),
+            receiver,
+            exit: Arc::new(AtomicBool::new(false)),
+        };
+        let subscription = || {
+            subscribers.lock().unwrap()[&7]
+                .subscription
+                .as_ref()
+                .map(|(commitment, _)| *commitment)
+        };
+
+        let reply = connection.handle_request(&format!(
+            r#"{{"method": "subscribe", "params": {{"commitment": "rooted", "authorities": ["{}"]}}}}"#,
+            Pubkey::new_unique()
+        ));
+        assert_eq!(reply, r#"{"result":"subscribed"}"#);
+        assert_eq!(subscription(), Some(Commitment::Rooted));
+
+        // A rejected request keeps the subscription.
+        let reply = connection
+            .handle_request(r#"{"method": "subscribe", "params": {"authorities": ["not a key"]}}"#);
+        assert!(reply.starts_with(r#"{"error":"#));
+        let reply = connection.handle_request("not json");
+        assert!(reply.contains("Invalid request"));
+        assert_eq!(subscription(), Some(Commitment::Rooted));
+
+        let reply = connection.handle_request(r#"{"method": "unsubscribe"}"#);
+        assert_eq!(reply, r#"{"result":"unsubscribed"}"#);
+        assert_eq!(subscription(), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut report = ConfigReport::default();
+        WebsocketSinkConfig {
+            listen_addr: "127.0.0.1:8900".to_string(),
+            max_connections: Some(1),
+            max_queued_messages: None,
+        }
+        .validate(&mut report);
+        assert!(report.errors.is_empty());
+
+        WebsocketSinkConfig {
+            listen_addr: "not an address".to_string(),
+            max_connections: Some(0),
+            max_queued_messages: Some(0),
+        }
+        .validate(&mut report);
+        assert_eq!(report.errors.len(), 3);
+    }
+}
@@ -1,2 +1,9 @@
+pub mod backfill;
 pub mod geyser_plugin_postgres;
+pub mod health;
+pub mod http_server;
+pub mod inscription_sink;
+pub mod metrics;
 pub mod postgres_client;
+pub mod prometheus;
+pub mod secrets;
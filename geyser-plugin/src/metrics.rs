@@ -0,0 +1,461 @@
+/// Counters and histograms describing the health of the write pipeline. The
+/// values are cumulative; the periodic `datapoint_info!` report sends the
+/// difference since the previous report.
+use {
+    domichain_metrics::*,
+    std::{
+        sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Upper bounds, in microseconds, of the write latency buckets.
+const WRITE_LATENCY_US_BOUNDS: &[u64] = &[
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000,
+];
+
+/// Upper bounds of the batch size buckets.
+const BATCH_SIZE_BOUNDS: &[u64] = &[1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000];
+
+/// A histogram with fixed buckets which can be updated concurrently.
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// One bucket per bound plus the overflow bucket.
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+/// A point in time copy of a `Histogram`.
+#[derive(Clone, Debug, Default)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<u64>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::default()).collect(),
+            sum: AtomicU64::default(),
+            count: AtomicU64::default(),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bounds(&self) -> &'static [u64] {
+        self.bounds
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            sum: self.sum.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl HistogramSnapshot {
+    /// The observations made between `previous` and this snapshot.
+    pub fn since(&self, previous: &HistogramSnapshot) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, count)| {
+                    count.saturating_sub(previous.buckets.get(i).copied().unwrap_or_default())
+                })
+                .collect(),
+            sum: self.sum.saturating_sub(previous.sum),
+            count: self.count.saturating_sub(previous.count),
+        }
+    }
+
+    /// The upper bound of the bucket holding the `percentile` (0-100)
+    /// observation. Observations above the last bound report that bound.
+    pub fn percentile(&self, bounds: &[u64], percentile: u64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((self.count * percentile + 99) / 100).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bounds
+                    .get(i)
+                    .or_else(|| bounds.last())
+                    .copied()
+                    .unwrap_or_default();
+            }
+        }
+        bounds.last().copied().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+/// Progress of one kind of work item through the queue.
+#[derive(Default)]
+pub struct WorkItemCounters {
+    pub enqueued: AtomicU64,
+    pub written: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkItemSnapshot {
+    pub enqueued: u64,
+    pub written: u64,
+    pub failed: u64,
+}
+
+impl WorkItemCounters {
+    fn snapshot(&self) -> WorkItemSnapshot {
+        WorkItemSnapshot {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl WorkItemSnapshot {
+    fn since(&self, previous: &WorkItemSnapshot) -> WorkItemSnapshot {
+        WorkItemSnapshot {
+            enqueued: self.enqueued.saturating_sub(previous.enqueued),
+            written: self.written.saturating_sub(previous.written),
+            failed: self.failed.saturating_sub(previous.failed),
+        }
+    }
+}
+
+/// Deliveries of the webhook sink.
+#[derive(Default)]
+pub struct DeliveryCounters {
+    /// Payloads accepted by an endpoint.
+    pub delivered: AtomicU64,
+    /// Attempts which failed and were scheduled for a retry.
+    pub retried: AtomicU64,
+    /// Payloads given up on after the last attempt, or because the queue
+    /// of their endpoint was full.
+    pub dropped: AtomicU64,
+    /// Payloads written to the retry queue directory because the queue of
+    /// their endpoint was full.
+    pub spilled: AtomicU64,
+    /// Payloads waiting for a retry.
+    pub retry_queue_depth: AtomicU64,
+}
+
+/// The lifecycle of one writer worker, or of one connection of the pipelined
+/// writer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum WorkerState {
+    Connecting = 0,
+    Connected = 1,
+    Failed = 2,
+    Disconnected = 3,
+    Stopped = 4,
+}
+
+impl WorkerState {
+    pub const ALL: [WorkerState; 5] = [
+        WorkerState::Connecting,
+        WorkerState::Connected,
+        WorkerState::Failed,
+        WorkerState::Disconnected,
+        WorkerState::Stopped,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerState::Connecting => "connecting",
+            WorkerState::Connected => "connected",
+            WorkerState::Failed => "failed",
+            WorkerState::Disconnected => "disconnected",
+            WorkerState::Stopped => "stopped",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|state| *state as u8 == value)
+            .unwrap_or(WorkerState::Failed)
+    }
+}
+
+/// The metrics shared by the plugin, the instruction decoder and the writers.
+pub struct PluginMetrics {
+    /// Decoded inscription events.
+    pub events: WorkItemCounters,
+    /// Slot status updates.
+    pub slots: WorkItemCounters,
+    pub webhook_deliveries: DeliveryCounters,
+    /// Inscription program instructions which could not be deserialized.
+    pub decode_failures: AtomicU64,
+    /// Connections re-established after the server closed them.
+    pub reconnects: AtomicU64,
+    /// Failed attempts to connect to the database.
+    pub connection_errors: AtomicU64,
+    /// Seconds since the epoch of the last database error, 0 if none.
+    pub last_db_error_unix_secs: AtomicU64,
+    /// Items waiting in the queue, updated on every send and receive.
+    pub queue_depth: AtomicU64,
+    /// The highest slot of an item written to every sink.
+    pub last_indexed_slot: AtomicU64,
+    /// The highest slot the validator reported as processed.
+    pub latest_processed_slot: AtomicU64,
+    /// The watermark: the highest rooted slot whose events, and those of
+    /// every slot before it, are written.
+    pub committed_slot: AtomicU64,
+    /// Gaps found between the committed slot and the slots seen after a
+    /// restart.
+    pub detected_gaps: AtomicU64,
+    pub detected_gap_slots: AtomicU64,
+    /// The gaps of the `backfill_gaps` table not filled yet, refreshed
+    /// periodically.
+    pub open_backfill_gaps: AtomicU64,
+    pub open_backfill_gap_slots: AtomicU64,
+    /// Set once a slot at or beyond `end_slot` is rooted.
+    pub end_slot_passed: AtomicBool,
+    /// Time spent writing one batch to every sink.
+    pub write_latency_us: Histogram,
+    /// Number of items written per batch.
+    pub batch_size: Histogram,
+    /// The largest batch written since the last report.
+    batch_size_max: AtomicU64,
+    worker_states: Vec<AtomicU8>,
+}
+
+/// A point in time copy of `PluginMetrics`.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSnapshot {
+    pub events: WorkItemSnapshot,
+    pub slots: WorkItemSnapshot,
+    pub webhook_delivered: u64,
+    pub webhook_retried: u64,
+    pub webhook_dropped: u64,
+    pub webhook_spilled: u64,
+    pub decode_failures: u64,
+    pub reconnects: u64,
+    pub write_latency_us: HistogramSnapshot,
+    pub batch_size: HistogramSnapshot,
+}
+
+impl PluginMetrics {
+    pub fn new(worker_count: usize) -> Self {
+        Self {
+            events: WorkItemCounters::default(),
+            slots: WorkItemCounters::default(),
+            webhook_deliveries: DeliveryCounters::default(),
+            decode_failures: AtomicU64::default(),
+            reconnects: AtomicU64::default(),
+            connection_errors: AtomicU64::default(),
+            last_db_error_unix_secs: AtomicU64::default(),
+            queue_depth: AtomicU64::default(),
+            last_indexed_slot: AtomicU64::default(),
+            latest_processed_slot: AtomicU64::default(),
+            committed_slot: AtomicU64::default(),
+            detected_gaps: AtomicU64::default(),
+            detected_gap_slots: AtomicU64::default(),
+            open_backfill_gaps: AtomicU64::default(),
+            open_backfill_gap_slots: AtomicU64::default(),
+            end_slot_passed: AtomicBool::default(),
+            write_latency_us: Histogram::new(WRITE_LATENCY_US_BOUNDS),
+            batch_size: Histogram::new(BATCH_SIZE_BOUNDS),
+            batch_size_max: AtomicU64::default(),
+            worker_states: (0..worker_count)
+                .map(|_| AtomicU8::new(WorkerState::Connecting as u8))
+                .collect(),
+        }
+    }
+
+    pub fn set_worker_state(&self, worker: usize, state: WorkerState) {
+        if let Some(worker_state) = self.worker_states.get(worker) {
+            worker_state.store(state as u8, Ordering::Relaxed);
+        }
+    }
+
+    pub fn worker_states(&self) -> Vec<WorkerState> {
+        self.worker_states
+            .iter()
+            .map(|state| WorkerState::from_u8(state.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Remember when the last database error happened.
+    pub fn record_db_error(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.last_db_error_unix_secs.store(now, Ordering::Relaxed);
+    }
+
+    /// Slots the validator has processed which are not indexed yet.
+    pub fn slot_lag(&self) -> u64 {
+        self.latest_processed_slot
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.last_indexed_slot.load(Ordering::Relaxed))
+    }
+
+    /// Record a batch of `batch_size` items which took `latency_us` to write.
+    pub fn record_batch(&self, batch_size: usize, latency_us: u64) {
+        self.batch_size.record(batch_size as u64);
+        self.batch_size_max
+            .fetch_max(batch_size as u64, Ordering::Relaxed);
+        self.write_latency_us.record(latency_us);
+    }
+
+    /// The largest batch written since the last call.
+    fn take_batch_size_max(&self) -> u64 {
+        self.batch_size_max.swap(0, Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            events: self.events.snapshot(),
+            slots: self.slots.snapshot(),
+            webhook_delivered: self.webhook_deliveries.delivered.load(Ordering::Relaxed),
+            webhook_retried: self.webhook_deliveries.retried.load(Ordering::Relaxed),
+            webhook_dropped: self.webhook_deliveries.dropped.load(Ordering::Relaxed),
+            webhook_spilled: self.webhook_deliveries.spilled.load(Ordering::Relaxed),
+            decode_failures: self.decode_failures.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            write_latency_us: self.write_latency_us.snapshot(),
+            batch_size: self.batch_size.snapshot(),
+        }
+    }
+
+    /// Send the activity since `previous` as a datapoint and return the
+    /// snapshot to diff the next report against.
+    pub fn report(&self, previous: &MetricsSnapshot, queue_depth: usize) -> MetricsSnapshot {
+        let current = self.snapshot();
+        let events = current.events.since(&previous.events);
+        let slots = current.slots.since(&previous.slots);
+        let latency = current.write_latency_us.since(&previous.write_latency_us);
+        let latency_bounds = self.write_latency_us.bounds();
+        let batch_size = current.batch_size.since(&previous.batch_size);
+
+        datapoint_info!(
+            "geyser_plugin_inscriptions",
+            ("queue_depth", queue_depth, i64),
+            ("events_enqueued", events.enqueued, i64),
+            ("events_written", events.written, i64),
+            ("events_failed", events.failed, i64),
+            ("slots_enqueued", slots.enqueued, i64),
+            ("slots_written", slots.written, i64),
+            ("slots_failed", slots.failed, i64),
+            (
+                "webhook_delivered",
+                current
+                    .webhook_delivered
+                    .saturating_sub(previous.webhook_delivered),
+                i64
+            ),
+            (
+                "webhook_retried",
+                current
+                    .webhook_retried
+                    .saturating_sub(previous.webhook_retried),
+                i64
+            ),
+            (
+                "webhook_dropped",
+                current
+                    .webhook_dropped
+                    .saturating_sub(previous.webhook_dropped),
+                i64
+            ),
+            (
+                "webhook_spilled",
+                current
+                    .webhook_spilled
+                    .saturating_sub(previous.webhook_spilled),
+                i64
+            ),
+            (
+                "webhook_retry_queue_depth",
+                self.webhook_deliveries
+                    .retry_queue_depth
+                    .load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "decode_failures",
+                current
+                    .decode_failures
+                    .saturating_sub(previous.decode_failures),
+                i64
+            ),
+            (
+                "reconnects",
+                current.reconnects.saturating_sub(previous.reconnects),
+                i64
+            ),
+            ("batches", batch_size.count, i64),
+            ("batch_size_mean", batch_size.mean(), i64),
+            ("batch_size_max", self.take_batch_size_max(), i64),
+            ("write_latency_us_mean", latency.mean(), i64),
+            (
+                "write_latency_us_p50",
+                latency.percentile(latency_bounds, 50),
+                i64
+            ),
+            (
+                "write_latency_us_p90",
+                latency.percentile(latency_bounds, 90),
+                i64
+            ),
+            (
+                "write_latency_us_p99",
+                latency.percentile(latency_bounds, 99),
+                i64
+            ),
+        );
+
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_size_max_is_observed_maximum() {
+        let metrics = PluginMetrics::new(1);
+        metrics.record_batch(3, 100);
+        metrics.record_batch(7, 100);
+        metrics.record_batch(4, 100);
+        // 7 falls in the bucket bounded by 10.
+        assert_eq!(
+            metrics
+                .batch_size
+                .snapshot()
+                .percentile(BATCH_SIZE_BOUNDS, 100),
+            10
+        );
+        assert_eq!(metrics.take_batch_size_max(), 7);
+        assert_eq!(metrics.take_batch_size_max(), 0);
+    }
+}
@@ -1,10 +1,23 @@
 #![allow(clippy::arithmetic_side_effects)]
 
-mod postgres_client_transaction;
+pub mod postgres_client_backfill;
+pub mod postgres_client_migration;
+pub(crate) mod postgres_client_partition;
+mod postgres_client_pipelined;
+pub mod postgres_client_transaction;
+pub mod postgres_client_watermark;
 
 /// A concurrent implementation for writing accounts into the PostgreSQL in parallel.
 use {
-    crate::geyser_plugin_postgres::{GeyserPluginPostgresConfig, GeyserPluginPostgresError},
+    crate::{
+        geyser_plugin_postgres::{
+            GeyserPluginPostgresConfig, GeyserPluginPostgresError, KeyStorage, SinkConfig, SslMode,
+            WriterKind,
+        },
+        inscription_sink::{self, InscriptionSink, SharedSink},
+        metrics::{MetricsSnapshot, PluginMetrics, WorkerState},
+        secrets,
+    },
     borsh::BorshDeserialize,
     chrono::Utc,
     crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
@@ -13,23 +26,28 @@ use {
     },
     domichain_measure::measure::Measure,
     domichain_metrics::*,
-    domichain_sdk::timing::AtomicInterval,
+    domichain_sdk::{pubkey::Pubkey, timing::AtomicInterval},
     log::*,
     mpl_inscription_program::instruction::MplInscriptionInstruction,
-    openssl::ssl::{SslConnector, SslFiletype, SslMethod},
+    openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode},
     postgres::{Client, NoTls, Statement},
-    postgres_client_transaction::LogInscriptionRequest,
+    postgres_client_backfill::BackfillGaps,
+    postgres_client_partition::EventPartitions,
+    postgres_client_pipelined::PipelinedPostgresWriter,
+    postgres_client_transaction::{DbKey, InscriptionEvent},
+    postgres_client_watermark::{CommitTracker, ResumePoint},
     postgres_openssl::MakeTlsConnector,
     std::{
-        collections::HashSet,
+        collections::{HashSet, VecDeque},
+        str::FromStr,
         sync::{
             atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
             Arc, Mutex,
         },
         thread::{self, sleep, Builder, JoinHandle},
-        time::Duration,
+        time::{Duration, Instant},
     },
-    tokio_postgres::types,
+    tokio_postgres::{tls::MakeTlsConnect, types, Socket},
 };
 
 /// The maximum asynchronous requests allowed in the channel to avoid excessive
@@ -37,24 +55,75 @@ use {
 const MAX_ASYNC_REQUESTS: usize = 40960;
 const DEFAULT_POSTGRES_PORT: u16 = 5432;
 const DEFAULT_THREADS_COUNT: usize = 100;
+const DEFAULT_PIPELINED_CONNECTIONS: usize = 4;
 const DEFAULT_ACCOUNTS_INSERT_BATCH_SIZE: usize = 10;
 const DEFAULT_PANIC_ON_DB_ERROR: bool = false;
+const DEFAULT_AUTO_MIGRATE: bool = false;
+const DEFAULT_SCHEMA: &str = "public";
+/// The longest identifier PostgreSQL keeps without truncating it.
+const MAX_IDENTIFIER_LEN: usize = 63;
+/// Leaves room in `MAX_IDENTIFIER_LEN` for the longest index name.
+const MAX_TABLE_PREFIX_LEN: usize = 16;
+const DEFAULT_MIN_CONNECTED_WORKERS: usize = 1;
+const DEFAULT_WORKER_STARTUP_TIMEOUT_SECS: u64 = 30;
+/// The connect timeout used when the connection string sets none, so that a
+/// worker connecting to an unreachable server gives up.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const WORKER_STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The delay before retrying the events a sink failed to write. It doubles
+/// with every attempt, up to `MAX_RETRY_BACKOFF`.
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+/// The failed batches a worker keeps for a retry. Beyond it the oldest is
+/// given up on.
+const MAX_FAILED_BATCHES: usize = 1000;
+const METRICS_REPORT_INTERVAL_MS: u64 = 10_000;
 
 struct PostgresSqlClientWrapper {
     client: Client,
     update_inscription_log_stmt: Statement,
+    insert_inscription_event_stmt: Statement,
+    update_slot_stmt: Statement,
+    update_committed_slot_stmt: Statement,
+    notify_stmt: Option<Statement>,
 }
 
 pub struct SimplePostgresClient {
     batch_size: usize,
     slots_at_startup: HashSet<u64>,
     client: Mutex<PostgresSqlClientWrapper>,
+    config: GeyserPluginPostgresConfig,
+    /// The `program_id` of the `indexer_state` row.
+    program_key: DbKey,
+    /// The watermark to write with the next events, or on flush.
+    committed_slot: Option<u64>,
 }
 
-struct PostgresClientWorker {
-    client: SimplePostgresClient,
+/// The events of a batch some sinks failed to write, retried with them.
+struct FailedEvents {
+    events: Vec<InscriptionEvent>,
+    /// The slot of each event batch, as counted by the `CommitTracker`.
+    event_slots: Vec<u64>,
+    /// The indexes of the sinks to retry.
+    sinks: Vec<usize>,
+    /// Whether the batch is still pending in the `CommitTracker`.
+    pending: bool,
+    attempts: u32,
+    due: Instant,
+}
+
+/// Drains the work queue into its sinks, one batch at a time.
+struct SinkWorker {
+    sinks: Vec<Box<dyn InscriptionSink>>,
+    /// The index the worker state is reported under, `None` when the sinks
+    /// report the state of their connections themselves.
+    index: Option<usize>,
+    batch_size: usize,
     /// Indicating if accounts notification during startup is done.
     is_startup_done: bool,
+    tracker: Arc<CommitTracker>,
+    /// The events to write again, oldest first.
+    failed_events: VecDeque<FailedEvents>,
 }
 
 pub(crate) fn abort() -> ! {
@@ -70,107 +139,250 @@ pub(crate) fn abort() -> ! {
     panic!("process::exit(1) is intercepted for friendly test failure...");
 }
 
-pub trait PostgresClient {
-    fn join(&mut self) -> thread::Result<()> {
-        Ok(())
+/// The schema and the table prefix the tables of the PostgreSQL sink are
+/// named with, and how they store keys. Statements and migrations are
+/// written as templates naming the tables `{schema}.{prefix}<table>`, with
+/// key columns of type `{key_type}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SchemaLayout {
+    schema: String,
+    prefix: String,
+    key_storage: KeyStorage,
+}
+
+impl SchemaLayout {
+    pub fn new(config: &GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
+        let schema = config
+            .schema
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SCHEMA.to_string());
+        let prefix = config.table_prefix.clone().unwrap_or_default();
+        if schema.is_empty() || !Self::is_identifier(&schema) || schema.len() > MAX_IDENTIFIER_LEN {
+            let msg = format!(
+                "\"schema\": {:?} must be a lowercase identifier of at most {} characters",
+                schema, MAX_IDENTIFIER_LEN
+            );
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::ConfigurationError { msg },
+            )));
+        }
+        if !Self::is_identifier(&prefix) || prefix.len() > MAX_TABLE_PREFIX_LEN {
+            let msg = format!(
+                "\"table_prefix\": {:?} must be a lowercase identifier of at most {} characters",
+                prefix, MAX_TABLE_PREFIX_LEN
+            );
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::ConfigurationError { msg },
+            )));
+        }
+        Ok(Self {
+            schema,
+            prefix,
+            key_storage: config.key_storage.unwrap_or_default(),
+        })
     }
 
-    fn log_inscription(
-        &mut self,
-        transaction_log_info: LogInscriptionRequest,
-    ) -> Result<(), GeyserPluginError>;
+    /// Whether `name` can be used unquoted in statements: lowercase letters,
+    /// digits and underscores, not starting with a digit.
+    pub(crate) fn is_identifier(name: &str) -> bool {
+        name.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    pub fn schema(&self) -> &str {
+        &self.schema
+    }
+
+    pub fn key_storage(&self) -> KeyStorage {
+        self.key_storage
+    }
+
+    /// The qualified name of `table`.
+    pub fn table(&self, table: &str) -> String {
+        format!("{}.{}{}", self.schema, self.prefix, table)
+    }
+
+    /// Replace `{schema}`, `{prefix}` and `{key_type}` in `template`.
+    pub fn render(&self, template: &str) -> String {
+        template
+            .replace("{schema}", &self.schema)
+            .replace("{prefix}", &self.prefix)
+            .replace("{key_type}", self.key_storage.column_type())
+    }
 }
 
 impl SimplePostgresClient {
-    pub fn connect_to_db(config: &GeyserPluginPostgresConfig) -> Result<Client, GeyserPluginError> {
+    /// Build the connection string from `connection_str`, or from `host`,
+    /// `user` and `port` when it is not set.
+    pub(crate) fn build_connection_str(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<String, GeyserPluginError> {
         let port = config.port.unwrap_or(DEFAULT_POSTGRES_PORT);
 
-        let connection_str = if let Some(connection_str) = &config.connection_str {
-            connection_str.clone()
-        } else {
-            if config.host.is_none() || config.user.is_none() {
-                let msg = format!(
-                    "\"connection_str\": {:?}, or \"host\": {:?} \"user\": {:?} must be specified",
-                    config.connection_str, config.host, config.user
-                );
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
-            }
-            format!(
-                "host={} user={} port={}",
-                config.host.as_ref().unwrap(),
-                config.user.as_ref().unwrap(),
-                port
-            )
+        if let Some(connection_str) = &config.connection_str {
+            return Ok(connection_str.clone());
+        }
+
+        if config.host.is_none() || config.user.is_none() {
+            let msg = format!(
+                "\"connection_str\", or \"host\": {:?} \"user\": {:?} must be specified",
+                config.host, config.user
+            );
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::ConfigurationError { msg },
+            )));
+        }
+        Ok(format!(
+            "host={} user={} port={}",
+            config.host.as_ref().unwrap(),
+            config.user.as_ref().unwrap(),
+            port
+        ))
+    }
+
+    /// Build the TLS connector of the `ssl_mode`. Returns `None` for plain
+    /// connections. The connector works for both the synchronous and the
+    /// tokio based clients.
+    pub(crate) fn build_tls_connector(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Option<MakeTlsConnector>, GeyserPluginError> {
+        let ssl_mode = config.ssl_mode();
+        if ssl_mode == SslMode::Disable {
+            return Ok(None);
+        }
+        let configuration_error = |msg| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+                msg,
+            }))
         };
 
-        let result = if let Some(true) = config.use_ssl {
-            if config.server_ca.is_none() {
-                let msg = "\"server_ca\" must be specified when \"use_ssl\" is set".to_string();
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
-            }
-            if config.client_cert.is_none() {
-                let msg = "\"client_cert\" must be specified when \"use_ssl\" is set".to_string();
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
-            }
-            if config.client_key.is_none() {
-                let msg = "\"client_key\" must be specified when \"use_ssl\" is set".to_string();
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
-            }
-            let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
-            if let Err(err) = builder.set_ca_file(config.server_ca.as_ref().unwrap()) {
-                let msg = format!(
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|err| {
+            configuration_error(format!("Failed to create the TLS connector: ({})", err))
+        })?;
+        if ssl_mode == SslMode::Require {
+            builder.set_verify(SslVerifyMode::NONE);
+        } else if let Some(server_ca) = &config.server_ca {
+            if let Err(err) = builder.set_ca_file(server_ca) {
+                return Err(configuration_error(format!(
                     "Failed to set the server certificate specified by \"server_ca\": {}. Error: ({})",
-                    config.server_ca.as_ref().unwrap(), err);
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
+                    server_ca, err
                 )));
             }
-            if let Err(err) =
-                builder.set_certificate_file(config.client_cert.as_ref().unwrap(), SslFiletype::PEM)
-            {
-                let msg = format!(
-                    "Failed to set the client certificate specified by \"client_cert\": {}. Error: ({})",
-                    config.client_cert.as_ref().unwrap(), err);
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
+        }
+
+        match (&config.client_cert, &config.client_key) {
+            (Some(client_cert), Some(client_key)) => {
+                if let Err(err) = builder.set_certificate_file(client_cert, SslFiletype::PEM) {
+                    return Err(configuration_error(format!(
+                        "Failed to set the client certificate specified by \"client_cert\": {}. Error: ({})",
+                        client_cert, err
+                    )));
+                }
+                if let Err(err) = builder.set_private_key_file(client_key, SslFiletype::PEM) {
+                    return Err(configuration_error(format!(
+                        "Failed to set the client key specified by \"client_key\": {}. Error: ({})",
+                        client_key, err
+                    )));
+                }
             }
-            if let Err(err) =
-                builder.set_private_key_file(config.client_key.as_ref().unwrap(), SslFiletype::PEM)
-            {
-                let msg = format!(
-                    "Failed to set the client key specified by \"client_key\": {}. Error: ({})",
-                    config.client_key.as_ref().unwrap(),
-                    err
-                );
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginPostgresError::ConfigurationError { msg },
-                )));
+            (None, None) => {}
+            _ => {
+                return Err(configuration_error(
+                    "\"client_cert\" and \"client_key\" must be specified together".to_string(),
+                ))
             }
+        }
 
-            let mut connector = MakeTlsConnector::new(builder.build());
+        let mut connector = MakeTlsConnector::new(builder.build());
+        if ssl_mode != SslMode::VerifyFull {
             connector.set_callback(|connect_config, _domain| {
                 connect_config.set_verify_hostname(false);
                 Ok(())
             });
-            Client::connect(&connection_str, connector)
-        } else {
-            Client::connect(&connection_str, NoTls)
+        }
+        Ok(Some(connector))
+    }
+
+    /// Parse the connection string and complete it with the password from
+    /// `password_file`, `PGPASSWORD` or the `PGPASSFILE` when it has none.
+    /// Also returns the connection string with its password masked, for the
+    /// logs.
+    pub(crate) fn build_connection_config(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<(tokio_postgres::Config, String), GeyserPluginError> {
+        let connection_str = Self::build_connection_str(config)?;
+        let redacted = secrets::redact_connection_str(&connection_str);
+        let configuration_error = |msg| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+                msg,
+            }))
+        };
+
+        let mut pg_config: tokio_postgres::Config = connection_str.parse().map_err(|err| {
+            configuration_error(format!(
+                "Invalid connection string {:?}: ({})",
+                redacted, err
+            ))
+        })?;
+        if let Some(password) =
+            secrets::resolve_password(&pg_config, config.password_file.as_deref())
+                .map_err(configuration_error)?
+        {
+            pg_config.password(password);
+        }
+        if pg_config.get_connect_timeout().is_none() {
+            pg_config.connect_timeout(DEFAULT_CONNECT_TIMEOUT);
+        }
+        // With a connector, tokio_postgres defaults to `prefer`, which falls
+        // back to a plain connection when the server does not offer TLS.
+        if config.ssl_mode() != SslMode::Disable {
+            pg_config.ssl_mode(tokio_postgres::config::SslMode::Require);
+        }
+        Ok((pg_config, redacted))
+    }
+
+    pub fn connect_to_db(config: &GeyserPluginPostgresConfig) -> Result<Client, GeyserPluginError> {
+        let (pg_config, redacted) = Self::build_connection_config(config)?;
+        let pg_config = postgres::Config::from(pg_config);
+
+        let result = match Self::build_tls_connector(config)? {
+            Some(connector) => pg_config.connect(connector),
+            None => pg_config.connect(NoTls),
+        };
+
+        match result {
+            Err(err) => {
+                let msg = format!(
+                    "Error in connecting to the PostgreSQL database: {:?} connection_str: {:?}",
+                    err, redacted
+                );
+                error!("{}", msg);
+                Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginPostgresError::DataStoreConnectionError { msg },
+                )))
+            }
+            Ok(client) => Ok(client),
+        }
+    }
+
+    /// Connect with `tokio_postgres`, handing the connection future over to
+    /// the current runtime, which drives the socket in the background.
+    pub(crate) async fn connect_to_db_async(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<tokio_postgres::Client, GeyserPluginError> {
+        let (pg_config, redacted) = Self::build_connection_config(config)?;
+
+        let result = match Self::build_tls_connector(config)? {
+            Some(connector) => Self::spawn_connection(&pg_config, connector).await,
+            None => Self::spawn_connection(&pg_config, NoTls).await,
         };
 
         match result {
             Err(err) => {
                 let msg = format!(
                     "Error in connecting to the PostgreSQL database: {:?} connection_str: {:?}",
-                    err, connection_str
+                    err, redacted
                 );
                 error!("{}", msg);
                 Err(GeyserPluginError::Custom(Box::new(
@@ -181,11 +393,38 @@ impl SimplePostgresClient {
         }
     }
 
+    async fn spawn_connection<T>(
+        pg_config: &tokio_postgres::Config,
+        tls: T,
+    ) -> Result<tokio_postgres::Client, tokio_postgres::Error>
+    where
+        T: MakeTlsConnect<Socket>,
+        T::Stream: Send + 'static,
+    {
+        let (client, connection) = pg_config.connect(tls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!("The PostgreSQL connection has failed: ({})", err);
+            }
+        });
+        Ok(client)
+    }
+
     pub fn new(config: &GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
         info!("Creating SimplePostgresClient...");
         let mut client = Self::connect_to_db(config)?;
         let update_transaction_log_stmt =
             Self::build_inscription_info_upsert_statement(&mut client, config)?;
+        let insert_inscription_event_stmt =
+            Self::build_inscription_event_insert_statement(&mut client, config)?;
+        let update_slot_stmt = Self::build_slot_upsert_statement(&mut client, config)?;
+        let update_committed_slot_stmt =
+            Self::build_committed_slot_upsert_statement(&mut client, config)?;
+        let notify_stmt = Self::build_notify_statement(&mut client, config)?;
+        let program_key = DbKey::pubkey(
+            &ParallelPostgresClient::parse_program_id(config)?,
+            config.key_storage.unwrap_or_default(),
+        );
 
         let batch_size = config
             .batch_size
@@ -197,39 +436,306 @@ impl SimplePostgresClient {
             client: Mutex::new(PostgresSqlClientWrapper {
                 client,
                 update_inscription_log_stmt: update_transaction_log_stmt,
+                insert_inscription_event_stmt,
+                update_slot_stmt,
+                update_committed_slot_stmt,
+                notify_stmt,
             }),
             slots_at_startup: HashSet::default(),
+            config: config.clone(),
+            program_key,
+            committed_slot: None,
         })
     }
 }
 
-impl PostgresClient for SimplePostgresClient {
-    fn log_inscription(
-        &mut self,
-        transaction_log_info: LogInscriptionRequest,
-    ) -> Result<(), GeyserPluginError> {
-        self.log_inscription_impl(transaction_log_info)
+impl SimplePostgresClient {
+    pub fn is_closed(&mut self) -> bool {
+        self.client.get_mut().unwrap().client.is_closed()
     }
 }
 
 #[warn(clippy::large_enum_variant)]
 enum DbWorkItem {
-    LogInscription(Box<LogInscriptionRequest>),
+    LogEvents(Vec<InscriptionEvent>),
+    UpdateSlot {
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    },
 }
 
-impl PostgresClientWorker {
-    fn new(config: GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
-        let result = SimplePostgresClient::new(&config);
-        match result {
-            Ok(client) => Ok(PostgresClientWorker {
-                client,
-                is_startup_done: false,
-            }),
+impl SinkWorker {
+    /// Write the events and slot updates of `batch` to every sink, events
+    /// first, along with the watermark they advance. The events a sink fails
+    /// to write are retried later. Returns true if any sink failed.
+    fn write_batch(&mut self, batch: Vec<DbWorkItem>, metrics: &PluginMetrics) -> bool {
+        let mut events = Vec::new();
+        let mut event_slots = Vec::new();
+        let mut slots = Vec::new();
+        for work in batch {
+            match work {
+                DbWorkItem::LogEvents(batch_events) => {
+                    event_slots.extend(batch_events.first().map(|event| event.slot));
+                    events.extend(batch_events);
+                }
+                DbWorkItem::UpdateSlot {
+                    slot,
+                    parent,
+                    status,
+                } => slots.push((slot, parent, status)),
+            }
+        }
+
+        let mut measure = Measure::start("geyser-plugin-postgres-worker-write");
+        let mut failed_sinks = Vec::new();
+        let mut slots_failed = vec![false; slots.len()];
+        let committed_slot = self.preview(&event_slots);
+        for (index, sink) in self.sinks.iter_mut().enumerate() {
+            Self::reconnect(sink.as_mut(), self.index, metrics);
+
+            if let Some(committed_slot) = committed_slot {
+                sink.set_committed_slot(committed_slot);
+            }
+            let mut events_failed = false;
+            if !events.is_empty() {
+                if let Err(err) = sink.write_events(&events) {
+                    error!(
+                        "Failed to write {} events to the {} sink: ({})",
+                        events.len(),
+                        sink.name(),
+                        err
+                    );
+                    events_failed = true;
+                }
+            }
+
+            for ((slot, parent, status), slot_failed) in slots.iter().zip(slots_failed.iter_mut()) {
+                if let Err(err) = sink.update_slot_status(*slot, *parent, status) {
+                    error!(
+                        "Failed to update the slot {} in the {} sink: ({})",
+                        slot,
+                        sink.name(),
+                        err
+                    );
+                    *slot_failed = true;
+                }
+            }
+
+            if let Err(err) = sink.flush() {
+                error!("Failed to flush the {} sink: ({})", sink.name(), err);
+                events_failed = true;
+                slots_failed.iter_mut().for_each(|failed| *failed = true);
+            }
+            if events_failed {
+                failed_sinks.push(index);
+            }
+        }
+        measure.stop();
+
+        let slots_failed = slots_failed.iter().filter(|failed| **failed).count();
+        let events_failed = !failed_sinks.is_empty();
+        let failed = events_failed || slots_failed > 0;
+        let event_count = events.len() as u64;
+        // Only the sinks keeping the watermark hold it back: the events of
+        // the others are retried without it.
+        let pending = failed_sinks
+            .iter()
+            .any(|index| self.sinks[*index].keeps_watermark());
+        if events_failed {
+            metrics
+                .events
+                .failed
+                .fetch_add(event_count, Ordering::Relaxed);
+        } else {
+            metrics
+                .events
+                .written
+                .fetch_add(event_count, Ordering::Relaxed);
+        }
+        if !pending {
+            self.tracker.written(&event_slots, committed_slot);
+            if let Some(committed_slot) = committed_slot {
+                metrics
+                    .committed_slot
+                    .fetch_max(committed_slot, Ordering::Relaxed);
+            }
+        }
+        metrics
+            .slots
+            .failed
+            .fetch_add(slots_failed as u64, Ordering::Relaxed);
+        metrics
+            .slots
+            .written
+            .fetch_add((slots.len() - slots_failed) as u64, Ordering::Relaxed);
+        metrics.record_batch(events.len() + slots.len(), measure.as_us());
+
+        if failed {
+            metrics.record_db_error();
+        } else {
+            let max_slot = events
+                .iter()
+                .map(|event| event.slot)
+                .chain(slots.iter().map(|(slot, _, _)| *slot))
+                .max()
+                .unwrap_or_default();
+            metrics
+                .last_indexed_slot
+                .fetch_max(max_slot, Ordering::Relaxed);
+        }
+        if events_failed {
+            self.schedule_retry(FailedEvents {
+                events,
+                event_slots,
+                sinks: failed_sinks,
+                pending,
+                attempts: 1,
+                due: Instant::now() + INITIAL_RETRY_BACKOFF,
+            });
+        }
+        failed
+    }
+
+    /// The watermark once the batches of the slots `written` are, kept below
+    /// the events the buffering sinks hold in memory, those of the batches
+    /// included.
+    fn preview(&self, written: &[u64]) -> Option<u64> {
+        let buffered_slot = self.sinks.iter().filter_map(|sink| sink.buffered_slot());
+        let limit = if self.sinks.iter().any(|sink| sink.buffers_events()) {
+            buffered_slot.chain(written.iter().copied()).min()
+        } else {
+            buffered_slot.min()
+        };
+        self.tracker
+            .preview_before(written, limit.unwrap_or(u64::MAX))
+    }
+
+    fn reconnect(sink: &mut dyn InscriptionSink, index: Option<usize>, metrics: &PluginMetrics) {
+        match sink.ensure_connected() {
+            Ok(true) => {
+                metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+                if let Some(index) = index {
+                    metrics.set_worker_state(index, WorkerState::Connected);
+                }
+            }
+            Ok(false) => {}
             Err(err) => {
-                error!("Error in creating SimplePostgresClient: {}", err);
-                Err(err)
+                error!("Failed to reconnect the {} sink: ({})", sink.name(), err);
+                metrics.connection_errors.fetch_add(1, Ordering::Relaxed);
+                metrics.record_db_error();
+                if let Some(index) = index {
+                    metrics.set_worker_state(index, WorkerState::Disconnected);
+                }
+            }
+        }
+    }
+
+    fn schedule_retry(&mut self, failed_events: FailedEvents) {
+        if self.failed_events.len() >= MAX_FAILED_BATCHES {
+            if let Some(dropped) = self.failed_events.pop_front() {
+                error!(
+                    "Giving up on {} events after {} attempts, too many batches failed",
+                    dropped.events.len(),
+                    dropped.attempts
+                );
+            }
+        }
+        self.failed_events.push_back(failed_events);
+    }
+
+    /// Write the failed events which are due again to the sinks which
+    /// failed them. Once every sink keeping the watermark has written them,
+    /// the watermark can move past their slots.
+    fn retry_failed_events(&mut self, metrics: &PluginMetrics) {
+        let now = Instant::now();
+        let (due, waiting) = std::mem::take(&mut self.failed_events)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|failed_events| failed_events.due <= now);
+        self.failed_events = waiting;
+
+        for mut failed_events in due {
+            let committed_slot = failed_events
+                .pending
+                .then(|| self.preview(&failed_events.event_slots))
+                .flatten();
+            let sinks = std::mem::take(&mut failed_events.sinks);
+            for index in sinks {
+                let sink = self.sinks[index].as_mut();
+                Self::reconnect(sink, self.index, metrics);
+                if let Some(committed_slot) = committed_slot {
+                    sink.set_committed_slot(committed_slot);
+                }
+                if let Err(err) = sink
+                    .write_events(&failed_events.events)
+                    .and_then(|()| sink.flush())
+                {
+                    error!(
+                        "Failed to write {} events to the {} sink, attempt {}: ({})",
+                        failed_events.events.len(),
+                        sink.name(),
+                        failed_events.attempts + 1,
+                        err
+                    );
+                    failed_events.sinks.push(index);
+                }
+            }
+
+            let pending = failed_events
+                .sinks
+                .iter()
+                .any(|index| self.sinks[*index].keeps_watermark());
+            if failed_events.pending && !pending {
+                self.tracker
+                    .written(&failed_events.event_slots, committed_slot);
+                if let Some(committed_slot) = committed_slot {
+                    metrics
+                        .committed_slot
+                        .fetch_max(committed_slot, Ordering::Relaxed);
+                }
+            }
+            failed_events.pending = pending;
+            if failed_events.sinks.is_empty() {
+                metrics
+                    .events
+                    .written
+                    .fetch_add(failed_events.events.len() as u64, Ordering::Relaxed);
+                continue;
+            }
+            metrics.record_db_error();
+            let backoff = INITIAL_RETRY_BACKOFF
+                .saturating_mul(1 << failed_events.attempts.min(16))
+                .min(MAX_RETRY_BACKOFF);
+            failed_events.attempts += 1;
+            failed_events.due = Instant::now() + backoff;
+            self.schedule_retry(failed_events);
+        }
+    }
+
+    /// Write the watermark if it advanced since the last batch, which
+    /// happens when the batches holding it back were written by other
+    /// workers. Called when the queue is idle.
+    fn advance_committed_slot(&mut self, metrics: &PluginMetrics) {
+        let committed_slot = match self.preview(&[]) {
+            Some(committed_slot) => committed_slot,
+            None => return,
+        };
+        let mut failed = false;
+        for sink in self.sinks.iter_mut() {
+            sink.set_committed_slot(committed_slot);
+            if let Err(err) = sink.flush() {
+                error!("Failed to flush the {} sink: ({})", sink.name(), err);
+                failed = true;
             }
         }
+        if failed {
+            metrics.record_db_error();
+        } else {
+            self.tracker.written(&[], Some(committed_slot));
+            metrics
+                .committed_slot
+                .fetch_max(committed_slot, Ordering::Relaxed);
+        }
     }
 
     fn do_work(
@@ -238,6 +744,7 @@ impl PostgresClientWorker {
         exit_worker: Arc<AtomicBool>,
         is_startup_done: Arc<AtomicBool>,
         startup_done_count: Arc<AtomicUsize>,
+        metrics: Arc<PluginMetrics>,
         panic_on_db_errors: bool,
     ) -> Result<(), GeyserPluginError> {
         while !exit_worker.load(Ordering::Relaxed) {
@@ -250,45 +757,40 @@ impl PostgresClientWorker {
                 100000,
                 100000
             );
+            let mut batch = Vec::with_capacity(self.batch_size);
             match work {
-                Ok(work) => match work {
-                    DbWorkItem::LogInscription(transaction_log_info) => {
-                        if let Err(err) = self.client.log_inscription(*transaction_log_info) {
-                            error!("Failed to update transaction: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
-                        }
+                Ok(work) => batch.push(work),
+                Err(RecvTimeoutError::Timeout) => {
+                    if !self.is_startup_done && is_startup_done.load(Ordering::Relaxed) {
+                        self.is_startup_done = true;
+                        startup_done_count.fetch_add(1, Ordering::Relaxed);
                     }
-                },
-                Err(err) => match err {
-                    RecvTimeoutError::Timeout => {
-                        if !self.is_startup_done && is_startup_done.load(Ordering::Relaxed) {
-                            // if let Err(err) = self.client.notify_end_of_startup() {
-                            //     error!("Error in notifying end of startup: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
-                            // }
-                            self.is_startup_done = true;
-                            startup_done_count.fetch_add(1, Ordering::Relaxed);
-                        }
-
-                        continue;
-                    }
-                    _ => {
-                        error!("Error in receiving the item {:?}", err);
-                        if panic_on_db_errors {
-                            abort();
-                        }
-                        break;
+                    self.retry_failed_events(&metrics);
+                    self.advance_committed_slot(&metrics);
+                    continue;
+                }
+                Err(err) => {
+                    error!("Error in receiving the item {:?}", err);
+                    if panic_on_db_errors {
+                        abort();
                     }
-                },
+                    break;
+                }
             }
+            batch.extend(receiver.try_iter().take(self.batch_size - 1));
+            metrics
+                .queue_depth
+                .store(receiver.len() as u64, Ordering::Relaxed);
+
+            if self.write_batch(batch, &metrics) && panic_on_db_errors {
+                abort();
+            }
+            self.retry_failed_events(&metrics);
         }
         Ok(())
     }
 }
+
 pub struct ParallelPostgresClient {
     workers: Vec<JoinHandle<Result<(), GeyserPluginError>>>,
     exit_worker: Arc<AtomicBool>,
@@ -296,63 +798,198 @@ pub struct ParallelPostgresClient {
     startup_done_count: Arc<AtomicUsize>,
     initialized_worker_count: Arc<AtomicUsize>,
     sender: Sender<DbWorkItem>,
+    program_id: Pubkey,
+    /// The partitions of the event table, when the `Postgres` sink is
+    /// configured.
+    partitions: Option<EventPartitions>,
+    tracker: Arc<CommitTracker>,
+    /// The watermark of the `Postgres` sink when loading, if any.
+    resume_point: Option<ResumePoint>,
+    /// The gaps of the index, when the `Postgres` sink is configured.
+    backfill_gaps: Option<BackfillGaps>,
     last_report: AtomicInterval,
+    metrics: Arc<PluginMetrics>,
+    last_reported_metrics: Mutex<MetricsSnapshot>,
 }
 
 impl ParallelPostgresClient {
     pub fn new(config: &GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
         info!("Creating ParallelPostgresClient...");
+        let program_id = Self::parse_program_id(config)?;
+        let sinks = config.sinks();
+        if sinks.is_empty() {
+            let msg = "\"sinks\" must not be empty".to_string();
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::ConfigurationError { msg },
+            )));
+        }
+        let (partitions, committed_slot) = if sinks.contains(&SinkConfig::Postgres) {
+            let mut client = SimplePostgresClient::connect_to_db(config)?;
+            let layout = SchemaLayout::new(config)?;
+            postgres_client_migration::prepare_schema(
+                &mut client,
+                &layout,
+                config.auto_migrate.unwrap_or(DEFAULT_AUTO_MIGRATE),
+            )?;
+            let committed_slot =
+                postgres_client_watermark::load_committed_slot(&mut client, &layout, &program_id)?;
+            (Some(EventPartitions::new(config)?), committed_slot)
+        } else {
+            (None, None)
+        };
+        let tracker = Arc::new(CommitTracker::new(committed_slot.unwrap_or_default()));
+        let resume_point = committed_slot.map(ResumePoint::new);
         let (sender, receiver) = bounded(MAX_ASYNC_REQUESTS);
         let exit_worker = Arc::new(AtomicBool::new(false));
         let mut workers = Vec::default();
         let is_startup_done = Arc::new(AtomicBool::new(false));
         let startup_done_count = Arc::new(AtomicUsize::new(0));
-        let worker_count = config.threads.unwrap_or(DEFAULT_THREADS_COUNT);
+        let writer = config.writer.unwrap_or_default();
+        let worker_count = Self::worker_count(config)?;
         let initialized_worker_count = Arc::new(AtomicUsize::new(0));
-        for i in 0..worker_count {
-            let cloned_receiver = receiver.clone();
-            let exit_clone = exit_worker.clone();
-            let is_startup_done_clone = is_startup_done.clone();
-            let startup_done_count_clone = startup_done_count.clone();
-            let initialized_worker_count_clone = initialized_worker_count.clone();
-            let config = config.clone();
-            let worker = Builder::new()
-                .name(format!("worker-{}", i))
-                .spawn(move || -> Result<(), GeyserPluginError> {
-                    let panic_on_db_errors = *config
-                        .panic_on_db_errors
-                        .as_ref()
-                        .unwrap_or(&DEFAULT_PANIC_ON_DB_ERROR);
-                    let result = PostgresClientWorker::new(config);
-
-                    match result {
-                        Ok(mut worker) => {
-                            initialized_worker_count_clone.fetch_add(1, Ordering::Relaxed);
-                            worker.do_work(
-                                cloned_receiver,
-                                exit_clone,
-                                is_startup_done_clone,
-                                startup_done_count_clone,
-                                panic_on_db_errors,
-                            )?;
-                            Ok(())
-                        }
-                        Err(err) => {
-                            error!("Error when making connection to database: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
+        let failed_worker_count = Arc::new(AtomicUsize::new(0));
+        let metrics = Arc::new(PluginMetrics::new(worker_count));
+        metrics
+            .committed_slot
+            .store(committed_slot.unwrap_or_default(), Ordering::Relaxed);
+        let backfill_gaps = sinks
+            .contains(&SinkConfig::Postgres)
+            .then(|| BackfillGaps::new(config, metrics.clone()))
+            .transpose()?;
+        let shared_sinks = inscription_sink::build_shared_sinks(config, &metrics)?;
+        let min_connected_workers = config
+            .min_connected_workers
+            .unwrap_or(DEFAULT_MIN_CONNECTED_WORKERS);
+        match writer {
+            WriterKind::Threaded => {
+                for i in 0..worker_count {
+                    let cloned_receiver = receiver.clone();
+                    let exit_clone = exit_worker.clone();
+                    let is_startup_done_clone = is_startup_done.clone();
+                    let startup_done_count_clone = startup_done_count.clone();
+                    let initialized_worker_count_clone = initialized_worker_count.clone();
+                    let failed_worker_count_clone = failed_worker_count.clone();
+                    let metrics_clone = metrics.clone();
+                    let shared_sinks = shared_sinks.clone();
+                    let tracker = tracker.clone();
+                    let config = config.clone();
+                    let worker = Builder::new()
+                        .name(format!("worker-{}", i))
+                        .spawn(move || -> Result<(), GeyserPluginError> {
+                            let panic_on_db_errors = *config
+                                .panic_on_db_errors
+                                .as_ref()
+                                .unwrap_or(&DEFAULT_PANIC_ON_DB_ERROR);
+                            let result =
+                                inscription_sink::build_worker_sinks(&config, &shared_sinks);
+
+                            match result {
+                                Ok(sinks) => {
+                                    let mut worker = SinkWorker {
+                                        sinks,
+                                        index: Some(i),
+                                        batch_size: Self::batch_size(&config),
+                                        is_startup_done: false,
+                                        tracker,
+                                        failed_events: VecDeque::new(),
+                                    };
+                                    initialized_worker_count_clone.fetch_add(1, Ordering::Relaxed);
+                                    metrics_clone.set_worker_state(i, WorkerState::Connected);
+                                    let result = worker.do_work(
+                                        cloned_receiver,
+                                        exit_clone,
+                                        is_startup_done_clone,
+                                        startup_done_count_clone,
+                                        metrics_clone.clone(),
+                                        panic_on_db_errors,
+                                    );
+                                    metrics_clone.set_worker_state(i, WorkerState::Stopped);
+                                    result
+                                }
+                                Err(err) => {
+                                    error!("Error when making connection to database: ({})", err);
+                                    failed_worker_count_clone.fetch_add(1, Ordering::Relaxed);
+                                    metrics_clone
+                                        .connection_errors
+                                        .fetch_add(1, Ordering::Relaxed);
+                                    metrics_clone.record_db_error();
+                                    metrics_clone.set_worker_state(i, WorkerState::Failed);
+                                    if panic_on_db_errors {
+                                        abort();
+                                    }
+                                    Err(err)
+                                }
+                            }
+                        })
+                        .unwrap();
+
+                    workers.push(worker);
+                }
+            }
+            WriterKind::Pipelined => {
+                let config = config.clone();
+                let exit_clone = exit_worker.clone();
+                let is_startup_done_clone = is_startup_done.clone();
+                let startup_done_count_clone = startup_done_count.clone();
+                let initialized_worker_count_clone = initialized_worker_count.clone();
+                let failed_worker_count_clone = failed_worker_count.clone();
+                let metrics_clone = metrics.clone();
+                let tracker = tracker.clone();
+                let worker = Builder::new()
+                    .name("pipelined-writer".to_string())
+                    .spawn(move || -> Result<(), GeyserPluginError> {
+                        let panic_on_db_errors = *config
+                            .panic_on_db_errors
+                            .as_ref()
+                            .unwrap_or(&DEFAULT_PANIC_ON_DB_ERROR);
+                        let result = Self::build_pipelined_sinks(
+                            &config,
+                            worker_count,
+                            &metrics_clone,
+                            &initialized_worker_count_clone,
+                            &failed_worker_count_clone,
+                            shared_sinks,
+                        );
+
+                        match result {
+                            Ok(sinks) => {
+                                let mut worker = SinkWorker {
+                                    sinks,
+                                    index: None,
+                                    batch_size: Self::batch_size(&config),
+                                    is_startup_done: false,
+                                    tracker,
+                                    failed_events: VecDeque::new(),
+                                };
+                                let result = worker.do_work(
+                                    receiver,
+                                    exit_clone,
+                                    is_startup_done_clone,
+                                    startup_done_count_clone,
+                                    metrics_clone.clone(),
+                                    panic_on_db_errors,
+                                );
+                                for i in 0..worker_count {
+                                    metrics_clone.set_worker_state(i, WorkerState::Stopped);
+                                }
+                                result
+                            }
+                            Err(err) => {
+                                error!("Error when making connection to database: ({})", err);
+                                if panic_on_db_errors {
+                                    abort();
+                                }
+                                Err(err)
                             }
-                            Err(err)
                         }
-                    }
-                })
-                .unwrap();
+                    })
+                    .unwrap();
 
-            workers.push(worker);
+                workers.push(worker);
+            }
         }
 
-        info!("Created ParallelPostgresClient.");
-        Ok(Self {
+        let mut client = Self {
             last_report: AtomicInterval::default(),
             workers,
             exit_worker,
@@ -360,22 +997,232 @@ impl ParallelPostgresClient {
             startup_done_count,
             initialized_worker_count,
             sender,
-        })
+            program_id,
+            partitions,
+            tracker,
+            resume_point,
+            backfill_gaps,
+            metrics,
+            last_reported_metrics: Mutex::default(),
+        };
+
+        let timeout = Duration::from_secs(
+            config
+                .worker_startup_timeout_secs
+                .unwrap_or(DEFAULT_WORKER_STARTUP_TIMEOUT_SECS),
+        );
+        if let Err(err) = client.wait_for_workers(
+            worker_count,
+            min_connected_workers,
+            &failed_worker_count,
+            timeout,
+        ) {
+            client.abandon_workers();
+            return Err(err);
+        }
+
+        info!("Created ParallelPostgresClient.");
+        Ok(client)
     }
 
-    pub fn join(&mut self) -> thread::Result<()> {
-        self.exit_worker.store(true, Ordering::Relaxed);
-        while !self.workers.is_empty() {
-            let worker = self.workers.pop();
-            if worker.is_none() {
-                break;
+    /// Block until at least `min_connected_workers` workers have connected to
+    /// the database and prepared their statements. Fails early once too many
+    /// workers have given up for the minimum to be reachable, or when
+    /// `timeout` elapses.
+    fn wait_for_workers(
+        &self,
+        worker_count: usize,
+        min_connected_workers: usize,
+        failed_worker_count: &AtomicUsize,
+        timeout: Duration,
+    ) -> Result<(), GeyserPluginError> {
+        let start = Instant::now();
+        loop {
+            let initialized = self.initialized_worker_count.load(Ordering::Relaxed);
+            if initialized >= min_connected_workers {
+                info!(
+                    "{} of {} workers connected to the database.",
+                    initialized, worker_count
+                );
+                return Ok(());
             }
-            let worker = worker.unwrap();
-            let result = worker.join().unwrap();
-            if result.is_err() {
-                error!("The worker thread has failed: {:?}", result);
+
+            let failed = failed_worker_count.load(Ordering::Relaxed);
+            let msg = if worker_count - failed < min_connected_workers {
+                format!(
+                    "Only {} of {} workers could connect to the database, {} failed. \
+                    At least {} are required (\"min_connected_workers\"). \
+                    Check the validator log for the connection errors.",
+                    initialized, worker_count, failed, min_connected_workers
+                )
+            } else if start.elapsed() >= timeout {
+                format!(
+                    "Timed out after {:?} waiting for workers to connect to the database: \
+                    {} of {} connected, {} failed, at least {} are required (\"min_connected_workers\").",
+                    timeout, initialized, worker_count, failed, min_connected_workers
+                )
+            } else {
+                sleep(WORKER_STARTUP_POLL_INTERVAL);
+                continue;
+            };
+
+            error!("{}", msg);
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::DataStoreConnectionError { msg },
+            )));
+        }
+    }
+
+    pub fn parse_program_id(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Pubkey, GeyserPluginError> {
+        match &config.program_id {
+            None => Ok(mpl_inscription_program::ID),
+            Some(program_id) => Pubkey::from_str(program_id).map_err(|err| {
+                GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+                    msg: format!(
+                        "\"program_id\": {:?} is not a valid public key: ({})",
+                        program_id, err
+                    ),
+                }))
+            }),
+        }
+    }
+
+    /// The number of threads, or connections of the `pipelined` writer,
+    /// checking `min_connected_workers` does not exceed it.
+    pub fn worker_count(config: &GeyserPluginPostgresConfig) -> Result<usize, GeyserPluginError> {
+        let writer = config.writer.unwrap_or_default();
+        let (field, worker_count) = match writer {
+            WriterKind::Threaded => ("threads", config.threads.unwrap_or(DEFAULT_THREADS_COUNT)),
+            WriterKind::Pipelined => (
+                "connections",
+                config.connections.unwrap_or(DEFAULT_PIPELINED_CONNECTIONS),
+            ),
+        };
+        let min_connected_workers = config
+            .min_connected_workers
+            .unwrap_or(DEFAULT_MIN_CONNECTED_WORKERS);
+        let msg = if worker_count == 0 {
+            format!("\"{}\" must be greater than 0", field)
+        } else if min_connected_workers > worker_count {
+            format!(
+                "\"min_connected_workers\": {} must not be greater than the {} {:?} writer workers",
+                min_connected_workers, worker_count, writer
+            )
+        } else {
+            return Ok(worker_count);
+        };
+        Err(GeyserPluginError::Custom(Box::new(
+            GeyserPluginPostgresError::ConfigurationError { msg },
+        )))
+    }
+
+    fn batch_size(config: &GeyserPluginPostgresConfig) -> usize {
+        config
+            .batch_size
+            .unwrap_or(DEFAULT_ACCOUNTS_INSERT_BATCH_SIZE)
+            .max(1)
+    }
+
+    /// Create the sinks of the pipelined writer thread: the pipelined
+    /// PostgreSQL writer when the `Postgres` sink is configured, followed by
+    /// the shared sinks. Without the `Postgres` sink the connections are
+    /// reported connected right away.
+    fn build_pipelined_sinks(
+        config: &GeyserPluginPostgresConfig,
+        connection_count: usize,
+        metrics: &Arc<PluginMetrics>,
+        initialized_worker_count: &AtomicUsize,
+        failed_worker_count: &AtomicUsize,
+        shared_sinks: Vec<SharedSink>,
+    ) -> Result<Vec<Box<dyn InscriptionSink>>, GeyserPluginError> {
+        let mut sinks: Vec<Box<dyn InscriptionSink>> = Vec::new();
+        if config.sinks().contains(&SinkConfig::Postgres) {
+            sinks.push(Box::new(PipelinedPostgresWriter::new(
+                config,
+                connection_count,
+                metrics.clone(),
+                initialized_worker_count,
+                failed_worker_count,
+            )?));
+        } else {
+            for i in 0..connection_count {
+                initialized_worker_count.fetch_add(1, Ordering::Relaxed);
+                metrics.set_worker_state(i, WorkerState::Connected);
             }
         }
+        sinks.extend(
+            shared_sinks
+                .into_iter()
+                .map(|sink| Box::new(sink) as Box<dyn InscriptionSink>),
+        );
+        Ok(sinks)
+    }
+
+    /// The ID of the program whose instructions are indexed.
+    pub fn program_id(&self) -> &Pubkey {
+        &self.program_id
+    }
+
+    pub fn metrics(&self) -> &Arc<PluginMetrics> {
+        &self.metrics
+    }
+
+    /// Mark the end of the validator startup. Workers acknowledge it through
+    /// `startup_done_count` once their queue has drained.
+    pub fn notify_end_of_startup(&self) {
+        self.is_startup_done.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_startup_done(&self) -> &Arc<AtomicBool> {
+        &self.is_startup_done
+    }
+
+    /// Send a metrics datapoint if the reporting interval has elapsed.
+    pub fn report_metrics(&self) {
+        if !self.last_report.should_update(METRICS_REPORT_INTERVAL_MS) {
+            return;
+        }
+        let mut last_reported_metrics = self.last_reported_metrics.lock().unwrap();
+        *last_reported_metrics = self
+            .metrics
+            .report(&last_reported_metrics, self.sender.len());
+    }
+
+    /// Signal the workers to exit after a failed startup, joining those
+    /// which have stopped and detaching those still connecting, which exit
+    /// once their connection attempt fails or times out.
+    fn abandon_workers(&mut self) {
+        self.exit_worker.store(true, Ordering::Relaxed);
+        let (finished, connecting): (Vec<_>, Vec<_>) = self
+            .workers
+            .drain(..)
+            .partition(|worker| worker.is_finished());
+        for worker in finished {
+            Self::join_worker(worker);
+        }
+        if !connecting.is_empty() {
+            warn!(
+                "Detaching {} worker threads still connecting to the database.",
+                connecting.len()
+            );
+        }
+    }
+
+    fn join_worker(worker: JoinHandle<Result<(), GeyserPluginError>>) {
+        match worker.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!("The worker thread has failed: ({})", err),
+            Err(err) => error!("The worker thread has panicked: {:?}", err),
+        }
+    }
+
+    pub fn join(&mut self) -> thread::Result<()> {
+        self.exit_worker.store(true, Ordering::Relaxed);
+        while let Some(worker) = self.workers.pop() {
+            Self::join_worker(worker);
+        }
 
         Ok(())
     }
@@ -409,3 +1256,320 @@ impl PostgresClientBuilder {
         ParallelPostgresClient::new(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::postgres_client::postgres_client_transaction::InscriptionInstructionType,
+        domichain_sdk::signature::Signature,
+    };
+
+    /// A sink failing its first `failures` writes.
+    struct FlakySink {
+        keeps_watermark: bool,
+        failures: usize,
+        written: Arc<Mutex<Vec<u64>>>,
+        committed_slot: Arc<Mutex<Option<u64>>>,
+    }
+
+    impl InscriptionSink for FlakySink {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn set_committed_slot(&mut self, slot: u64) {
+            *self.committed_slot.lock().unwrap() = Some(slot);
+        }
+
+        fn keeps_watermark(&self) -> bool {
+            self.keeps_watermark
+        }
+
+        fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginPostgresError::SinkError {
+                        msg: "flaky".to_string(),
+                    },
+                )));
+            }
+            self.written
+                .lock()
+                .unwrap()
+                .extend(events.iter().map(|event| event.slot));
+            Ok(())
+        }
+    }
+
+    /// A sink keeping the events in memory until `written` is set.
+    #[derive(Default)]
+    struct BufferingSink {
+        buffered: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl InscriptionSink for BufferingSink {
+        fn name(&self) -> &'static str {
+            "buffering"
+        }
+
+        fn buffers_events(&self) -> bool {
+            true
+        }
+
+        fn buffered_slot(&self) -> Option<u64> {
+            self.buffered.lock().unwrap().iter().min().copied()
+        }
+
+        fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+            self.buffered
+                .lock()
+                .unwrap()
+                .extend(events.iter().map(|event| event.slot));
+            Ok(())
+        }
+    }
+
+    fn event(slot: u64) -> InscriptionEvent {
+        InscriptionEvent {
+            slot,
+            signature: Signature::default(),
+            instruction_index: 0,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account: Some(Pubkey::new_unique()),
+            metadata_account: None,
+            mint_account: None,
+            authority: None,
+            accounts: vec![],
+        }
+    }
+
+    fn worker(sinks: Vec<FlakySink>) -> SinkWorker {
+        let tracker = Arc::new(CommitTracker::new(10));
+        tracker.enqueued(11);
+        tracker.rooted(11);
+        SinkWorker {
+            sinks: sinks
+                .into_iter()
+                .map(|sink| Box::new(sink) as Box<dyn InscriptionSink>)
+                .collect(),
+            index: None,
+            batch_size: 1,
+            is_startup_done: false,
+            tracker,
+            failed_events: VecDeque::new(),
+        }
+    }
+
+    fn flaky_sink(keeps_watermark: bool, failures: usize) -> FlakySink {
+        FlakySink {
+            keeps_watermark,
+            failures,
+            written: Arc::default(),
+            committed_slot: Arc::default(),
+        }
+    }
+
+    /// Retry the failed events right away.
+    fn retry_now(worker: &mut SinkWorker, metrics: &PluginMetrics) {
+        for failed_events in worker.failed_events.iter_mut() {
+            failed_events.due = Instant::now();
+        }
+        worker.retry_failed_events(metrics);
+    }
+
+    #[test]
+    fn test_failed_events_are_retried() {
+        let metrics = PluginMetrics::new(1);
+        let sink = flaky_sink(true, 2);
+        let written = sink.written.clone();
+        let committed_slot = sink.committed_slot.clone();
+        let other_sink = flaky_sink(false, 0);
+        let other_written = other_sink.written.clone();
+        let mut worker = worker(vec![sink, other_sink]);
+
+        assert!(worker.write_batch(vec![DbWorkItem::LogEvents(vec![event(11)])], &metrics));
+        // The batch holds the watermark back until it is written.
+        assert_eq!(worker.tracker.preview(&[]), None);
+        assert_eq!(worker.failed_events.len(), 1);
+        assert_eq!(*other_written.lock().unwrap(), vec![11]);
+
+        // Not due yet.
+        worker.retry_failed_events(&metrics);
+        assert_eq!(worker.failed_events.len(), 1);
+
+        retry_now(&mut worker, &metrics);
+        assert_eq!(worker.failed_events.len(), 1);
+        assert_eq!(worker.failed_events[0].attempts, 2);
+        assert!(written.lock().unwrap().is_empty());
+
+        *committed_slot.lock().unwrap() = None;
+        retry_now(&mut worker, &metrics);
+        assert!(worker.failed_events.is_empty());
+        assert_eq!(*written.lock().unwrap(), vec![11]);
+        // Only the failed sink is written to again.
+        assert_eq!(*other_written.lock().unwrap(), vec![11]);
+        assert_eq!(*committed_slot.lock().unwrap(), Some(11));
+        assert_eq!(metrics.committed_slot.load(Ordering::Relaxed), 11);
+        assert_eq!(metrics.events.written.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.events.failed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_failed_events_of_other_sinks_do_not_hold_the_watermark() {
+        let metrics = PluginMetrics::new(1);
+        let sink = flaky_sink(true, 0);
+        let committed_slot = sink.committed_slot.clone();
+        let other_sink = flaky_sink(false, 1);
+        let other_written = other_sink.written.clone();
+        let mut worker = worker(vec![sink, other_sink]);
+
+        assert!(worker.write_batch(vec![DbWorkItem::LogEvents(vec![event(11)])], &metrics));
+        assert_eq!(*committed_slot.lock().unwrap(), Some(11));
+        assert_eq!(metrics.committed_slot.load(Ordering::Relaxed), 11);
+        assert!(!worker.failed_events[0].pending);
+
+        retry_now(&mut worker, &metrics);
+        assert!(worker.failed_events.is_empty());
+        assert_eq!(*other_written.lock().unwrap(), vec![11]);
+    }
+
+    #[test]
+    fn test_buffered_events_hold_the_watermark() {
+        let metrics = PluginMetrics::new(1);
+        let sink = flaky_sink(true, 0);
+        let committed_slot = sink.committed_slot.clone();
+        let buffering_sink = BufferingSink::default();
+        let buffered = buffering_sink.buffered.clone();
+        let mut worker = worker(vec![sink]);
+        worker.sinks.push(Box::new(buffering_sink));
+        worker.tracker.rooted(12);
+
+        // The events of the batch are only in memory once written.
+        assert!(!worker.write_batch(vec![DbWorkItem::LogEvents(vec![event(11)])], &metrics));
+        assert_eq!(*committed_slot.lock().unwrap(), None);
+        assert_eq!(worker.preview(&[]), None);
+
+        // Written to a file, they no longer hold it back.
+        buffered.lock().unwrap().clear();
+        assert_eq!(worker.preview(&[]), Some(12));
+        worker.advance_committed_slot(&metrics);
+        assert_eq!(*committed_slot.lock().unwrap(), Some(12));
+        assert_eq!(metrics.committed_slot.load(Ordering::Relaxed), 12);
+    }
+
+    fn schema_layout(config: serde_json::Value) -> Result<SchemaLayout, GeyserPluginError> {
+        SchemaLayout::new(&serde_json::from_value(config).unwrap())
+    }
+
+    #[test]
+    fn test_schema_layout_render() {
+        let layout = schema_layout(serde_json::json!({})).unwrap();
+        assert_eq!(layout.table("slots"), "public.slots");
+        assert_eq!(
+            layout.render("CREATE TABLE {schema}.{prefix}slots (key {key_type})"),
+            "CREATE TABLE public.slots (key TEXT)"
+        );
+
+        let layout = schema_layout(serde_json::json!({
+            "schema": "indexer",
+            "table_prefix": "devnet_",
+            "key_storage": "binary",
+        }))
+        .unwrap();
+        assert_eq!(layout.table("slots"), "indexer.devnet_slots");
+        assert_eq!(
+            layout.render("CREATE INDEX {prefix}slots_idx ON {schema}.{prefix}slots ({key_type})"),
+            "CREATE INDEX devnet_slots_idx ON indexer.devnet_slots (BYTEA)"
+        );
+    }
+
+    #[test]
+    fn test_schema_layout_identifiers() {
+        assert!(SchemaLayout::is_identifier("inscriptions_2"));
+        assert!(SchemaLayout::is_identifier("_private"));
+        assert!(!SchemaLayout::is_identifier("Inscriptions"));
+        assert!(!SchemaLayout::is_identifier("2nd"));
+        assert!(!SchemaLayout::is_identifier("public; DROP TABLE slots"));
+        assert!(!SchemaLayout::is_identifier("\"quoted\""));
+
+        assert!(schema_layout(serde_json::json!({ "schema": "" })).is_err());
+        assert!(
+            schema_layout(serde_json::json!({ "schema": "a".repeat(MAX_IDENTIFIER_LEN) })).is_ok()
+        );
+        assert!(
+            schema_layout(serde_json::json!({ "schema": "a".repeat(MAX_IDENTIFIER_LEN + 1) }))
+                .is_err()
+        );
+        // An empty prefix is the default.
+        assert!(schema_layout(serde_json::json!({ "table_prefix": "" })).is_ok());
+        assert!(schema_layout(
+            serde_json::json!({ "table_prefix": "a".repeat(MAX_TABLE_PREFIX_LEN + 1) })
+        )
+        .is_err());
+        assert!(schema_layout(serde_json::json!({ "table_prefix": "dev-net_" })).is_err());
+    }
+
+    #[test]
+    fn test_tls_connector() {
+        let config = |value| serde_json::from_value::<GeyserPluginPostgresConfig>(value).unwrap();
+        let connection_str = "host=localhost user=postgres password=secret";
+
+        let plain = config(serde_json::json!({ "connection_str": connection_str }));
+        assert!(SimplePostgresClient::build_tls_connector(&plain)
+            .unwrap()
+            .is_none());
+        let (pg_config, _) = SimplePostgresClient::build_connection_config(&plain).unwrap();
+        assert_eq!(
+            pg_config.get_ssl_mode(),
+            tokio_postgres::config::SslMode::Prefer
+        );
+
+        // Encrypted connections never fall back to plain ones.
+        for ssl_mode in ["require", "verify-ca", "verify-full"] {
+            let config = config(serde_json::json!({
+                "connection_str": connection_str,
+                "ssl_mode": ssl_mode,
+            }));
+            assert!(SimplePostgresClient::build_tls_connector(&config)
+                .unwrap()
+                .is_some());
+            let (pg_config, _) = SimplePostgresClient::build_connection_config(&config).unwrap();
+            assert_eq!(
+                pg_config.get_ssl_mode(),
+                tokio_postgres::config::SslMode::Require
+            );
+        }
+    }
+
+    #[test]
+    fn test_tls_connector_invalid_files() {
+        let config = |value| serde_json::from_value::<GeyserPluginPostgresConfig>(value).unwrap();
+        let missing = tempfile::tempdir().unwrap().path().join("missing.pem");
+        let missing = missing.to_str().unwrap();
+
+        assert!(
+            SimplePostgresClient::build_tls_connector(&config(serde_json::json!({
+                "ssl_mode": "verify-full",
+                "server_ca": missing,
+            })))
+            .is_err()
+        );
+        // The CA is not used by `require`.
+        assert!(
+            SimplePostgresClient::build_tls_connector(&config(serde_json::json!({
+                "ssl_mode": "require",
+                "server_ca": missing,
+            })))
+            .is_ok()
+        );
+        assert!(
+            SimplePostgresClient::build_tls_connector(&config(serde_json::json!({
+                "ssl_mode": "require",
+                "client_cert": missing,
+            })))
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,317 @@
+/// Records the slot ranges the plugin missed in the `backfill_gaps` table,
+/// for a backfill to index them later, and follows the gaps still open in
+/// the metrics. The gaps are written by a background thread with its own
+/// connection, which retries until the database accepts them.
+use {
+    crate::{
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, GeyserPluginPostgresError},
+        metrics::PluginMetrics,
+        postgres_client::{
+            postgres_client_transaction::DbKey, ParallelPostgresClient, SchemaLayout,
+            SimplePostgresClient,
+        },
+    },
+    chrono::Utc,
+    crossbeam_channel::{unbounded, RecvTimeoutError, Sender},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    domichain_sdk::pubkey::Pubkey,
+    log::*,
+    postgres::{Client, GenericClient},
+    std::{
+        sync::{atomic::Ordering, Arc},
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Records a gap, extending a gap of the same start if any, and opening it
+/// again.
+const GAP_UPSERT_STMT: &str =
+    "INSERT INTO {schema}.{prefix}backfill_gaps AS gaps (program_id, start_slot, end_slot, detected_on) \
+    VALUES ($1, $2, $3, $4) ON CONFLICT (program_id, start_slot) DO UPDATE SET \
+        end_slot=GREATEST(gaps.end_slot, excluded.end_slot), \
+        detected_on=excluded.detected_on, \
+        filled_on=NULL";
+
+fn error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError { msg }))
+}
+
+/// A range of slots, both ends included, missing from the index.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BackfillGap {
+    pub start_slot: u64,
+    pub end_slot: u64,
+}
+
+impl BackfillGap {
+    pub fn slot_count(&self) -> u64 {
+        self.end_slot - self.start_slot + 1
+    }
+}
+
+pub fn record_gap(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+    program_id: &Pubkey,
+    gap: &BackfillGap,
+) -> Result<(), GeyserPluginError> {
+    client
+        .execute(
+            &layout.render(GAP_UPSERT_STMT),
+            &[
+                &DbKey::pubkey(program_id, layout.key_storage()),
+                &(gap.start_slot as i64),
+                &(gap.end_slot as i64),
+                &Utc::now().naive_utc(),
+            ],
+        )
+        .map(|_| ())
+        .map_err(|err| {
+            error(format!(
+                "Failed to record the backfill gap of the slots {} to {}: {}",
+                gap.start_slot, gap.end_slot, err
+            ))
+        })
+}
+
+/// The gaps of `program_id` which are not filled yet, by start slot.
+pub fn open_gaps(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+    program_id: &Pubkey,
+) -> Result<Vec<BackfillGap>, GeyserPluginError> {
+    let rows = client
+        .query(
+            &layout.render(
+                "SELECT start_slot, end_slot FROM {schema}.{prefix}backfill_gaps \
+                WHERE program_id = $1 AND filled_on IS NULL ORDER BY start_slot",
+            ),
+            &[&DbKey::pubkey(program_id, layout.key_storage())],
+        )
+        .map_err(|err| error(format!("Failed to read the backfill gaps: {}", err)))?;
+    Ok(rows
+        .iter()
+        .map(|row| BackfillGap {
+            start_slot: row.get::<_, i64>(0) as u64,
+            end_slot: row.get::<_, i64>(1) as u64,
+        })
+        .collect())
+}
+
+/// Mark the open gaps of `program_id` lying within the slots `start_slot`
+/// to `end_slot` as filled, returning how many were.
+pub fn mark_gaps_filled(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+    program_id: &Pubkey,
+    start_slot: u64,
+    end_slot: u64,
+) -> Result<u64, GeyserPluginError> {
+    client
+        .execute(
+            &layout.render(
+                "UPDATE {schema}.{prefix}backfill_gaps SET filled_on = $4 \
+                WHERE program_id = $1 AND filled_on IS NULL \
+                    AND start_slot >= $2 AND end_slot <= $3",
+            ),
+            &[
+                &DbKey::pubkey(program_id, layout.key_storage()),
+                &(start_slot as i64),
+                &(end_slot as i64),
+                &Utc::now().naive_utc(),
+            ],
+        )
+        .map_err(|err| {
+            error(format!(
+                "Failed to mark the backfill gaps as filled: {}",
+                err
+            ))
+        })
+}
+
+struct GapRecorder {
+    config: GeyserPluginPostgresConfig,
+    layout: SchemaLayout,
+    program_id: Pubkey,
+    /// Connected on first use, and again once closed.
+    client: Option<Client>,
+    /// The gaps detected and not recorded yet.
+    pending: Vec<BackfillGap>,
+    metrics: Arc<PluginMetrics>,
+}
+
+/// The connection `client`, connecting it first if it is not connected.
+fn connect<'a>(
+    client: &'a mut Option<Client>,
+    config: &GeyserPluginPostgresConfig,
+) -> Result<&'a mut Client, GeyserPluginError> {
+    if client
+        .as_ref()
+        .filter(|client| !client.is_closed())
+        .is_none()
+    {
+        *client = Some(SimplePostgresClient::connect_to_db(config)?);
+    }
+    Ok(client.as_mut().unwrap())
+}
+
+impl GapRecorder {
+    fn record_pending(&mut self) -> Result<(), GeyserPluginError> {
+        while let Some(gap) = self.pending.first().copied() {
+            let client = connect(&mut self.client, &self.config)?;
+            record_gap(client, &self.layout, &self.program_id, &gap)?;
+            info!(
+                "Recorded the backfill gap of the slots {} to {}",
+                gap.start_slot, gap.end_slot
+            );
+            self.pending.remove(0);
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self) -> Result<(), GeyserPluginError> {
+        let client = connect(&mut self.client, &self.config)?;
+        let gaps = open_gaps(client, &self.layout, &self.program_id)?;
+        self.metrics
+            .open_backfill_gaps
+            .store(gaps.len() as u64, Ordering::Relaxed);
+        self.metrics.open_backfill_gap_slots.store(
+            gaps.iter().map(BackfillGap::slot_count).sum(),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+
+    fn maintain(&mut self) {
+        if let Err(err) = self.record_pending().and_then(|_| self.refresh()) {
+            error!("{}", err);
+            self.metrics.record_db_error();
+        }
+    }
+}
+
+/// The gaps of the index, recorded and followed by a background thread.
+pub struct BackfillGaps {
+    sender: Option<Sender<BackfillGap>>,
+    thread: Option<JoinHandle<()>>,
+    metrics: Arc<PluginMetrics>,
+}
+
+impl BackfillGaps {
+    pub fn new(
+        config: &GeyserPluginPostgresConfig,
+        metrics: Arc<PluginMetrics>,
+    ) -> Result<Self, GeyserPluginError> {
+        let mut recorder = GapRecorder {
+            config: config.clone(),
+            layout: SchemaLayout::new(config)?,
+            program_id: ParallelPostgresClient::parse_program_id(config)?,
+            client: None,
+            pending: Vec::new(),
+            metrics: metrics.clone(),
+        };
+        let (sender, receiver) = unbounded();
+        let thread = Builder::new()
+            .name("backfill-gaps".to_string())
+            .spawn(move || loop {
+                recorder.maintain();
+                match receiver.recv_timeout(REFRESH_INTERVAL) {
+                    Ok(gap) => recorder.pending.push(gap),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        if !recorder.pending.is_empty() {
+                            recorder.maintain();
+                        }
+                        break;
+                    }
+                }
+            })
+            .map_err(|err| error(format!("Failed to start the backfill gap thread: {}", err)))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            metrics,
+        })
+    }
+
+    /// Report the slots `start_slot` to `end_slot` as missing and have them
+    /// recorded.
+    pub fn record(&self, start_slot: u64, end_slot: u64) {
+        let gap = BackfillGap {
+            start_slot,
+            end_slot,
+        };
+        error!(
+            "The slots {} to {} were not indexed, recording them for a backfill",
+            start_slot, end_slot
+        );
+        self.metrics.detected_gaps.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .detected_gap_slots
+            .fetch_add(gap.slot_count(), Ordering::Relaxed);
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(gap);
+        }
+    }
+}
+
+impl Drop for BackfillGaps {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The backfill gap thread has panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    #[test]
+    fn test_gap_slot_count() {
+        let gap = BackfillGap {
+            start_slot: 10,
+            end_slot: 10,
+        };
+        assert_eq!(gap.slot_count(), 1);
+        let gap = BackfillGap {
+            start_slot: 10,
+            end_slot: 19,
+        };
+        assert_eq!(gap.slot_count(), 10);
+    }
+
+    #[test]
+    fn test_gap_upsert_matches_the_table() {
+        // The upsert merges on the primary key of the table.
+        let migration = include_str!("../../migrations/0005_backfill_gaps.sql");
+        assert!(migration.contains("PRIMARY KEY (program_id, start_slot)"));
+        assert!(GAP_UPSERT_STMT.contains("ON CONFLICT (program_id, start_slot)"));
+        // A gap detected again is extended, never shrunk, and reopened.
+        assert!(GAP_UPSERT_STMT.contains("end_slot=GREATEST(gaps.end_slot, excluded.end_slot)"));
+        assert!(GAP_UPSERT_STMT.contains("filled_on=NULL"));
+    }
+
+    #[test]
+    fn test_record_without_database() {
+        // No database can be connected to, so the gaps stay pending.
+        let config = serde_json::from_value(json!({})).unwrap();
+        let metrics = Arc::new(PluginMetrics::new(1));
+        let gaps = BackfillGaps::new(&config, metrics.clone()).unwrap();
+        gaps.record(10, 19);
+        gaps.record(30, 30);
+        assert_eq!(metrics.detected_gaps.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.detected_gap_slots.load(Ordering::Relaxed), 11);
+
+        // Dropping it does not wait for the database.
+        drop(gaps);
+        assert!(metrics.last_db_error_unix_secs.load(Ordering::Relaxed) > 0);
+    }
+}
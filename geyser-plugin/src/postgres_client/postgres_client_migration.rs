@@ -0,0 +1,281 @@
+/// The versioned migrations of the PostgreSQL schema, embedded in the plugin.
+/// The versions applied to a database are recorded in its `schema_version`
+/// table. The migrations are templates rendered with the `SchemaLayout` of the
+/// configuration.
+use {
+    crate::{geyser_plugin_postgres::GeyserPluginPostgresError, postgres_client::SchemaLayout},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    log::*,
+    postgres::{Client, GenericClient},
+};
+
+/// A step of the schema, applied in a transaction.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// The migrations, in the order they are applied.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("../../migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "partition inscription_events by slot range",
+        sql: include_str!("../../migrations/0002_partition_inscription_events.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "base58 helper functions and views",
+        sql: include_str!("../../migrations/0003_base58_helpers.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "indexer state watermark",
+        sql: include_str!("../../migrations/0004_indexer_state.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "backfill gaps",
+        sql: include_str!("../../migrations/0005_backfill_gaps.sql"),
+    },
+];
+
+/// The schema version the plugin writes to.
+pub const SCHEMA_VERSION: i32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+const CREATE_SCHEMA_VERSION_TABLE: &str = "CREATE SCHEMA IF NOT EXISTS {schema};
+CREATE TABLE IF NOT EXISTS {schema}.{prefix}schema_version (
+    version INT NOT NULL,
+    description TEXT NOT NULL,
+    applied_on TIMESTAMP NOT NULL,
+    CONSTRAINT {prefix}schema_version_pk PRIMARY KEY (version)
+)";
+
+/// The key of the advisory lock serializing the plugin instances migrating
+/// the same database, whatever their tables.
+const MIGRATION_LOCK_KEY: i64 = 0x696e_7363_7269_7074;
+
+fn error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError { msg }))
+}
+
+/// The latest migration applied to the database, 0 when the database has no
+/// `schema_version` table.
+pub fn current_version(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+) -> Result<i32, postgres::Error> {
+    let table = layout.table("schema_version");
+    let exists: bool = client
+        .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?
+        .get(0);
+    if !exists {
+        return Ok(0);
+    }
+    client
+        .query_one(
+            &format!("SELECT COALESCE(MAX(version), 0) FROM {}", table),
+            &[],
+        )
+        .map(|row| row.get(0))
+}
+
+/// Check the key columns of the tables have the type of the configured
+/// `key_storage`, which cannot be changed once the tables are created.
+pub fn check_key_storage(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+) -> Result<(), GeyserPluginError> {
+    let column_type: Option<String> = client
+        .query_opt(
+            "SELECT format_type(atttypid, atttypmod) FROM pg_attribute \
+            WHERE attrelid = to_regclass($1) AND attname = 'account'",
+            &[&layout.table("inscriptions")],
+        )
+        .map_err(|err| {
+            error(format!(
+                "Failed to read the type of the key columns: {}",
+                err
+            ))
+        })?
+        .map(|row| row.get(0));
+    let expected_type = layout.key_storage().column_type().to_lowercase();
+    match column_type {
+        Some(column_type) if column_type != expected_type => Err(error(format!(
+            "The tables store keys as {} but \"key_storage\": {:?} requires {}",
+            column_type,
+            layout.key_storage(),
+            expected_type
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The migrations to apply to a database at `version`, none when it is at
+/// `SCHEMA_VERSION`. A database behind is refused unless `auto_migrate` is
+/// set, and one ahead of the plugin always is.
+fn pending_migrations(
+    version: i32,
+    auto_migrate: bool,
+) -> Result<&'static [Migration], GeyserPluginError> {
+    if version > SCHEMA_VERSION {
+        return Err(error(format!(
+            "The database schema is at version {}, ahead of the version {} this plugin \
+            supports. Upgrade the plugin.",
+            version, SCHEMA_VERSION
+        )));
+    }
+    let pending = &MIGRATIONS[MIGRATIONS.partition_point(|m| m.version <= version)..];
+    if !pending.is_empty() && !auto_migrate {
+        return Err(error(format!(
+            "The database schema is at version {}, behind the version {} this plugin \
+            requires. Set \"auto_migrate\" to true to apply the pending migrations.",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(pending)
+}
+
+/// Check the database is at `SCHEMA_VERSION`. When it is behind, the pending
+/// migrations are applied if `auto_migrate` is set, in a single transaction,
+/// and refused otherwise. A database ahead of the plugin is always refused.
+pub fn prepare_schema(
+    client: &mut Client,
+    layout: &SchemaLayout,
+    auto_migrate: bool,
+) -> Result<(), GeyserPluginError> {
+    let mut transaction = client.transaction().map_err(|err| {
+        error(format!(
+            "Failed to start the migration transaction: {}",
+            err
+        ))
+    })?;
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .map_err(|err| error(format!("Failed to lock the schema: {}", err)))?;
+    let version = current_version(&mut transaction, layout)
+        .map_err(|err| error(format!("Failed to read the schema version: {}", err)))?;
+
+    let pending = pending_migrations(version, auto_migrate)?;
+    if pending.is_empty() {
+        info!("The database schema is at version {}", version);
+        return check_key_storage(&mut transaction, layout);
+    }
+
+    transaction
+        .batch_execute(&layout.render(CREATE_SCHEMA_VERSION_TABLE))
+        .map_err(|err| {
+            error(format!(
+                "Failed to create the schema_version table: {}",
+                err
+            ))
+        })?;
+    for migration in pending {
+        info!(
+            "Applying the schema migration {}: {}",
+            migration.version, migration.description
+        );
+        transaction
+            .batch_execute(&layout.render(migration.sql))
+            .map_err(|err| {
+                error(format!(
+                    "Failed to apply the schema migration {}: {}",
+                    migration.version, err
+                ))
+            })?;
+        transaction
+            .execute(
+                &layout.render(
+                    "INSERT INTO {schema}.{prefix}schema_version \
+                        (version, description, applied_on) VALUES ($1, $2, now())",
+                ),
+                &[&migration.version, &migration.description],
+            )
+            .map_err(|err| {
+                error(format!(
+                    "Failed to record the schema migration {}: {}",
+                    migration.version, err
+                ))
+            })?;
+    }
+    check_key_storage(&mut transaction, layout)?;
+    transaction
+        .commit()
+        .map_err(|err| error(format!("Failed to commit the schema migrations: {}", err)))?;
+    info!("Migrated the database schema to version {}", SCHEMA_VERSION);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::geyser_plugin_postgres::GeyserPluginPostgresConfig};
+
+    fn versions(migrations: &[Migration]) -> Vec<i32> {
+        migrations
+            .iter()
+            .map(|migration| migration.version)
+            .collect()
+    }
+
+    #[test]
+    fn test_migrations_are_consecutive() {
+        assert_eq!(
+            versions(MIGRATIONS),
+            (1..=SCHEMA_VERSION).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        assert_eq!(
+            versions(pending_migrations(0, true).unwrap()),
+            versions(MIGRATIONS)
+        );
+        assert_eq!(
+            versions(pending_migrations(SCHEMA_VERSION - 1, true).unwrap()),
+            [SCHEMA_VERSION]
+        );
+        assert!(pending_migrations(SCHEMA_VERSION, true).unwrap().is_empty());
+        // A database at the current version needs no migration to be allowed.
+        assert!(pending_migrations(SCHEMA_VERSION, false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pending_migrations_refused() {
+        let err = pending_migrations(SCHEMA_VERSION - 1, false).unwrap_err();
+        assert!(err.to_string().contains("auto_migrate"));
+        // A database ahead of the plugin is refused even with auto_migrate.
+        let err = pending_migrations(SCHEMA_VERSION + 1, true).unwrap_err();
+        assert!(err.to_string().contains("Upgrade the plugin"));
+    }
+
+    #[test]
+    fn test_migrations_render() {
+        let config: GeyserPluginPostgresConfig = serde_json::from_value(serde_json::json!({
+            "schema": "indexer",
+            "table_prefix": "test_",
+        }))
+        .unwrap();
+        let layout = SchemaLayout::new(&config).unwrap();
+        for sql in MIGRATIONS
+            .iter()
+            .map(|migration| migration.sql)
+            .chain([CREATE_SCHEMA_VERSION_TABLE])
+        {
+            let sql = layout.render(sql);
+            for placeholder in ["{schema}", "{prefix}", "{key_type}"] {
+                assert!(!sql.contains(placeholder));
+            }
+        }
+        assert!(layout
+            .render(CREATE_SCHEMA_VERSION_TABLE)
+            .contains("indexer.test_schema_version"));
+    }
+}
@@ -0,0 +1,456 @@
+/// Maintains the slot range partitions of the `inscription_events` table.
+/// A background thread creates the partitions `partitions_ahead` ahead of the
+/// highest slot seen, and is woken up as soon as a slot gets close to the
+/// last partition, so the validator never waits on DDL. With a retention
+/// window, partitions whose slots are all older than it are detached.
+use {
+    crate::{
+        geyser_plugin_postgres::{GeyserPluginPostgresConfig, GeyserPluginPostgresError},
+        postgres_client::{SchemaLayout, SimplePostgresClient},
+    },
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender, TrySendError},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    log::*,
+    postgres::Client,
+    std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const DEFAULT_PARTITION_SLOTS: u64 = 432_000;
+const DEFAULT_PARTITIONS_AHEAD: u64 = 2;
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10);
+
+fn error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError { msg }))
+}
+
+fn configuration_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::ConfigurationError {
+        msg,
+    }))
+}
+
+/// Check the partitioning options, returning the number of slots of a
+/// partition.
+pub(crate) fn check_config(config: &GeyserPluginPostgresConfig) -> Result<u64, String> {
+    let partition_slots = config.partition_slots.unwrap_or(DEFAULT_PARTITION_SLOTS);
+    if partition_slots == 0 {
+        return Err("\"partition_slots\" must be greater than 0".to_string());
+    }
+    if let Some(archive_schema) = &config.partition_archive_schema {
+        if archive_schema.is_empty() || !SchemaLayout::is_identifier(archive_schema) {
+            return Err(format!(
+                "\"partition_archive_schema\": {:?} must be a lowercase identifier",
+                archive_schema
+            ));
+        }
+    }
+    Ok(partition_slots)
+}
+
+/// The slot ranges, the end excluded, of the partitions to create after
+/// `covered_until` up to the one holding `target_slot`. The slots before the
+/// partition holding `slot` share the first range.
+fn partition_ranges(
+    covered_until: u64,
+    slot: u64,
+    target_slot: u64,
+    partition_slots: u64,
+) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut start = covered_until;
+    while start <= target_slot {
+        let end = ((start / partition_slots + 1) * partition_slots)
+            .max(slot / partition_slots * partition_slots);
+        ranges.push((start, end));
+        start = end;
+    }
+    ranges
+}
+
+struct PartitionState {
+    config: GeyserPluginPostgresConfig,
+    layout: SchemaLayout,
+    partition_slots: u64,
+    partitions_ahead: u64,
+    retention_slots: Option<u64>,
+    archive_schema: Option<String>,
+    /// Connected on first use, and again once closed.
+    client: Mutex<Option<Client>>,
+    /// The end, excluded, of the last partition.
+    covered_until: AtomicU64,
+    highest_slot: AtomicU64,
+    rooted_slot: AtomicU64,
+}
+
+impl PartitionState {
+    fn with_client<T>(
+        &self,
+        f: impl FnOnce(&mut Client) -> Result<T, GeyserPluginError>,
+    ) -> Result<T, GeyserPluginError> {
+        let mut client = self.client.lock().unwrap();
+        if client
+            .as_ref()
+            .filter(|client| !client.is_closed())
+            .is_none()
+        {
+            *client = Some(SimplePostgresClient::connect_to_db(&self.config)?);
+        }
+        f(client.as_mut().unwrap())
+    }
+
+    /// Create the partitions up to the one holding `target_slot`. The slots
+    /// between the last partition and the one holding `slot`, if any, get a
+    /// single partition, so that a validator starting far ahead of the
+    /// database does not create one partition per skipped range.
+    fn create_partitions(&self, slot: u64, target_slot: u64) -> Result<(), GeyserPluginError> {
+        self.with_client(|client| {
+            let covered_until = self.covered_until.load(Ordering::Relaxed);
+            for (start, end) in
+                partition_ranges(covered_until, slot, target_slot, self.partition_slots)
+            {
+                self.create_partition(client, start, end)?;
+                self.covered_until.store(end, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+    }
+
+    fn create_partition(
+        &self,
+        client: &mut Client,
+        start: u64,
+        end: u64,
+    ) -> Result<(), GeyserPluginError> {
+        let name = self
+            .layout
+            .render(&format!("{{prefix}}inscription_events_{}", start));
+        let statement = self.layout.render(&format!(
+            "CREATE TABLE IF NOT EXISTS {{schema}}.{} \
+            PARTITION OF {{schema}}.{{prefix}}inscription_events \
+            FOR VALUES FROM ({}) TO ({})",
+            name, start, end
+        ));
+        let result = client.transaction().and_then(|mut transaction| {
+            transaction.batch_execute(&statement)?;
+            transaction.execute(
+                &self.layout.render(
+                    "INSERT INTO {schema}.{prefix}event_partitions \
+                        (name, start_slot, end_slot, created_on) \
+                    VALUES ($1, $2, $3, now()) ON CONFLICT (name) DO NOTHING",
+                ),
+                &[&name, &(start as i64), &(end as i64)],
+            )?;
+            transaction.commit()
+        });
+        match result {
+            Ok(()) => {
+                info!(
+                    "Created the partition {} of slots [{}, {})",
+                    name, start, end
+                );
+                Ok(())
+            }
+            Err(err) => Err(error(format!(
+                "Failed to create the partition {} of slots [{}, {}): {}",
+                name, start, end, err
+            ))),
+        }
+    }
+
+    /// Detach the partitions whose slots are all older than the retention
+    /// window, moving them to `archive_schema` if set.
+    fn detach_partitions(&self, cutoff_slot: u64) -> Result<(), GeyserPluginError> {
+        self.with_client(|client| {
+            let names: Vec<String> = client
+                .query(
+                    &self.layout.render(
+                        "SELECT name FROM {schema}.{prefix}event_partitions \
+                        WHERE detached_on IS NULL AND end_slot <= $1 ORDER BY start_slot",
+                    ),
+                    &[&(cutoff_slot as i64)],
+                )
+                .map_err(|err| error(format!("Failed to list the partitions: {}", err)))?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+
+            for name in names {
+                self.detach_partition(client, &name)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn detach_partition(&self, client: &mut Client, name: &str) -> Result<(), GeyserPluginError> {
+        let schema = self.layout.schema();
+        let mut statements = self.layout.render(&format!(
+            "ALTER TABLE {{schema}}.{{prefix}}inscription_events \
+            DETACH PARTITION {{schema}}.{};",
+            name
+        ));
+        let archived_as = match &self.archive_schema {
+            Some(archive_schema) => {
+                statements.push_str(&format!(
+                    "CREATE SCHEMA IF NOT EXISTS {}; ALTER TABLE {}.{} SET SCHEMA {};",
+                    archive_schema, schema, name, archive_schema
+                ));
+                format!("{}.{}", archive_schema, name)
+            }
+            None => format!("{}.{}", schema, name),
+        };
+        let result = client.transaction().and_then(|mut transaction| {
+            transaction.batch_execute(&statements)?;
+            transaction.execute(
+                &self.layout.render(
+                    "UPDATE {schema}.{prefix}event_partitions \
+                    SET detached_on = now(), archived_as = $2 WHERE name = $1",
+                ),
+                &[&name, &archived_as],
+            )?;
+            transaction.commit()
+        });
+        match result {
+            Ok(()) => {
+                info!("Detached the partition {} as {}", name, archived_as);
+                Ok(())
+            }
+            Err(err) => Err(error(format!(
+                "Failed to detach the partition {}: {}",
+                name, err
+            ))),
+        }
+    }
+
+    fn maintain(&self) {
+        let highest_slot = self.highest_slot.load(Ordering::Relaxed);
+        // Nothing is known of the slots of the validator before its first
+        // notification.
+        if highest_slot > 0 {
+            let target_slot = highest_slot + self.partitions_ahead * self.partition_slots;
+            if target_slot >= self.covered_until.load(Ordering::Relaxed) {
+                if let Err(err) = self.create_partitions(highest_slot, target_slot) {
+                    error!("{}", err);
+                }
+            }
+        }
+
+        let rooted_slot = self.rooted_slot.load(Ordering::Relaxed);
+        if let Some(cutoff_slot) = self
+            .retention_slots
+            .and_then(|retention_slots| rooted_slot.checked_sub(retention_slots))
+        {
+            if let Err(err) = self.detach_partitions(cutoff_slot) {
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+/// The partitions of the `inscription_events` table, maintained by a
+/// background thread with its own connection.
+pub struct EventPartitions {
+    state: Arc<PartitionState>,
+    /// Wakes the maintenance thread up, which exits once it is dropped.
+    wake_sender: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EventPartitions {
+    pub fn new(config: &GeyserPluginPostgresConfig) -> Result<Self, GeyserPluginError> {
+        let partition_slots = check_config(config).map_err(configuration_error)?;
+
+        let layout = SchemaLayout::new(config)?;
+        let mut client = SimplePostgresClient::connect_to_db(config)?;
+        let covered_until: Option<i64> = client
+            .query_one(
+                &layout.render("SELECT MAX(end_slot) FROM {schema}.{prefix}event_partitions"),
+                &[],
+            )
+            .map_err(|err| error(format!("Failed to read the partitions: {}", err)))?
+            .get(0);
+
+        let state = Arc::new(PartitionState {
+            config: config.clone(),
+            layout,
+            partition_slots,
+            partitions_ahead: config.partitions_ahead.unwrap_or(DEFAULT_PARTITIONS_AHEAD),
+            retention_slots: config.partition_retention_slots,
+            archive_schema: config.partition_archive_schema.clone(),
+            client: Mutex::new(Some(client)),
+            covered_until: AtomicU64::new(covered_until.unwrap_or(0) as u64),
+            highest_slot: AtomicU64::new(0),
+            rooted_slot: AtomicU64::new(0),
+        });
+        let (wake_sender, wake_receiver) = bounded(1);
+        let thread = Builder::new()
+            .name("event-partitions".to_string())
+            .spawn({
+                let state = state.clone();
+                move || loop {
+                    match wake_receiver.recv_timeout(MAINTENANCE_INTERVAL) {
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => state.maintain(),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })
+            .map_err(|err| {
+                error(format!(
+                    "Failed to start the partition maintenance thread: {}",
+                    err
+                ))
+            })?;
+
+        Ok(Self {
+            state,
+            wake_sender: Some(wake_sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Record that the events of `slot` are about to be written, waking the
+    /// maintenance thread up when `slot` gets within `partitions_ahead`
+    /// partitions of the last one. It never blocks: called from the
+    /// notifications of the validator, it leaves the DDL to that thread.
+    pub fn cover(&self, slot: u64) {
+        let state = &self.state;
+        state.highest_slot.fetch_max(slot, Ordering::Relaxed);
+        let target_slot = slot + state.partitions_ahead * state.partition_slots;
+        if target_slot < state.covered_until.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(wake_sender) = &self.wake_sender {
+            match wake_sender.try_send(()) {
+                // A wake up is pending already.
+                Ok(()) | Err(TrySendError::Full(())) => {}
+                Err(TrySendError::Disconnected(())) => {
+                    error!("The partition maintenance thread has exited")
+                }
+            }
+        }
+    }
+
+    /// Create the missing partitions up to the one holding `slot` right
+    /// away, for the writers which can wait on them, e.g. the backfill.
+    pub fn cover_now(&self, slot: u64) -> Result<(), GeyserPluginError> {
+        let state = &self.state;
+        state.highest_slot.fetch_max(slot, Ordering::Relaxed);
+        if slot < state.covered_until.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        state.create_partitions(slot, slot + state.partitions_ahead * state.partition_slots)
+    }
+
+    /// The detached partitions holding slots of `[start_slot, end_slot]`, by
+    /// their first slot: `(archived_as, start_slot, end_slot)`, the end
+    /// excluded. Events of these slots cannot be written any more.
+    pub fn detached_partitions(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<Vec<(String, u64, u64)>, GeyserPluginError> {
+        let state = &self.state;
+        state.with_client(|client| {
+            client
+                .query(
+                    &state.layout.render(
+                        "SELECT COALESCE(archived_as, name), start_slot, end_slot \
+                        FROM {schema}.{prefix}event_partitions \
+                        WHERE detached_on IS NOT NULL AND start_slot <= $2 AND end_slot > $1 \
+                        ORDER BY start_slot",
+                    ),
+                    &[
+                        &(start_slot.min(i64::MAX as u64) as i64),
+                        &(end_slot.min(i64::MAX as u64) as i64),
+                    ],
+                )
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| {
+                            (
+                                row.get(0),
+                                row.get::<_, i64>(1) as u64,
+                                row.get::<_, i64>(2) as u64,
+                            )
+                        })
+                        .collect()
+                })
+                .map_err(|err| error(format!("Failed to list the partitions: {}", err)))
+        })
+    }
+
+    pub fn update_slot_status(&self, slot: u64, status: &SlotStatus) {
+        self.state.highest_slot.fetch_max(slot, Ordering::Relaxed);
+        if *status == SlotStatus::Rooted {
+            self.state.rooted_slot.fetch_max(slot, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for EventPartitions {
+    fn drop(&mut self) {
+        drop(self.wake_sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The partition maintenance thread has panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    #[test]
+    fn test_partition_ranges() {
+        assert_eq!(
+            partition_ranges(200, 250, 450, 100),
+            [(200, 300), (300, 400), (400, 500)]
+        );
+        // Covered already.
+        assert!(partition_ranges(500, 250, 450, 100).is_empty());
+        // A range starting off a boundary ends on the next one.
+        assert_eq!(partition_ranges(250, 250, 250, 100), [(250, 300)]);
+    }
+
+    #[test]
+    fn test_partition_ranges_skip_ahead() {
+        // The slots skipped by a validator starting far ahead of the
+        // database get a single partition.
+        assert_eq!(
+            partition_ranges(0, 1_000_050, 1_000_250, 100),
+            [
+                (0, 1_000_000),
+                (1_000_000, 1_000_100),
+                (1_000_100, 1_000_200),
+                (1_000_200, 1_000_300),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_config() {
+        let config = |value| serde_json::from_value::<GeyserPluginPostgresConfig>(value).unwrap();
+        assert_eq!(
+            check_config(&config(json!({}))),
+            Ok(DEFAULT_PARTITION_SLOTS)
+        );
+        assert_eq!(
+            check_config(&config(json!({
+                "partition_slots": 1000,
+                "partition_archive_schema": "archive",
+            }))),
+            Ok(1000)
+        );
+        assert!(check_config(&config(json!({ "partition_slots": 0 }))).is_err());
+        assert!(check_config(&config(json!({ "partition_archive_schema": "Archive" }))).is_err());
+        assert!(check_config(&config(json!({ "partition_archive_schema": "" }))).is_err());
+    }
+}
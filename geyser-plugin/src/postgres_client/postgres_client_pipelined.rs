@@ -0,0 +1,821 @@
+/// Module implementing the pipelined writer: a small tokio runtime driving a
+/// handful of `tokio_postgres` connections. Every batch of events is sharded
+/// over the connections by inscription account, each writing its share in
+/// one transaction with all statements issued concurrently, which lets
+/// `tokio_postgres` pipeline them instead of waiting for a round trip per
+/// statement. The events of one account stay on one connection, so the
+/// transactions of a batch never contend for the same `inscriptions` row.
+/// The transaction of the last shard is committed after the others, along
+/// with the watermark. Slot updates are held until the end of the batch and
+/// sent at once over a single connection, which keeps them in order.
+use {
+    crate::{
+        geyser_plugin_postgres::{
+            GeyserPluginPostgresConfig, GeyserPluginPostgresError, KeyStorage,
+        },
+        inscription_sink::InscriptionSink,
+        metrics::{PluginMetrics, WorkerState},
+        postgres_client::{
+            postgres_client_transaction::{
+                DbEventRow, DbKey, InscriptionEvent, NotifyPayload, INSCRIPTION_EVENT_INSERT_STMT,
+                INSCRIPTION_UPSERT_STMT, NOTIFY_STMT, SLOT_UPSERT_STMT,
+            },
+            postgres_client_watermark::COMMITTED_SLOT_UPSERT_STMT,
+            ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
+    },
+    chrono::{NaiveDateTime, Utc},
+    domichain_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, SlotStatus},
+    domichain_sdk::signature::Signature,
+    futures::future::{join, join_all, try_join_all},
+    log::*,
+    std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+    tokio::{
+        runtime::{Builder, Runtime},
+        task::JoinHandle,
+    },
+    tokio_postgres::{Client, Statement, Transaction},
+};
+
+/// The number of runtime threads driving the connections.
+const PIPELINED_RUNTIME_THREADS: usize = 2;
+/// How long a connection which could not be opened waits before the next
+/// attempt.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct PipelinedStatements {
+    update_inscription_log_stmt: Statement,
+    insert_inscription_event_stmt: Statement,
+    update_slot_stmt: Statement,
+    update_committed_slot_stmt: Statement,
+    notify_stmt: Option<Statement>,
+}
+
+struct PipelinedConnection {
+    client: Client,
+    statements: PipelinedStatements,
+}
+
+/// One of the `connections` of the writer, which is retried while it could
+/// not be opened.
+struct ConnectionSlot {
+    /// The index the connection is reported under.
+    index: usize,
+    connection: Option<PipelinedConnection>,
+    /// The attempt to open the connection running on the runtime, which the
+    /// batches do not wait for.
+    connecting: Option<JoinHandle<Result<PipelinedConnection, GeyserPluginError>>>,
+    last_attempt: Instant,
+}
+
+pub(crate) struct PipelinedPostgresWriter {
+    runtime: Runtime,
+    config: GeyserPluginPostgresConfig,
+    connections: Vec<ConnectionSlot>,
+    metrics: Arc<PluginMetrics>,
+    /// The `program_id` of the `indexer_state` row.
+    program_key: DbKey,
+    /// The watermark to write once the next events are, or on flush.
+    committed_slot: Option<u64>,
+    /// The `slot`, `parent` and `status` of the slot updates to write on
+    /// flush.
+    pending_slots: Vec<(i64, Option<i64>, &'static str)>,
+    /// The events committed by a batch which partly failed, until it is
+    /// retried.
+    committed_events: HashSet<EventKey>,
+}
+
+/// The `signature` and `instruction_index` of an event.
+type EventKey = (Signature, u32);
+
+impl PipelinedPostgresWriter {
+    /// Open up to `connection_count` connections, bumping
+    /// `initialized_worker_count` or `failed_worker_count` for every one of
+    /// them. Fails only when no connection could be established; the others
+    /// are retried before the batches.
+    pub(crate) fn new(
+        config: &GeyserPluginPostgresConfig,
+        connection_count: usize,
+        metrics: Arc<PluginMetrics>,
+        initialized_worker_count: &AtomicUsize,
+        failed_worker_count: &AtomicUsize,
+    ) -> Result<Self, GeyserPluginError> {
+        info!("Creating PipelinedPostgresWriter...");
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(PIPELINED_RUNTIME_THREADS)
+            .thread_name("pg-pipelined")
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                GeyserPluginError::Custom(Box::new(
+                    GeyserPluginPostgresError::DataStoreConnectionError {
+                        msg: format!("Failed to create the tokio runtime: ({})", err),
+                    },
+                ))
+            })?;
+
+        let mut connections = Vec::with_capacity(connection_count);
+        for i in 0..connection_count {
+            let connection = match runtime.block_on(Self::connect(config)) {
+                Ok(connection) => {
+                    initialized_worker_count.fetch_add(1, Ordering::Relaxed);
+                    metrics.set_worker_state(i, WorkerState::Connected);
+                    Some(connection)
+                }
+                Err(err) => {
+                    error!("Error when making connection to database: ({})", err);
+                    failed_worker_count.fetch_add(1, Ordering::Relaxed);
+                    metrics.connection_errors.fetch_add(1, Ordering::Relaxed);
+                    metrics.record_db_error();
+                    metrics.set_worker_state(i, WorkerState::Failed);
+                    None
+                }
+            };
+            connections.push(ConnectionSlot {
+                index: i,
+                connection,
+                connecting: None,
+                last_attempt: Instant::now(),
+            });
+        }
+
+        let connected = connections
+            .iter()
+            .filter(|slot| slot.connection.is_some())
+            .count();
+        if connected == 0 {
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginPostgresError::DataStoreConnectionError {
+                    msg: "The pipelined writer could not open any connection to the PostgreSQL database."
+                        .to_string(),
+                },
+            )));
+        }
+
+        info!(
+            "Created PipelinedPostgresWriter with {} of {} connections.",
+            connected,
+            connections.len()
+        );
+        Ok(Self {
+            runtime,
+            config: config.clone(),
+            connections,
+            metrics,
+            program_key: DbKey::pubkey(
+                &ParallelPostgresClient::parse_program_id(config)?,
+                config.key_storage.unwrap_or_default(),
+            ),
+            committed_slot: None,
+            pending_slots: Vec::new(),
+            committed_events: HashSet::new(),
+        })
+    }
+
+    async fn connect(
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<PipelinedConnection, GeyserPluginError> {
+        let client = SimplePostgresClient::connect_to_db_async(config).await?;
+
+        let notify_stmt = match &config.notify_channel {
+            Some(_) => Some(Self::prepare(&client, NOTIFY_STMT, config).await?),
+            None => None,
+        };
+        let statements = PipelinedStatements {
+            update_inscription_log_stmt: Self::prepare(&client, INSCRIPTION_UPSERT_STMT, config)
+                .await?,
+            insert_inscription_event_stmt: Self::prepare(
+                &client,
+                INSCRIPTION_EVENT_INSERT_STMT,
+                config,
+            )
+            .await?,
+            update_slot_stmt: Self::prepare(&client, SLOT_UPSERT_STMT, config).await?,
+            update_committed_slot_stmt: Self::prepare(&client, COMMITTED_SLOT_UPSERT_STMT, config)
+                .await?,
+            notify_stmt,
+        };
+        Ok(PipelinedConnection { client, statements })
+    }
+
+    async fn prepare(
+        client: &Client,
+        stmt: &str,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Statement, GeyserPluginError> {
+        let stmt = SchemaLayout::new(config)?.render(stmt);
+        client.prepare(&stmt).await.map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError {
+                msg: format!(
+                    "Error in preparing for the transaction update PostgreSQL database: ({}) host: {:?} user: {:?}",
+                    err, config.host, config.user
+                ),
+            }))
+        })
+    }
+
+    /// Replace connections the server has closed since the last batch, and
+    /// retry those which could not be opened every `RECONNECT_INTERVAL`. The
+    /// attempts run on the runtime, the batches keep going through the open
+    /// connections meanwhile.
+    fn reconnect(&mut self) {
+        for slot in self.connections.iter_mut() {
+            if let Some(connecting) = slot.connecting.take() {
+                if !connecting.is_finished() {
+                    slot.connecting = Some(connecting);
+                    continue;
+                }
+                match self.runtime.block_on(connecting) {
+                    Ok(Ok(connection)) => {
+                        slot.connection = Some(connection);
+                        self.metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+                        self.metrics
+                            .set_worker_state(slot.index, WorkerState::Connected);
+                    }
+                    Ok(Err(err)) => {
+                        error!("Failed to reconnect to the database: ({})", err);
+                        self.metrics
+                            .connection_errors
+                            .fetch_add(1, Ordering::Relaxed);
+                        self.metrics.record_db_error();
+                    }
+                    Err(err) => {
+                        error!(
+                            "The reconnection to the database did not complete: ({})",
+                            err
+                        );
+                        self.metrics
+                            .connection_errors
+                            .fetch_add(1, Ordering::Relaxed);
+                        self.metrics.record_db_error();
+                    }
+                }
+                continue;
+            }
+
+            let closed = match &slot.connection {
+                Some(connection) => connection.client.is_closed(),
+                None => false,
+            };
+            if closed {
+                warn!("A pipelined PostgreSQL connection was closed, reconnecting...");
+                self.metrics
+                    .set_worker_state(slot.index, WorkerState::Disconnected);
+                slot.connection = None;
+            } else if slot.connection.is_some() || slot.last_attempt.elapsed() < RECONNECT_INTERVAL
+            {
+                continue;
+            }
+            slot.last_attempt = Instant::now();
+            let config = self.config.clone();
+            slot.connecting = Some(
+                self.runtime
+                    .spawn(async move { Self::connect(&config).await }),
+            );
+        }
+    }
+
+    /// The open connections.
+    fn open_connections(
+        connections: &mut [ConnectionSlot],
+    ) -> impl Iterator<Item = &mut PipelinedConnection> {
+        connections
+            .iter_mut()
+            .filter_map(|slot| slot.connection.as_mut())
+    }
+
+    /// Forget the events of a retried batch skipped as committed, once the
+    /// rest of it is.
+    fn forget_committed(&mut self, skipped: &[&InscriptionEvent]) {
+        for event in skipped {
+            self.committed_events.remove(&event_key(event));
+        }
+    }
+
+    fn no_connection_error() -> GeyserPluginError {
+        GeyserPluginError::Custom(Box::new(
+            GeyserPluginPostgresError::DataStoreConnectionError {
+                msg: "No connection to the PostgreSQL database is open.".to_string(),
+            },
+        ))
+    }
+
+    /// Write one event, and the `inscriptions` row it creates, within
+    /// `transaction`, and announce it on `notify_channel`.
+    async fn write_event(
+        transaction: &Transaction<'_>,
+        statements: &PipelinedStatements,
+        event: &InscriptionEvent,
+        notify_channel: Option<&String>,
+        key_storage: KeyStorage,
+        updated_on: NaiveDateTime,
+    ) -> Result<(), tokio_postgres::Error> {
+        let row = DbEventRow::new(event, key_storage, updated_on);
+        transaction
+            .execute(
+                &statements.insert_inscription_event_stmt,
+                &row.event_params(),
+            )
+            .await?;
+
+        if let Some(params) = row.inscription_params() {
+            transaction
+                .execute(&statements.update_inscription_log_stmt, &params)
+                .await?;
+        }
+
+        if let (Some(notify_stmt), Some(channel)) = (&statements.notify_stmt, notify_channel) {
+            transaction
+                .execute(notify_stmt, &[channel, &NotifyPayload::to_json(event)])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Write `events` within `transaction`, with all statements in flight at
+    /// once.
+    async fn write_all(
+        transaction: &Transaction<'_>,
+        statements: &PipelinedStatements,
+        events: &[&InscriptionEvent],
+        notify_channel: Option<&String>,
+        key_storage: KeyStorage,
+        updated_on: NaiveDateTime,
+    ) -> Result<(), tokio_postgres::Error> {
+        try_join_all(events.iter().map(|event| {
+            Self::write_event(
+                transaction,
+                statements,
+                event,
+                notify_channel,
+                key_storage,
+                updated_on,
+            )
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Write every shard of a batch in one transaction on its connection.
+    /// The transaction of the last shard is committed once the others are,
+    /// and advances the watermark to `committed_slot` when they all
+    /// succeeded. Returns the result of every shard, in order.
+    async fn write_shards(
+        mut shards: Vec<(&mut PipelinedConnection, Vec<&InscriptionEvent>)>,
+        committed_slot: Option<(&DbKey, i64)>,
+        notify_channel: Option<&String>,
+        key_storage: KeyStorage,
+    ) -> Vec<Result<(), tokio_postgres::Error>> {
+        let (last_connection, last_events) = match shards.pop() {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        let updated_on = Utc::now().naive_utc();
+        let PipelinedConnection { client, statements } = last_connection;
+        let statements = &*statements;
+        let last = async {
+            let transaction = client.transaction().await?;
+            Self::write_all(
+                &transaction,
+                statements,
+                &last_events,
+                notify_channel,
+                key_storage,
+                updated_on,
+            )
+            .await?;
+            Ok::<_, tokio_postgres::Error>(transaction)
+        };
+        let others = join_all(
+            shards
+                .into_iter()
+                .map(move |(connection, events)| async move {
+                    let PipelinedConnection { client, statements } = connection;
+                    let transaction = client.transaction().await?;
+                    Self::write_all(
+                        &transaction,
+                        statements,
+                        &events,
+                        notify_channel,
+                        key_storage,
+                        updated_on,
+                    )
+                    .await?;
+                    transaction.commit().await
+                }),
+        );
+
+        let (mut results, last) = join(others, last).await;
+        let last = match last {
+            Ok(transaction) => {
+                let watermark = match committed_slot {
+                    Some((program_key, committed_slot)) if results.iter().all(Result::is_ok) => {
+                        transaction
+                            .execute(
+                                &statements.update_committed_slot_stmt,
+                                &[program_key, &committed_slot, &updated_on],
+                            )
+                            .await
+                    }
+                    _ => Ok(0),
+                };
+                match watermark {
+                    Ok(_) => transaction.commit().await,
+                    Err(err) => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        };
+        results.push(last);
+        results
+    }
+
+    /// Write the watermark set for the batch on its own, when no events
+    /// carry it.
+    fn write_committed_slot(&mut self) -> Result<(), GeyserPluginError> {
+        let committed_slot = match self.committed_slot.take() {
+            Some(committed_slot) => committed_slot,
+            None => return Ok(()),
+        };
+        let connection = Self::open_connections(&mut self.connections)
+            .next()
+            .ok_or_else(Self::no_connection_error)?;
+        let result = self.runtime.block_on(connection.client.execute(
+            &connection.statements.update_committed_slot_stmt,
+            &[
+                &self.program_key,
+                &(committed_slot as i64),
+                &Utc::now().naive_utc(),
+            ],
+        ));
+
+        if let Err(err) = result {
+            let msg = format!(
+                "Failed to persist the committed slot to the PostgreSQL database. Error: {:?}",
+                err
+            );
+            error!("{}", msg);
+            return Err(GeyserPluginError::SlotStatusUpdateError { msg });
+        }
+        Ok(())
+    }
+
+    /// Write the slot updates of the batch, all in flight at once on one
+    /// connection. The server runs them in the order they are sent, so a
+    /// slot updated several times in the batch ends with its last status.
+    fn write_slots(&mut self) -> Result<(), GeyserPluginError> {
+        if self.pending_slots.is_empty() {
+            return Ok(());
+        }
+        let connection = Self::open_connections(&mut self.connections)
+            .next()
+            .ok_or_else(Self::no_connection_error)?;
+        let pending_slots = std::mem::take(&mut self.pending_slots);
+        let connection = &*connection;
+        let updated_on = Utc::now().naive_utc();
+        let writes = pending_slots
+            .iter()
+            .map(|(slot, parent, status)| async move {
+                connection
+                    .client
+                    .execute(
+                        &connection.statements.update_slot_stmt,
+                        &[slot, parent, status, &updated_on],
+                    )
+                    .await
+            });
+
+        let errors = self
+            .runtime
+            .block_on(join_all(writes))
+            .into_iter()
+            .filter_map(|result| result.err())
+            .map(|err| format!("{:?}", err))
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            // Sent again on the next flush, ahead of the updates since.
+            self.pending_slots = pending_slots;
+            let msg = format!(
+                "Failed to persist {} of {} slot updates to the PostgreSQL database. Error: {}",
+                errors.len(),
+                self.pending_slots.len(),
+                errors.join(", ")
+            );
+            error!("{}", msg);
+            return Err(GeyserPluginError::SlotStatusUpdateError { msg });
+        }
+        Ok(())
+    }
+}
+
+impl InscriptionSink for PipelinedPostgresWriter {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    /// Reconnections are reported per connection, so this never returns
+    /// true.
+    fn ensure_connected(&mut self) -> Result<bool, GeyserPluginError> {
+        self.reconnect();
+        Ok(false)
+    }
+
+    fn set_committed_slot(&mut self, slot: u64) {
+        self.committed_slot = Some(slot);
+    }
+
+    fn keeps_watermark(&self) -> bool {
+        true
+    }
+
+    /// The events of a batch which partly failed that were committed are
+    /// skipped when it is retried, so that they are not announced twice.
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        let notify_channel = self.config.notify_channel.as_ref();
+        let key_storage = self.config.key_storage.unwrap_or_default();
+        let (skipped, events): (Vec<_>, Vec<_>) = events
+            .iter()
+            .partition(|event| self.committed_events.contains(&event_key(event)));
+        if events.is_empty() {
+            self.forget_committed(&skipped);
+            return self.write_committed_slot();
+        }
+        let mut connections = Self::open_connections(&mut self.connections).collect::<Vec<_>>();
+        if connections.is_empty() {
+            self.committed_slot = None;
+            return Err(Self::no_connection_error());
+        }
+        let shards = shard_by_account(events, connections.len())
+            .into_iter()
+            .zip(connections.iter_mut())
+            .filter(|(shard, _)| !shard.is_empty())
+            .map(|(shard, connection)| (&mut **connection, shard))
+            .collect::<Vec<_>>();
+        let keys = shards
+            .iter()
+            .map(|(_, shard)| shard.iter().map(|event| event_key(event)).collect())
+            .collect::<Vec<Vec<_>>>();
+        let committed_slot = self
+            .committed_slot
+            .take()
+            .map(|committed_slot| (&self.program_key, committed_slot as i64));
+
+        let results = self.runtime.block_on(Self::write_shards(
+            shards,
+            committed_slot,
+            notify_channel,
+            key_storage,
+        ));
+        let mut committed = Vec::new();
+        let mut errors = Vec::new();
+        for (result, keys) in results.into_iter().zip(keys) {
+            match result {
+                Ok(()) => committed.extend(keys),
+                Err(err) => errors.push(format!("{:?}", err)),
+            }
+        }
+        if !errors.is_empty() {
+            // The watermark was not written: it must not cover the events
+            // which failed.
+            self.committed_events.extend(committed);
+            let msg = format!(
+                "Failed to persist the update of transaction info to the PostgreSQL database. Error: {}",
+                errors.join(", ")
+            );
+            error!("{}", msg);
+            return Err(GeyserPluginError::AccountsUpdateError { msg });
+        }
+        self.forget_committed(&skipped);
+        Ok(())
+    }
+
+    /// Held until `flush`, which writes the slot updates of the batch at
+    /// once.
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        self.pending_slots.push((
+            slot as i64,
+            parent.map(|parent| parent as i64),
+            status.as_str(),
+        ));
+        Ok(())
+    }
+
+    /// The watermark is only written once the slot updates are.
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        self.write_slots()?;
+        self.write_committed_slot()
+    }
+}
+
+fn event_key(event: &InscriptionEvent) -> EventKey {
+    (event.signature, event.instruction_index)
+}
+
+/// Split `events` into `shard_count` shards, keeping their order, with all
+/// the events of an inscription account in the same shard. Events without an
+/// account go to the first shard.
+fn shard_by_account<'a>(
+    events: impl IntoIterator<Item = &'a InscriptionEvent>,
+    shard_count: usize,
+) -> Vec<Vec<&'a InscriptionEvent>> {
+    let mut shards = vec![Vec::new(); shard_count.max(1)];
+    for event in events {
+        let shard = event
+            .inscription_account
+            .map(|account| {
+                let mut prefix = [0u8; 8];
+                prefix.copy_from_slice(&account.as_ref()[..8]);
+                u64::from_le_bytes(prefix) as usize % shards.len()
+            })
+            .unwrap_or_default();
+        shards[shard].push(event);
+    }
+    shards
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::postgres_client::{
+            postgres_client_partition::EventPartitions,
+            postgres_client_transaction::InscriptionInstructionType, DEFAULT_PIPELINED_CONNECTIONS,
+            DEFAULT_THREADS_COUNT,
+        },
+        domichain_sdk::{pubkey::Pubkey, signature::Signature},
+        std::thread,
+    };
+
+    /// The number of events written by each writer in the benchmark.
+    const BENCH_EVENTS: usize = 100_000;
+    /// The number of inscription accounts the benchmark events are spread
+    /// over.
+    const BENCH_ACCOUNTS: usize = 1_000;
+    /// The slot of the benchmark events, far from any real one.
+    const BENCH_SLOT: u64 = 4_000_000_000;
+
+    fn event(instruction_index: u32, inscription_account: Option<Pubkey>) -> InscriptionEvent {
+        InscriptionEvent {
+            slot: 1,
+            signature: Signature::default(),
+            instruction_index,
+            instruction_type: InscriptionInstructionType::WriteData,
+            program_id: Pubkey::new_unique(),
+            inscription_account,
+            metadata_account: None,
+            mint_account: None,
+            authority: None,
+            accounts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_shard_by_account_keeps_accounts_together() {
+        let accounts = (0..8).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let events = (0..64)
+            .map(|i| event(i, Some(accounts[i as usize % accounts.len()])))
+            .chain([event(64, None)])
+            .collect::<Vec<_>>();
+
+        let shards = shard_by_account(&events, 3);
+        assert_eq!(shards.len(), 3);
+        assert_eq!(shards.iter().map(Vec::len).sum::<usize>(), events.len());
+        for account in &accounts {
+            let holding = shards
+                .iter()
+                .filter(|shard| {
+                    shard
+                        .iter()
+                        .any(|event| event.inscription_account == Some(*account))
+                })
+                .count();
+            assert_eq!(holding, 1);
+        }
+        assert!(shards[0]
+            .iter()
+            .any(|event| event.inscription_account.is_none()));
+        for shard in &shards {
+            assert!(shard
+                .windows(2)
+                .all(|pair| pair[0].instruction_index < pair[1].instruction_index));
+        }
+    }
+
+    #[test]
+    fn test_shard_by_account_single_connection() {
+        let events = vec![event(0, Some(Pubkey::new_unique())), event(1, None)];
+        let shards = shard_by_account(&events, 1);
+        assert_eq!(shards, vec![events.iter().collect::<Vec<_>>()]);
+    }
+
+    /// Events of `BENCH_ACCOUNTS` accounts, one in ten creating its
+    /// inscription, cut into batches of `batch_size`.
+    fn bench_batches(accounts: &[Pubkey], batch_size: usize) -> Vec<Vec<InscriptionEvent>> {
+        let events = (0..BENCH_EVENTS)
+            .map(|i| {
+                let account = accounts[i % accounts.len()];
+                let instruction_type = if i % 10 == 0 {
+                    InscriptionInstructionType::Initialize
+                } else {
+                    InscriptionInstructionType::WriteData
+                };
+                InscriptionEvent {
+                    slot: BENCH_SLOT,
+                    signature: Signature::new_unique(),
+                    instruction_index: 0,
+                    instruction_type,
+                    program_id: Pubkey::new_unique(),
+                    inscription_account: Some(account),
+                    metadata_account: Some(account),
+                    mint_account: None,
+                    authority: Some(account),
+                    accounts: vec![account],
+                }
+            })
+            .collect::<Vec<_>>();
+        events.chunks(batch_size).map(<[_]>::to_vec).collect()
+    }
+
+    fn report(writer: &str, elapsed: Duration) {
+        info!(
+            "{}: {} events in {:?}, {:.0} events/s",
+            writer,
+            BENCH_EVENTS,
+            elapsed,
+            BENCH_EVENTS as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    /// Compares the throughput of the threaded and the pipelined writers on
+    /// the database of the plugin configuration named by
+    /// `INSCRIPTIONS_BENCH_CONFIG`, with the `threads`, `connections` and
+    /// `batch_size` it sets, and logs the results. Run it with `cargo test
+    /// --release -- --ignored bench_writer_throughput`.
+    #[test]
+    #[ignore]
+    fn bench_writer_throughput() {
+        domichain_logger::setup_with_default("info");
+        let config_file = std::env::var("INSCRIPTIONS_BENCH_CONFIG")
+            .expect("INSCRIPTIONS_BENCH_CONFIG must name a plugin configuration");
+        let config = GeyserPluginPostgresConfig::load(&config_file).unwrap();
+        let batch_size = ParallelPostgresClient::batch_size(&config);
+        let partitions = EventPartitions::new(&config).unwrap();
+        partitions.cover_now(BENCH_SLOT).unwrap();
+        let accounts = (0..BENCH_ACCOUNTS)
+            .map(|_| Pubkey::new_unique())
+            .collect::<Vec<_>>();
+
+        let threads = config.threads.unwrap_or(DEFAULT_THREADS_COUNT);
+        let mut clients = (0..threads)
+            .map(|_| SimplePostgresClient::new(&config).unwrap())
+            .collect::<Vec<_>>();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for batch in bench_batches(&accounts, batch_size) {
+            sender.send(batch).unwrap();
+        }
+        drop(sender);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for client in clients.iter_mut() {
+                let receiver = receiver.clone();
+                scope.spawn(move || {
+                    for batch in receiver {
+                        client.write_events(&batch).unwrap();
+                    }
+                });
+            }
+        });
+        report(&format!("threaded, {} threads", threads), start.elapsed());
+
+        let connections = config.connections.unwrap_or(DEFAULT_PIPELINED_CONNECTIONS);
+        let mut writer = PipelinedPostgresWriter::new(
+            &config,
+            connections,
+            Arc::new(PluginMetrics::new(connections)),
+            &AtomicUsize::default(),
+            &AtomicUsize::default(),
+        )
+        .unwrap();
+        let batches = bench_batches(&accounts, batch_size);
+        let start = Instant::now();
+        for batch in &batches {
+            writer.write_events(batch).unwrap();
+        }
+        report(
+            &format!("pipelined, {} connections", connections),
+            start.elapsed(),
+        );
+    }
+}
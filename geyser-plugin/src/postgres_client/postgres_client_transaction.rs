@@ -1,38 +1,345 @@
-/// Module responsible for handling persisting transaction data to the PostgreSQL
-/// database.
+/// Module responsible for decoding inscription instructions out of
+/// transactions and persisting them to the PostgreSQL database.
 use {
     crate::{
-        geyser_plugin_postgres::{GeyserPluginPostgresConfig, GeyserPluginPostgresError},
-        postgres_client::{DbWorkItem, ParallelPostgresClient, SimplePostgresClient},
+        geyser_plugin_postgres::{
+            GeyserPluginPostgresConfig, GeyserPluginPostgresError, KeyStorage,
+        },
+        inscription_sink::InscriptionSink,
+        postgres_client::{
+            postgres_client_watermark::COMMITTED_SLOT_UPSERT_STMT, DbWorkItem,
+            ParallelPostgresClient, SchemaLayout, SimplePostgresClient,
+        },
     },
     borsh::BorshDeserialize,
-    chrono::Utc,
+    bytes::BytesMut,
+    chrono::{NaiveDateTime, Utc},
     domichain_geyser_plugin_interface::geyser_plugin_interface::{
-        GeyserPluginError, ReplicaTransactionInfoV2,
+        GeyserPluginError, ReplicaTransactionInfoV2, SlotStatus,
     },
-    domichain_runtime::{bank::RewardType, transaction_batch},
     domichain_sdk::{
-        account_info::AccountInfo,
         instruction::CompiledInstruction,
-        message::{
-            v0::{self, LoadedAddresses, MessageAddressTableLookup},
-            Message, MessageHeader, SanitizedMessage,
-        },
+        message::{AccountKeys, SanitizedMessage},
         pubkey::Pubkey,
-        transaction::TransactionError,
-    },
-    domichain_transaction_status::{
-        InnerInstructions, Reward, TransactionStatusMeta, TransactionTokenBalance,
+        signature::Signature,
     },
     log::*,
-    mpl_inscription_program::instruction::{
-        accounts::WriteDataAccounts, MplInscriptionInstruction,
-    },
-    postgres::{Client, Statement},
-    postgres_types::{FromSql, ToSql},
-    std::sync::atomic::Ordering,
+    mpl_inscription_program::instruction::MplInscriptionInstruction,
+    postgres::{Client, Row, Statement},
+    postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type},
+    serde_derive::{Deserialize, Serialize},
+    std::{error::Error, str::FromStr, sync::atomic::Ordering},
 };
 
+/// Upserts one row into the `inscriptions` table. Shared by the threaded and
+/// the pipelined writers. The statements are rendered with `SchemaLayout`.
+/// A row is never replaced by one of an earlier slot, so the outcome does not
+/// depend on the order concurrent transactions commit in.
+pub(crate) const INSCRIPTION_UPSERT_STMT: &str =
+    "INSERT INTO {schema}.{prefix}inscriptions AS insc (slot, signature, account, mint_account, \
+        metadata_account, authority, updated_on) \
+    VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (account) DO UPDATE SET \
+        slot=excluded.slot, \
+        signature=excluded.signature, \
+        account=excluded.account, \
+        mint_account=excluded.mint_account, \
+        metadata_account=excluded.metadata_account, \
+        authority=excluded.authority, \
+        updated_on=excluded.updated_on \
+    WHERE insc.slot <= excluded.slot";
+
+/// Records one inscription instruction in the `inscription_events` table.
+pub(crate) const INSCRIPTION_EVENT_INSERT_STMT: &str =
+    "INSERT INTO {schema}.{prefix}inscription_events (slot, signature, instruction_index, instruction_type, \
+        inscription_account, metadata_account, mint_account, authority, accounts, updated_on) \
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+    ON CONFLICT (slot, signature, instruction_index) DO NOTHING";
+
+/// Notifies the listeners of `notify_channel` of one event, delivered when
+/// the transaction commits.
+pub(crate) const NOTIFY_STMT: &str = "SELECT pg_notify($1, $2)";
+
+/// Upserts the status of a slot into the `slots` table.
+pub(crate) const SLOT_UPSERT_STMT: &str =
+    "INSERT INTO {schema}.{prefix}slots AS slots (slot, parent, status, updated_on) \
+    VALUES ($1, $2, $3, $4) ON CONFLICT (slot) DO UPDATE SET \
+        parent=COALESCE(excluded.parent, slots.parent), \
+        status=excluded.status, \
+        updated_on=excluded.updated_on";
+
+/// The inscription program instruction an event was decoded from.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InscriptionInstructionType {
+    Initialize,
+    InitializeFromMint,
+    WriteData,
+    Close,
+    /// Any other instruction of the inscription program.
+    Other,
+}
+
+impl InscriptionInstructionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InscriptionInstructionType::Initialize => "initialize",
+            InscriptionInstructionType::InitializeFromMint => "initialize_from_mint",
+            InscriptionInstructionType::WriteData => "write_data",
+            InscriptionInstructionType::Close => "close",
+            InscriptionInstructionType::Other => "other",
+        }
+    }
+
+    /// The inverse of `as_str`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "initialize" => Some(InscriptionInstructionType::Initialize),
+            "initialize_from_mint" => Some(InscriptionInstructionType::InitializeFromMint),
+            "write_data" => Some(InscriptionInstructionType::WriteData),
+            "close" => Some(InscriptionInstructionType::Close),
+            "other" => Some(InscriptionInstructionType::Other),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction creates the inscription account.
+    pub fn is_create(&self) -> bool {
+        matches!(
+            self,
+            InscriptionInstructionType::Initialize | InscriptionInstructionType::InitializeFromMint
+        )
+    }
+}
+
+/// One successfully executed inscription program instruction. This is what
+/// the workers hand over to every `InscriptionSink`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InscriptionEvent {
+    pub slot: u64,
+    pub signature: Signature,
+    /// The position of the instruction in the transaction.
+    pub instruction_index: u32,
+    pub instruction_type: InscriptionInstructionType,
+    pub program_id: Pubkey,
+    pub inscription_account: Option<Pubkey>,
+    pub metadata_account: Option<Pubkey>,
+    pub mint_account: Option<Pubkey>,
+    /// The account paying for the instruction, recorded as the authority.
+    pub authority: Option<Pubkey>,
+    /// Every account passed to the instruction, in order.
+    pub accounts: Vec<Pubkey>,
+}
+
+impl InscriptionEvent {
+    /// Read an event back from the `inscription_events` columns `slot,
+    /// signature, instruction_index, instruction_type, inscription_account,
+    /// metadata_account, mint_account, authority, accounts`, in this order.
+    /// The table does not record the program, which is `program_id`.
+    pub fn from_row(row: &Row, program_id: Pubkey) -> Result<Self, String> {
+        let parse_optional_key =
+            |key: Option<DbKey>| key.as_ref().map(DbKey::to_pubkey).transpose();
+
+        let instruction_type: String = row.get(3);
+        Ok(InscriptionEvent {
+            slot: row.get::<_, i64>(0) as u64,
+            signature: row.get::<_, DbKey>(1).to_signature()?,
+            instruction_index: row.get::<_, i32>(2) as u32,
+            instruction_type: InscriptionInstructionType::from_name(&instruction_type)
+                .unwrap_or(InscriptionInstructionType::Other),
+            program_id,
+            inscription_account: parse_optional_key(row.get(4))?,
+            metadata_account: parse_optional_key(row.get(5))?,
+            mint_account: parse_optional_key(row.get(6))?,
+            authority: parse_optional_key(row.get(7))?,
+            accounts: row
+                .get::<_, Vec<DbKey>>(8)
+                .iter()
+                .map(DbKey::to_pubkey)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// A pubkey or signature column value: base58 TEXT or BYTEA, depending on
+/// the `KeyStorage` of the tables.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DbKey {
+    Base58(String),
+    Binary(Vec<u8>),
+}
+
+impl DbKey {
+    pub fn pubkey(key: &Pubkey, storage: KeyStorage) -> Self {
+        match storage {
+            KeyStorage::Base58 => DbKey::Base58(key.to_string()),
+            KeyStorage::Binary => DbKey::Binary(key.to_bytes().to_vec()),
+        }
+    }
+
+    pub fn signature(signature: &Signature, storage: KeyStorage) -> Self {
+        match storage {
+            KeyStorage::Base58 => DbKey::Base58(signature.to_string()),
+            KeyStorage::Binary => DbKey::Binary(signature.as_ref().to_vec()),
+        }
+    }
+
+    /// The value sorting before all others, to start a key set pagination
+    /// from.
+    pub fn min(storage: KeyStorage) -> Self {
+        match storage {
+            KeyStorage::Base58 => DbKey::Base58(String::new()),
+            KeyStorage::Binary => DbKey::Binary(Vec::new()),
+        }
+    }
+
+    pub fn to_pubkey(&self) -> Result<Pubkey, String> {
+        let result = match self {
+            DbKey::Base58(key) => Pubkey::from_str(key).map_err(|err| err.to_string()),
+            DbKey::Binary(key) => Pubkey::try_from(key.as_slice()).map_err(|err| err.to_string()),
+        };
+        result.map_err(|err| format!("Invalid key {:?} in the database: ({})", self, err))
+    }
+
+    pub fn to_signature(&self) -> Result<Signature, String> {
+        let result = match self {
+            DbKey::Base58(signature) => {
+                Signature::from_str(signature).map_err(|err| err.to_string())
+            }
+            DbKey::Binary(signature) => <[u8; 64]>::try_from(signature.as_slice())
+                .map(Signature::from)
+                .map_err(|err| err.to_string()),
+        };
+        result.map_err(|err| format!("Invalid signature {:?} in the database: ({})", self, err))
+    }
+}
+
+impl ToSql for DbKey {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        // Both are accepted by TEXT and BYTEA columns, the check keeps keys
+        // from being written in the storage of the other.
+        match self {
+            DbKey::Base58(key) if *ty != Type::BYTEA => key.to_sql(ty, out),
+            DbKey::Binary(key) if *ty == Type::BYTEA => key.to_sql(ty, out),
+            _ => Err(format!("A {:?} key cannot be stored in a {} column", self, ty).into()),
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <String as ToSql>::accepts(ty) || <Vec<u8> as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for DbKey {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if *ty == Type::BYTEA {
+            Vec::from_sql(ty, raw).map(DbKey::Binary)
+        } else {
+            String::from_sql(ty, raw).map(DbKey::Base58)
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <String as FromSql>::accepts(ty) || <Vec<u8> as FromSql>::accepts(ty)
+    }
+}
+
+/// The parameters of `INSCRIPTION_EVENT_INSERT_STMT` for an event, and of
+/// `INSCRIPTION_UPSERT_STMT` for the `inscriptions` row it creates.
+pub(crate) struct DbEventRow {
+    slot: i64,
+    signature: DbKey,
+    instruction_index: i32,
+    instruction_type: &'static str,
+    creates_inscription: bool,
+    inscription_account: Option<DbKey>,
+    metadata_account: Option<DbKey>,
+    mint_account: Option<DbKey>,
+    authority: Option<DbKey>,
+    accounts: Vec<DbKey>,
+    updated_on: NaiveDateTime,
+}
+
+impl DbEventRow {
+    pub(crate) fn new(
+        event: &InscriptionEvent,
+        storage: KeyStorage,
+        updated_on: NaiveDateTime,
+    ) -> Self {
+        let key = |key: &Pubkey| DbKey::pubkey(key, storage);
+        DbEventRow {
+            slot: event.slot as i64,
+            signature: DbKey::signature(&event.signature, storage),
+            instruction_index: event.instruction_index as i32,
+            instruction_type: event.instruction_type.as_str(),
+            creates_inscription: event.instruction_type.is_create(),
+            inscription_account: event.inscription_account.as_ref().map(key),
+            metadata_account: event.metadata_account.as_ref().map(key),
+            mint_account: event.mint_account.as_ref().map(key),
+            authority: event.authority.as_ref().map(key),
+            accounts: event.accounts.iter().map(key).collect(),
+            updated_on,
+        }
+    }
+
+    pub(crate) fn event_params(&self) -> [&(dyn ToSql + Sync); 10] {
+        [
+            &self.slot,
+            &self.signature,
+            &self.instruction_index,
+            &self.instruction_type,
+            &self.inscription_account,
+            &self.metadata_account,
+            &self.mint_account,
+            &self.authority,
+            &self.accounts,
+            &self.updated_on,
+        ]
+    }
+
+    /// The parameters of the `inscriptions` row, for the events creating an
+    /// inscription, as `DbInscription::from_event`.
+    pub(crate) fn inscription_params(&self) -> Option<[&(dyn ToSql + Sync); 7]> {
+        if !self.creates_inscription {
+            return None;
+        }
+        Some([
+            &self.slot,
+            &self.signature,
+            self.inscription_account.as_ref()?,
+            &self.mint_account,
+            self.metadata_account.as_ref()?,
+            self.authority.as_ref()?,
+            &self.updated_on,
+        ])
+    }
+}
+
+/// The compact payload sent on `notify_channel` for every event.
+#[derive(Serialize)]
+pub(crate) struct NotifyPayload<'a> {
+    pub account: Option<String>,
+    pub slot: u64,
+    pub event_type: &'a str,
+}
+
+impl NotifyPayload<'_> {
+    pub(crate) fn to_json(event: &InscriptionEvent) -> String {
+        serde_json::to_string(&NotifyPayload {
+            account: event.inscription_account.map(|key| key.to_string()),
+            slot: event.slot,
+            event_type: event.instruction_type.as_str(),
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// A row of the `inscriptions` table.
 pub struct DbInscription {
     pub slot: i64,
     pub signature: String,
@@ -42,32 +349,109 @@ pub struct DbInscription {
     pub authority: String,
 }
 
-pub struct LogInscriptionRequest {
-    pub inscription_info: DbInscription,
+impl DbInscription {
+    /// The `inscriptions` row written for events creating an inscription.
+    pub fn from_event(event: &InscriptionEvent) -> Option<Self> {
+        if !event.instruction_type.is_create() {
+            return None;
+        }
+        Some(DbInscription {
+            slot: event.slot as i64,
+            signature: event.signature.to_string(),
+            account: event.inscription_account?.to_string(),
+            mint_account: event.mint_account.map(|key| key.to_string()),
+            metadata_account: event.metadata_account?.to_string(),
+            authority: event.authority?.to_string(),
+        })
+    }
+}
+
+/// Decode the instructions of `program_id` in a transaction into events.
+/// Returns the events and the number of instructions which could not be
+/// deserialized.
+pub fn decode_inscription_events(
+    program_id: &Pubkey,
+    slot: u64,
+    signature: &Signature,
+    account_keys: &AccountKeys,
+    instructions: &[CompiledInstruction],
+) -> (Vec<InscriptionEvent>, usize) {
+    let mut events = Vec::new();
+    let mut decode_failures = 0;
+
+    for (instruction_index, compiled) in instructions.iter().enumerate() {
+        if account_keys.get(compiled.program_id_index.into()) != Some(program_id) {
+            continue;
+        }
+
+        let instruction = match MplInscriptionInstruction::try_from_slice(&compiled.data) {
+            Ok(instruction) => instruction,
+            Err(err) => {
+                debug!(
+                    "Failed to decode inscription instruction in transaction {}: {}",
+                    signature, err
+                );
+                decode_failures += 1;
+                continue;
+            }
+        };
+
+        let accounts = compiled
+            .accounts
+            .iter()
+            .filter_map(|index| account_keys.get((*index).into()).copied())
+            .collect::<Vec<_>>();
+        let account = |index: usize| accounts.get(index).copied();
+
+        let (instruction_type, mint_account, authority) = match instruction {
+            MplInscriptionInstruction::Initialize => {
+                (InscriptionInstructionType::Initialize, None, account(3))
+            }
+            MplInscriptionInstruction::InitializeFromMint => (
+                InscriptionInstructionType::InitializeFromMint,
+                account(2),
+                account(5),
+            ),
+            MplInscriptionInstruction::WriteData(_) => {
+                (InscriptionInstructionType::WriteData, None, account(2))
+            }
+            MplInscriptionInstruction::Close => {
+                (InscriptionInstructionType::Close, None, account(2))
+            }
+            _ => (InscriptionInstructionType::Other, None, None),
+        };
+
+        events.push(InscriptionEvent {
+            slot,
+            signature: *signature,
+            instruction_index: instruction_index as u32,
+            instruction_type,
+            program_id: *program_id,
+            inscription_account: account(0),
+            metadata_account: account(1),
+            mint_account,
+            authority,
+            accounts,
+        });
+    }
+
+    (events, decode_failures)
 }
 
 impl SimplePostgresClient {
-    pub(crate) fn build_inscription_info_upsert_statement(
+    pub(crate) fn prepare_statement(
         client: &mut Client,
+        stmt: &str,
         config: &GeyserPluginPostgresConfig,
     ) -> Result<Statement, GeyserPluginError> {
-        let stmt = "INSERT INTO inscriptions AS insc (slot, signature, account, mint_account, \
-                metadata_account, authority, updated_on) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (account) DO UPDATE SET \
-                account=excluded.account, \
-                mint_account=excluded.mint_account, \
-                metadata_account=excluded.metadata_account, \
-                authority=excluded.authority, \
-                updated_on=excluded.updated_on";
-
-        let stmt = client.prepare(stmt);
+        let stmt = client.prepare(&SchemaLayout::new(config)?.render(stmt));
 
         match stmt {
             Err(err) => {
                 Err(GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError {
                     msg: format!(
-                        "Error in preparing for the transaction update PostgreSQL database: ({}) host: {:?} user: {:?} config: {:?}",
-                        err, config.host, config.user, config
+                        "Error in preparing for the transaction update PostgreSQL database: ({}) host: {:?} user: {:?}",
+                        err, config.host, config.user
                     ),
                 })))
             }
@@ -75,40 +459,151 @@ impl SimplePostgresClient {
         }
     }
 
-    pub(crate) fn log_inscription_impl(
+    pub(crate) fn build_inscription_info_upsert_statement(
+        client: &mut Client,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Statement, GeyserPluginError> {
+        Self::prepare_statement(client, INSCRIPTION_UPSERT_STMT, config)
+    }
+
+    pub(crate) fn build_inscription_event_insert_statement(
+        client: &mut Client,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Statement, GeyserPluginError> {
+        Self::prepare_statement(client, INSCRIPTION_EVENT_INSERT_STMT, config)
+    }
+
+    /// The notification statement, when `notify_channel` is set.
+    pub(crate) fn build_notify_statement(
+        client: &mut Client,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Option<Statement>, GeyserPluginError> {
+        config
+            .notify_channel
+            .as_ref()
+            .map(|_| Self::prepare_statement(client, NOTIFY_STMT, config))
+            .transpose()
+    }
+
+    pub(crate) fn build_slot_upsert_statement(
+        client: &mut Client,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Statement, GeyserPluginError> {
+        Self::prepare_statement(client, SLOT_UPSERT_STMT, config)
+    }
+
+    pub(crate) fn build_committed_slot_upsert_statement(
+        client: &mut Client,
+        config: &GeyserPluginPostgresConfig,
+    ) -> Result<Statement, GeyserPluginError> {
+        Self::prepare_statement(client, COMMITTED_SLOT_UPSERT_STMT, config)
+    }
+
+    /// Write the events, the `inscriptions` rows they create and the
+    /// watermark they advance, in one database transaction. Every event is
+    /// announced on `notify_channel`, if set, once the transaction commits.
+    pub(crate) fn write_events_impl(
         &mut self,
-        inscription_log_info: LogInscriptionRequest,
+        events: &[InscriptionEvent],
     ) -> Result<(), GeyserPluginError> {
+        let committed_slot = self.committed_slot.take();
         let client = self.client.get_mut().unwrap();
-        let statement = &client.update_inscription_log_stmt;
-        let client = &mut client.client;
+        let notify_channel = self.config.notify_channel.as_ref();
+        let key_storage = self.config.key_storage.unwrap_or_default();
         let updated_on = Utc::now().naive_utc();
 
-        let inscription_info = inscription_log_info.inscription_info;
-        let result = client.query(
-            statement,
+        let result = (|| {
+            let mut transaction = client.client.transaction()?;
+            for event in events {
+                let row = DbEventRow::new(event, key_storage, updated_on);
+                transaction.execute(&client.insert_inscription_event_stmt, &row.event_params())?;
+
+                if let Some(params) = row.inscription_params() {
+                    transaction.execute(&client.update_inscription_log_stmt, &params)?;
+                }
+
+                if let (Some(notify_stmt), Some(channel)) = (&client.notify_stmt, notify_channel) {
+                    transaction.execute(notify_stmt, &[channel, &NotifyPayload::to_json(event)])?;
+                }
+            }
+            if let Some(committed_slot) = committed_slot {
+                transaction.execute(
+                    &client.update_committed_slot_stmt,
+                    &[&self.program_key, &(committed_slot as i64), &updated_on],
+                )?;
+            }
+            transaction.commit()
+        })();
+
+        if let Err(err) = result {
+            let msg = format!(
+                "Failed to persist the update of transaction info to the PostgreSQL database. Error: {:?}",
+                err
+            );
+            error!("{}", msg);
+            return Err(GeyserPluginError::AccountsUpdateError { msg });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn update_slot_status_impl(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        let client = self.client.get_mut().unwrap();
+        let updated_on = Utc::now().naive_utc();
+
+        let result = client.client.execute(
+            &client.update_slot_stmt,
             &[
-                &inscription_info.slot,
-                &inscription_info.signature,
-                &inscription_info.account,
-                &inscription_info.mint_account,
-                &inscription_info.metadata_account,
-                &inscription_info.authority,
+                &(slot as i64),
+                &parent.map(|parent| parent as i64),
+                &status.as_str(),
                 &updated_on,
             ],
         );
 
         if let Err(err) = result {
             let msg = format!(
-                "Failed to persist the update of transaction info to the PostgreSQL database. Error: {:?}",
+                "Failed to persist the update of slot to the PostgreSQL database. Error: {:?}",
                 err
             );
             error!("{}", msg);
-            return Err(GeyserPluginError::AccountsUpdateError { msg });
+            return Err(GeyserPluginError::SlotStatusUpdateError { msg });
         }
 
         Ok(())
     }
+
+    /// Write the watermark left over by a batch without events.
+    pub(crate) fn flush_committed_slot(&mut self) -> Result<(), GeyserPluginError> {
+        let committed_slot = match self.committed_slot.take() {
+            Some(committed_slot) => committed_slot,
+            None => return Ok(()),
+        };
+        let client = self.client.get_mut().unwrap();
+        let result = client.client.execute(
+            &client.update_committed_slot_stmt,
+            &[
+                &self.program_key,
+                &(committed_slot as i64),
+                &Utc::now().naive_utc(),
+            ],
+        );
+
+        if let Err(err) = result {
+            let msg = format!(
+                "Failed to persist the committed slot to the PostgreSQL database. Error: {:?}",
+                err
+            );
+            error!("{}", msg);
+            return Err(GeyserPluginError::SlotStatusUpdateError { msg });
+        }
+        Ok(())
+    }
 }
 
 impl ParallelPostgresClient {
@@ -117,91 +612,419 @@ impl ParallelPostgresClient {
         transaction_info: &ReplicaTransactionInfoV2,
         slot: u64,
     ) -> Result<(), GeyserPluginError> {
-        let instructions = match transaction_info.transaction.message() {
+        if self.is_committed(slot) {
+            return Ok(());
+        }
+        let message = transaction_info.transaction.message();
+        let instructions = match message {
             SanitizedMessage::Legacy(message) => &message.message.instructions,
             SanitizedMessage::V0(message) => &message.message.instructions,
         };
 
-        let inscription_instructions = instructions
-            .into_iter()
-            .filter_map(|compiled| {
-                MplInscriptionInstruction::try_from_slice(&compiled.data)
-                    .ok()
-                    .and_then(|parsed| Some((compiled.clone(), parsed)))
+        let (events, decode_failures) = decode_inscription_events(
+            &self.program_id,
+            slot,
+            transaction_info.signature,
+            &message.account_keys(),
+            instructions,
+        );
+        self.metrics
+            .decode_failures
+            .fetch_add(decode_failures as u64, Ordering::Relaxed);
+
+        if !events.is_empty() {
+            if let Some(partitions) = &self.partitions {
+                partitions.cover(slot);
+            }
+            let event_count = events.len() as u64;
+            self.tracker.enqueued(slot);
+            if let Err(err) = self.sender.send(DbWorkItem::LogEvents(events)) {
+                return Err(GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!("Failed to update the transaction, error: {:?}", err),
+                });
+            }
+            self.metrics
+                .events
+                .enqueued
+                .fetch_add(event_count, Ordering::Relaxed);
+            self.metrics
+                .queue_depth
+                .store(self.sender.len() as u64, Ordering::Relaxed);
+        }
+
+        self.report_metrics();
+        Ok(())
+    }
+
+    pub fn update_slot_status(
+        &self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        if let (Some(resume_point), Some(backfill_gaps)) = (&self.resume_point, &self.backfill_gaps)
+        {
+            if let Some((start_slot, end_slot)) = resume_point.check_gap(slot, parent) {
+                backfill_gaps.record(start_slot, end_slot);
+            }
+        }
+        if self.is_committed(slot) {
+            return Ok(());
+        }
+        if let Some(partitions) = &self.partitions {
+            partitions.update_slot_status(slot, &status);
+        }
+        if status == SlotStatus::Rooted {
+            self.tracker.rooted(slot);
+        }
+        if let Err(err) = self.sender.send(DbWorkItem::UpdateSlot {
+            slot,
+            parent,
+            status,
+        }) {
+            return Err(GeyserPluginError::SlotStatusUpdateError {
+                msg: format!("Failed to update the slot {}, error: {:?}", slot, err),
+            });
+        }
+        self.metrics.slots.enqueued.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether the events of `slot` were committed before the plugin was
+    /// loaded, in which case the validator is replaying it.
+    fn is_committed(&self, slot: u64) -> bool {
+        self.resume_point
+            .as_ref()
+            .map(|resume_point| resume_point.is_committed(slot))
+            .unwrap_or(false)
+    }
+}
+
+impl InscriptionSink for SimplePostgresClient {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn ensure_connected(&mut self) -> Result<bool, GeyserPluginError> {
+        if !self.is_closed() {
+            return Ok(false);
+        }
+        warn!("The PostgreSQL connection was closed, reconnecting...");
+        *self = SimplePostgresClient::new(&self.config)?;
+        Ok(true)
+    }
+
+    fn set_committed_slot(&mut self, slot: u64) {
+        self.committed_slot = Some(slot);
+    }
+
+    fn keeps_watermark(&self) -> bool {
+        true
+    }
+
+    fn write_events(&mut self, events: &[InscriptionEvent]) -> Result<(), GeyserPluginError> {
+        self.write_events_impl(events)
+    }
+
+    fn update_slot_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        self.update_slot_status_impl(slot, parent, status)
+    }
+
+    fn flush(&mut self) -> Result<(), GeyserPluginError> {
+        self.flush_committed_slot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, domichain_sdk::message::v0::LoadedAddresses};
+
+    /// Data of the first instruction `is_variant` accepts, trying every
+    /// discriminant followed by zeroed arguments: zeroes decode as empty
+    /// vectors and strings, `None` and 0.
+    fn instruction_data(is_variant: fn(&MplInscriptionInstruction) -> bool) -> Vec<u8> {
+        (0..=32)
+            .flat_map(|args_len| {
+                (0..=u8::MAX).map(move |discriminant| {
+                    let mut data = vec![0; args_len + 1];
+                    data[0] = discriminant;
+                    data
+                })
+            })
+            .find(|data| {
+                MplInscriptionInstruction::try_from_slice(data)
+                    .map(|instruction| is_variant(&instruction))
+                    .unwrap_or(false)
             })
+            .expect("no data decodes as the instruction")
+    }
+
+    /// The keys of a transaction: `account_count` accounts, then the
+    /// inscription program and another program.
+    fn account_keys(account_count: usize) -> (Vec<Pubkey>, u8, u8) {
+        let mut keys = (0..account_count)
+            .map(|_| Pubkey::new_unique())
             .collect::<Vec<_>>();
+        keys.push(mpl_inscription_program::ID);
+        keys.push(Pubkey::new_unique());
+        (keys, account_count as u8, account_count as u8 + 1)
+    }
 
-        if inscription_instructions.is_empty() {
-            return Ok(());
+    fn decode(
+        keys: &[Pubkey],
+        instructions: &[CompiledInstruction],
+    ) -> (Vec<InscriptionEvent>, usize) {
+        decode_inscription_events(
+            &mpl_inscription_program::ID,
+            7,
+            &Signature::default(),
+            &AccountKeys::new(keys, None),
+            instructions,
+        )
+    }
+
+    #[test]
+    fn test_decode_account_mapping() {
+        let (keys, program_index, _) = account_keys(6);
+        let accounts = (0..6).collect::<Vec<u8>>();
+        let cases: [(fn(&MplInscriptionInstruction) -> bool, _, _, _); 4] = [
+            (
+                |instruction| matches!(instruction, MplInscriptionInstruction::Initialize),
+                InscriptionInstructionType::Initialize,
+                None,
+                Some(keys[3]),
+            ),
+            (
+                |instruction| matches!(instruction, MplInscriptionInstruction::InitializeFromMint),
+                InscriptionInstructionType::InitializeFromMint,
+                Some(keys[2]),
+                Some(keys[5]),
+            ),
+            (
+                |instruction| matches!(instruction, MplInscriptionInstruction::WriteData(_)),
+                InscriptionInstructionType::WriteData,
+                None,
+                Some(keys[2]),
+            ),
+            (
+                |instruction| matches!(instruction, MplInscriptionInstruction::Close),
+                InscriptionInstructionType::Close,
+                None,
+                Some(keys[2]),
+            ),
+        ];
+
+        for (is_variant, instruction_type, mint_account, authority) in cases {
+            let instruction = CompiledInstruction {
+                program_id_index: program_index,
+                accounts: accounts.clone(),
+                data: instruction_data(is_variant),
+            };
+            let (events, decode_failures) = decode(&keys, &[instruction]);
+            assert_eq!(decode_failures, 0);
+            assert_eq!(
+                events,
+                vec![InscriptionEvent {
+                    slot: 7,
+                    signature: Signature::default(),
+                    instruction_index: 0,
+                    instruction_type,
+                    program_id: mpl_inscription_program::ID,
+                    inscription_account: Some(keys[0]),
+                    metadata_account: Some(keys[1]),
+                    mint_account,
+                    authority,
+                    accounts: keys[..6].to_vec(),
+                }]
+            );
         }
+    }
+
+    #[test]
+    fn test_decode_skips_other_programs() {
+        let (keys, program_index, other_program_index) = account_keys(4);
+        let data =
+            instruction_data(|instruction| matches!(instruction, MplInscriptionInstruction::Close));
+        let instructions = [
+            CompiledInstruction {
+                program_id_index: other_program_index,
+                accounts: vec![0, 1, 2],
+                data: data.clone(),
+            },
+            CompiledInstruction {
+                program_id_index: program_index,
+                accounts: vec![0, 1, 2],
+                data,
+            },
+        ];
 
-        let account_keys = match transaction_info.transaction.message() {
-            SanitizedMessage::Legacy(message) => message.account_keys(),
-            SanitizedMessage::V0(message) => message.account_keys(),
+        let (events, decode_failures) = decode(&keys, &instructions);
+        assert_eq!(decode_failures, 0);
+        assert_eq!(events.len(), 1);
+        // The position in the transaction is kept.
+        assert_eq!(events[0].instruction_index, 1);
+        assert_eq!(events[0].authority, Some(keys[2]));
+    }
+
+    #[test]
+    fn test_decode_failures() {
+        let (keys, program_index, _) = account_keys(4);
+        let instructions = [
+            CompiledInstruction {
+                program_id_index: program_index,
+                accounts: vec![0, 1, 2],
+                data: vec![],
+            },
+            CompiledInstruction {
+                program_id_index: program_index,
+                accounts: vec![0, 1, 2, 3],
+                data: instruction_data(|instruction| {
+                    matches!(instruction, MplInscriptionInstruction::Initialize)
+                }),
+            },
+        ];
+
+        let (events, decode_failures) = decode(&keys, &instructions);
+        assert_eq!(decode_failures, 1);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].instruction_index, 1);
+        assert_eq!(
+            events[0].instruction_type,
+            InscriptionInstructionType::Initialize
+        );
+    }
+
+    #[test]
+    fn test_decode_missing_accounts() {
+        let (keys, program_index, _) = account_keys(4);
+        // The authority of `Initialize` is the fourth account, and an
+        // index beyond the keys is dropped.
+        let instruction = CompiledInstruction {
+            program_id_index: program_index,
+            accounts: vec![0, 1, 200],
+            data: instruction_data(|instruction| {
+                matches!(instruction, MplInscriptionInstruction::Initialize)
+            }),
         };
 
-        fn get_account_pubkey_slice<'a>(
-            instruction_accounts: &'a Vec<&'a Pubkey>,
-            index: usize,
-        ) -> String {
-            bs58::encode(instruction_accounts.get(index).unwrap().as_ref()).into_string()
-        }
-
-        let mut inscription_info: Option<DbInscription> = None;
-
-        for (compiled_instruction, inscription_instruction) in inscription_instructions {
-            match inscription_instruction {
-                MplInscriptionInstruction::Initialize => {
-                    let instruction_accounts = compiled_instruction
-                        .accounts
-                        .into_iter()
-                        .filter_map(|index| account_keys.get(index.into()))
-                        .collect::<Vec<_>>();
-
-                    inscription_info = Some(DbInscription {
-                        slot: slot as i64,
-                        account: get_account_pubkey_slice(&instruction_accounts, 0),
-                        metadata_account: get_account_pubkey_slice(&instruction_accounts, 1),
-                        mint_account: None,
-                        authority: get_account_pubkey_slice(&instruction_accounts, 3),
-                        signature: bs58::encode(transaction_info.signature.as_ref()).into_string(),
-                    });
-                }
+        let (events, _) = decode(&keys, &[instruction]);
+        assert_eq!(events[0].accounts, vec![keys[0], keys[1]]);
+        assert_eq!(events[0].inscription_account, Some(keys[0]));
+        assert_eq!(events[0].authority, None);
+        assert!(DbInscription::from_event(&events[0]).is_none());
+    }
 
-                MplInscriptionInstruction::InitializeFromMint => {
-                    let instruction_accounts = compiled_instruction
-                        .accounts
-                        .into_iter()
-                        .filter_map(|index| account_keys.get(index.into()))
-                        .collect::<Vec<_>>();
-
-                    inscription_info = Some(DbInscription {
-                        slot: slot as i64,
-                        account: get_account_pubkey_slice(&instruction_accounts, 0),
-                        metadata_account: get_account_pubkey_slice(&instruction_accounts, 1),
-                        mint_account: Some(get_account_pubkey_slice(&instruction_accounts, 2)),
-                        authority: get_account_pubkey_slice(&instruction_accounts, 5),
-                        signature: bs58::encode(transaction_info.signature.as_ref()).into_string(),
-                    });
-                }
+    #[test]
+    fn test_decode_loaded_addresses() {
+        let (keys, program_index, _) = account_keys(2);
+        let loaded_addresses = LoadedAddresses {
+            writable: vec![Pubkey::new_unique()],
+            readonly: vec![],
+        };
+        let instruction = CompiledInstruction {
+            program_id_index: program_index,
+            accounts: vec![0, 1, keys.len() as u8],
+            data: instruction_data(|instruction| {
+                matches!(instruction, MplInscriptionInstruction::WriteData(_))
+            }),
+        };
 
-                _ => {
-                    // do nothing
-                }
-            }
+        let (events, _) = decode_inscription_events(
+            &mpl_inscription_program::ID,
+            7,
+            &Signature::default(),
+            &AccountKeys::new(&keys, Some(&loaded_addresses)),
+            &[instruction],
+        );
+        assert_eq!(events[0].authority, Some(loaded_addresses.writable[0]));
+    }
+
+    #[test]
+    fn test_instruction_type_names() {
+        for instruction_type in [
+            InscriptionInstructionType::Initialize,
+            InscriptionInstructionType::InitializeFromMint,
+            InscriptionInstructionType::WriteData,
+            InscriptionInstructionType::Close,
+            InscriptionInstructionType::Other,
+        ] {
+            assert_eq!(
+                InscriptionInstructionType::from_name(instruction_type.as_str()),
+                Some(instruction_type)
+            );
         }
+        assert_eq!(InscriptionInstructionType::from_name("unknown"), None);
+    }
 
-        if let Some(inscription_info) = inscription_info {
-            let wrk_item =
-                DbWorkItem::LogInscription(Box::new(LogInscriptionRequest { inscription_info }));
+    /// Write `key` as a `ty` parameter and read it back as a `ty` column.
+    fn round_trip(key: &DbKey, ty: &Type) -> Result<DbKey, Box<dyn Error + Sync + Send>> {
+        let mut out = BytesMut::new();
+        key.to_sql_checked(ty, &mut out)?;
+        DbKey::from_sql(ty, &out)
+    }
 
-            if let Err(err) = self.sender.send(wrk_item) {
-                return Err(GeyserPluginError::SlotStatusUpdateError {
-                    msg: format!("Failed to update the transaction, error: {:?}", err),
-                });
-            }
+    #[test]
+    fn test_db_key_round_trip() {
+        let pubkey = Pubkey::new_unique();
+        let signature = Signature::new_unique();
+        for (storage, ty) in [
+            (KeyStorage::Base58, Type::TEXT),
+            (KeyStorage::Base58, Type::VARCHAR),
+            (KeyStorage::Binary, Type::BYTEA),
+        ] {
+            let key = DbKey::pubkey(&pubkey, storage);
+            let read = round_trip(&key, &ty).unwrap();
+            assert_eq!(read, key);
+            assert_eq!(read.to_pubkey(), Ok(pubkey));
+
+            let key = DbKey::signature(&signature, storage);
+            let read = round_trip(&key, &ty).unwrap();
+            assert_eq!(read, key);
+            assert_eq!(read.to_signature(), Ok(signature));
         }
+    }
 
-        Ok(())
+    #[test]
+    fn test_db_key_storage_mismatch() {
+        let pubkey = Pubkey::new_unique();
+        assert!(round_trip(&DbKey::pubkey(&pubkey, KeyStorage::Base58), &Type::BYTEA).is_err());
+        assert!(round_trip(&DbKey::pubkey(&pubkey, KeyStorage::Binary), &Type::TEXT).is_err());
+        assert!(round_trip(&DbKey::pubkey(&pubkey, KeyStorage::Binary), &Type::INT8).is_err());
+    }
+
+    #[test]
+    fn test_db_key_invalid() {
+        assert!(DbKey::Base58("not a key".to_string()).to_pubkey().is_err());
+        assert!(DbKey::Binary(vec![1; 31]).to_pubkey().is_err());
+        assert!(DbKey::Binary(vec![1; 32]).to_signature().is_err());
+        // A pubkey is not a signature, whatever the storage.
+        let pubkey = Pubkey::new_unique();
+        assert!(DbKey::pubkey(&pubkey, KeyStorage::Base58)
+            .to_signature()
+            .is_err());
+    }
+
+    #[test]
+    fn test_db_key_min_sorts_first() {
+        let pubkey = Pubkey::new_unique();
+        match (
+            DbKey::min(KeyStorage::Base58),
+            DbKey::pubkey(&pubkey, KeyStorage::Base58),
+        ) {
+            (DbKey::Base58(min), DbKey::Base58(key)) => assert!(min < key),
+            _ => unreachable!(),
+        }
+        match (
+            DbKey::min(KeyStorage::Binary),
+            DbKey::pubkey(&Pubkey::default(), KeyStorage::Binary),
+        ) {
+            (DbKey::Binary(min), DbKey::Binary(key)) => assert!(min < key),
+            _ => unreachable!(),
+        }
     }
 }
@@ -0,0 +1,307 @@
+/// Maintains the `indexer_state` watermark: the highest rooted slot whose
+/// events, and those of every slot before it, are written. The workers write
+/// it along with the events which advance it, and the plugin reads it back
+/// when loading to skip the slots the validator replays after a restart.
+use {
+    crate::{
+        geyser_plugin_postgres::GeyserPluginPostgresError,
+        postgres_client::{postgres_client_transaction::DbKey, SchemaLayout},
+    },
+    domichain_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    domichain_sdk::pubkey::Pubkey,
+    log::*,
+    postgres::GenericClient,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+    },
+};
+
+/// Advances the watermark of a program, never moving it back.
+pub(crate) const COMMITTED_SLOT_UPSERT_STMT: &str =
+    "INSERT INTO {schema}.{prefix}indexer_state AS state (program_id, committed_slot, updated_on) \
+    VALUES ($1, $2, $3) ON CONFLICT (program_id) DO UPDATE SET \
+        committed_slot=GREATEST(state.committed_slot, excluded.committed_slot), \
+        updated_on=excluded.updated_on";
+
+/// The watermark of `program_id`, `None` when nothing was committed yet.
+pub fn load_committed_slot(
+    client: &mut impl GenericClient,
+    layout: &SchemaLayout,
+    program_id: &Pubkey,
+) -> Result<Option<u64>, GeyserPluginError> {
+    client
+        .query_opt(
+            &layout.render(
+                "SELECT committed_slot FROM {schema}.{prefix}indexer_state WHERE program_id = $1",
+            ),
+            &[&DbKey::pubkey(program_id, layout.key_storage())],
+        )
+        .map(|row| row.map(|row| row.get::<_, i64>(0) as u64))
+        .map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginPostgresError::DataSchemaError {
+                msg: format!(
+                    "Failed to read the committed slot of the program {}: {}",
+                    program_id, err
+                ),
+            }))
+        })
+}
+
+#[derive(Default)]
+struct TrackerState {
+    /// The event batches enqueued and not written yet, counted by slot.
+    pending: BTreeMap<u64, usize>,
+    /// The rooted slots above the watermark.
+    rooted: BTreeSet<u64>,
+    committed_slot: u64,
+}
+
+/// Follows the event batches between the queue and the sinks to tell the
+/// workers how far the watermark can go. A rooted slot is committed once no
+/// batch of it, or of an earlier slot, is still pending. Batches are
+/// enqueued before their slot is rooted, so a rooted slot has all its
+/// batches counted.
+///
+/// A batch the sinks keeping the watermark fail to write stays pending,
+/// holding the watermark back, until a retry of the worker writes it. A
+/// batch given up on holds it back until the plugin restarts, when its
+/// slots are replayed.
+#[derive(Default)]
+pub(crate) struct CommitTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl CommitTracker {
+    pub(crate) fn new(committed_slot: u64) -> Self {
+        Self {
+            state: Mutex::new(TrackerState {
+                committed_slot,
+                ..TrackerState::default()
+            }),
+        }
+    }
+
+    /// Count a batch of events of `slot` sent to the workers.
+    pub(crate) fn enqueued(&self, slot: u64) {
+        *self.state.lock().unwrap().pending.entry(slot).or_default() += 1;
+    }
+
+    pub(crate) fn rooted(&self, slot: u64) {
+        let mut state = self.state.lock().unwrap();
+        if slot > state.committed_slot {
+            state.rooted.insert(slot);
+        }
+    }
+
+    /// The watermark once the batches of the slots `written` are, when it
+    /// is ahead of the current one.
+    pub(crate) fn preview(&self, written: &[u64]) -> Option<u64> {
+        self.preview_before(written, u64::MAX)
+    }
+
+    /// As `preview`, with the watermark kept below `limit`, the first slot
+    /// whose events a sink may not have made durable yet.
+    pub(crate) fn preview_before(&self, written: &[u64], limit: u64) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let first_pending = state
+            .pending
+            .iter()
+            .find(|(slot, count)| {
+                **count > written.iter().filter(|written| *written == *slot).count()
+            })
+            .map(|(slot, _)| *slot)
+            .unwrap_or(u64::MAX)
+            .min(limit);
+        state
+            .rooted
+            .range(..first_pending)
+            .next_back()
+            .copied()
+            .filter(|slot| *slot > state.committed_slot)
+    }
+
+    /// Record that the batches of the slots `written` are written, along
+    /// with the watermark `committed_slot` previewed for them.
+    pub(crate) fn written(&self, written: &[u64], committed_slot: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        for slot in written {
+            if let Some(count) = state.pending.get_mut(slot) {
+                *count -= 1;
+                if *count == 0 {
+                    state.pending.remove(slot);
+                }
+            }
+        }
+        if let Some(committed_slot) = committed_slot {
+            let committed_slot = state.committed_slot.max(committed_slot);
+            state.committed_slot = committed_slot;
+            state.rooted = state.rooted.split_off(&(committed_slot + 1));
+        }
+    }
+}
+
+/// The watermark read when loading. The slots up to it are skipped, and
+/// the slots between it and the first slot seen beyond it, which were
+/// indexed by no one, are reported.
+pub(crate) struct ResumePoint {
+    committed_slot: u64,
+    gap_checked: AtomicBool,
+}
+
+impl ResumePoint {
+    pub(crate) fn new(committed_slot: u64) -> Self {
+        info!(
+            "The events are committed up to the slot {}, earlier slots are skipped",
+            committed_slot
+        );
+        Self {
+            committed_slot,
+            gap_checked: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the events of `slot` were committed before the restart.
+    pub(crate) fn is_committed(&self, slot: u64) -> bool {
+        slot <= self.committed_slot
+    }
+
+    /// Check the first slot status update beyond the watermark follows it,
+    /// returning the missed slots, first and last, otherwise. When the
+    /// parent is unknown, a gap may also be slots the cluster skipped.
+    pub(crate) fn check_gap(&self, slot: u64, parent: Option<u64>) -> Option<(u64, u64)> {
+        if self.is_committed(slot) || self.gap_checked.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        let last_missed = parent.unwrap_or_else(|| slot.saturating_sub(1));
+        (last_missed > self.committed_slot).then(|| (self.committed_slot + 1, last_missed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn committed_slot(tracker: &CommitTracker) -> u64 {
+        tracker.state.lock().unwrap().committed_slot
+    }
+
+    #[test]
+    fn test_preview_waits_for_pending_batches() {
+        let tracker = CommitTracker::new(10);
+        tracker.enqueued(11);
+        tracker.enqueued(11);
+        tracker.enqueued(12);
+        tracker.rooted(11);
+        tracker.rooted(12);
+        tracker.rooted(13);
+
+        assert_eq!(tracker.preview(&[]), None);
+        // The slot 11 has two batches.
+        assert_eq!(tracker.preview(&[11]), None);
+        assert_eq!(tracker.preview(&[11, 11]), Some(11));
+        assert_eq!(tracker.preview(&[11, 11, 12]), Some(13));
+        // Previewing records nothing.
+        assert_eq!(tracker.preview(&[]), None);
+
+        tracker.written(&[11, 11], Some(11));
+        assert_eq!(committed_slot(&tracker), 11);
+        assert_eq!(tracker.preview(&[]), None);
+        assert_eq!(tracker.preview(&[12]), Some(13));
+
+        tracker.written(&[12], Some(13));
+        assert_eq!(committed_slot(&tracker), 13);
+        assert_eq!(tracker.preview(&[]), None);
+    }
+
+    #[test]
+    fn test_preview_needs_rooted_slots() {
+        let tracker = CommitTracker::new(10);
+        tracker.enqueued(11);
+        assert_eq!(tracker.preview(&[11]), None);
+
+        // Slots at or below the watermark are ignored.
+        tracker.rooted(9);
+        tracker.rooted(10);
+        assert_eq!(tracker.preview(&[11]), None);
+
+        // A rooted slot without events is committed once the slots before
+        // it are.
+        tracker.rooted(12);
+        assert_eq!(tracker.preview(&[]), None);
+        assert_eq!(tracker.preview(&[11]), Some(12));
+    }
+
+    #[test]
+    fn test_written_never_moves_back() {
+        let tracker = CommitTracker::new(10);
+        tracker.rooted(12);
+        assert_eq!(tracker.preview(&[]), Some(12));
+        tracker.written(&[], Some(12));
+
+        // A worker writing an older preview after another one.
+        tracker.written(&[], Some(11));
+        assert_eq!(committed_slot(&tracker), 12);
+        assert_eq!(tracker.preview(&[]), None);
+
+        // Batches which were not counted are ignored.
+        tracker.written(&[20], None);
+        assert!(tracker.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn test_failed_batch_holds_the_watermark() {
+        let tracker = CommitTracker::new(10);
+        tracker.enqueued(11);
+        tracker.enqueued(12);
+        tracker.rooted(11);
+        tracker.rooted(12);
+
+        // The batch of the slot 11 failed, the one of the slot 12 is
+        // written without advancing the watermark.
+        assert_eq!(tracker.preview(&[12]), None);
+        tracker.written(&[12], None);
+        assert_eq!(tracker.preview(&[]), None);
+
+        // Its retry commits both.
+        assert_eq!(tracker.preview(&[11]), Some(12));
+        tracker.written(&[11], Some(12));
+        assert_eq!(committed_slot(&tracker), 12);
+    }
+
+    #[test]
+    fn test_preview_before() {
+        let tracker = CommitTracker::new(10);
+        tracker.rooted(11);
+        tracker.rooted(13);
+        assert_eq!(tracker.preview_before(&[], 13), Some(11));
+        assert_eq!(tracker.preview_before(&[], 14), Some(13));
+        assert_eq!(tracker.preview_before(&[], 11), None);
+    }
+
+    #[test]
+    fn test_check_gap() {
+        // The slots replayed up to the watermark are skipped, and checked
+        // once the first one beyond it is seen.
+        let resume_point = ResumePoint::new(100);
+        assert!(resume_point.is_committed(100));
+        assert!(!resume_point.is_committed(101));
+        assert_eq!(resume_point.check_gap(99, Some(98)), None);
+        assert_eq!(resume_point.check_gap(101, Some(100)), None);
+        // Only the first slot beyond the watermark is checked.
+        assert_eq!(resume_point.check_gap(110, Some(105)), None);
+
+        let resume_point = ResumePoint::new(100);
+        assert_eq!(resume_point.check_gap(105, Some(103)), Some((101, 103)));
+        assert_eq!(resume_point.check_gap(106, Some(105)), None);
+
+        // Without the parent, the slot before is assumed to be it.
+        let resume_point = ResumePoint::new(100);
+        assert_eq!(resume_point.check_gap(105, None), Some((101, 104)));
+        let resume_point = ResumePoint::new(100);
+        assert_eq!(resume_point.check_gap(101, None), None);
+    }
+}
@@ -0,0 +1,310 @@
+/// Exposes `PluginMetrics` in the Prometheus text exposition format.
+use {
+    crate::{
+        http_server::{HttpResponse, HttpServer},
+        metrics::{Histogram, PluginMetrics, WorkerState},
+    },
+    std::{
+        fmt::Write,
+        io,
+        net::SocketAddr,
+        sync::{atomic::Ordering, Arc},
+    },
+};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const PREFIX: &str = "domichain_inscriptions";
+
+/// Start the `/metrics` endpoint on `addr`.
+pub fn start_server(addr: SocketAddr, metrics: Arc<PluginMetrics>) -> io::Result<HttpServer> {
+    HttpServer::start("prometheus-metrics", addr, move |path| match path {
+        "/metrics" => HttpResponse::ok(CONTENT_TYPE, render(&metrics)),
+        _ => HttpResponse::not_found(),
+    })
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+    let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
+
+fn write_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let snapshot = histogram.snapshot();
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} histogram");
+    let mut cumulative = 0;
+    for (bound, count) in histogram.bounds().iter().zip(snapshot.buckets.iter()) {
+        cumulative += count;
+        let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+    }
+    let _ = writeln!(
+        out,
+        "{PREFIX}_{name}_bucket{{le=\"+Inf\"}} {}",
+        snapshot.count
+    );
+    let _ = writeln!(out, "{PREFIX}_{name}_sum {}", snapshot.sum);
+    let _ = writeln!(out, "{PREFIX}_{name}_count {}", snapshot.count);
+}
+
+pub fn render(metrics: &PluginMetrics) -> String {
+    let mut out = String::new();
+
+    write_metric(
+        &mut out,
+        "last_indexed_slot",
+        "gauge",
+        "The highest slot of an item written to every sink.",
+        metrics.last_indexed_slot.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "latest_processed_slot",
+        "gauge",
+        "The highest slot the validator reported as processed.",
+        metrics.latest_processed_slot.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "slot_lag",
+        "gauge",
+        "Processed slots which are not indexed yet.",
+        metrics.slot_lag(),
+    );
+    write_metric(
+        &mut out,
+        "committed_slot",
+        "gauge",
+        "The highest rooted slot whose events, and those of every slot before it, are written.",
+        metrics.committed_slot.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "open_backfill_gaps",
+        "gauge",
+        "Gaps of the backfill_gaps table which are not filled yet.",
+        metrics.open_backfill_gaps.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "open_backfill_gap_slots",
+        "gauge",
+        "Slots of the gaps which are not filled yet.",
+        metrics.open_backfill_gap_slots.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "end_slot_passed",
+        "gauge",
+        "1 once a slot at or beyond end_slot is rooted.",
+        metrics.end_slot_passed.load(Ordering::Relaxed) as u64,
+    );
+    write_metric(
+        &mut out,
+        "queue_depth",
+        "gauge",
+        "Items waiting in the queue for a writer.",
+        metrics.queue_depth.load(Ordering::Relaxed),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP {PREFIX}_items_total Work items by kind and outcome."
+    );
+    let _ = writeln!(out, "# TYPE {PREFIX}_items_total counter");
+    for (kind, counters) in [("event", &metrics.events), ("slot", &metrics.slots)] {
+        for (outcome, value) in [
+            ("enqueued", &counters.enqueued),
+            ("written", &counters.written),
+            ("failed", &counters.failed),
+        ] {
+            let _ = writeln!(
+                out,
+                "{PREFIX}_items_total{{kind=\"{kind}\",outcome=\"{outcome}\"}} {}",
+                value.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    write_metric(
+        &mut out,
+        "db_errors_total",
+        "counter",
+        "Failed writes to the sinks.",
+        metrics.events.failed.load(Ordering::Relaxed)
+            + metrics.slots.failed.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "connection_errors_total",
+        "counter",
+        "Failed attempts to connect to the database.",
+        metrics.connection_errors.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "reconnects_total",
+        "counter",
+        "Connections re-established after the server closed them.",
+        metrics.reconnects.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "detected_gaps_total",
+        "counter",
+        "Gaps found between the committed slot and the slots seen after a restart.",
+        metrics.detected_gaps.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "detected_gap_slots_total",
+        "counter",
+        "Slots of the gaps found after a restart.",
+        metrics.detected_gap_slots.load(Ordering::Relaxed),
+    );
+    write_metric(
+        &mut out,
+        "decode_failures_total",
+        "counter",
+        "Inscription program instructions which could not be deserialized.",
+        metrics.decode_failures.load(Ordering::Relaxed),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP {PREFIX}_webhook_deliveries_total Webhook payloads by outcome."
+    );
+    let _ = writeln!(out, "# TYPE {PREFIX}_webhook_deliveries_total counter");
+    for (outcome, value) in [
+        ("delivered", &metrics.webhook_deliveries.delivered),
+        ("retried", &metrics.webhook_deliveries.retried),
+        ("dropped", &metrics.webhook_deliveries.dropped),
+        ("spilled", &metrics.webhook_deliveries.spilled),
+    ] {
+        let _ = writeln!(
+            out,
+            "{PREFIX}_webhook_deliveries_total{{outcome=\"{outcome}\"}} {}",
+            value.load(Ordering::Relaxed)
+        );
+    }
+    write_metric(
+        &mut out,
+        "webhook_retry_queue_depth",
+        "gauge",
+        "Webhook payloads waiting for a retry.",
+        metrics
+            .webhook_deliveries
+            .retry_queue_depth
+            .load(Ordering::Relaxed),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP {PREFIX}_worker_state The state of each writer worker, 1 for the current state."
+    );
+    let _ = writeln!(out, "# TYPE {PREFIX}_worker_state gauge");
+    for (worker, current) in metrics.worker_states().iter().enumerate() {
+        for state in WorkerState::ALL {
+            let _ = writeln!(
+                out,
+                "{PREFIX}_worker_state{{worker=\"{worker}\",state=\"{}\"}} {}",
+                state.as_str(),
+                u8::from(state == *current)
+            );
+        }
+    }
+
+    write_histogram(
+        &mut out,
+        "write_latency_microseconds",
+        "Time spent writing one batch to every sink.",
+        &metrics.write_latency_us,
+    );
+    write_histogram(
+        &mut out,
+        "batch_size",
+        "Number of items written per batch.",
+        &metrics.batch_size,
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(out: &str) -> Vec<&str> {
+        out.lines().collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = PluginMetrics::new(2);
+        metrics.last_indexed_slot.store(42, Ordering::Relaxed);
+        metrics.latest_processed_slot.store(50, Ordering::Relaxed);
+        metrics.events.enqueued.fetch_add(5, Ordering::Relaxed);
+        metrics.events.failed.fetch_add(2, Ordering::Relaxed);
+        metrics.slots.failed.fetch_add(1, Ordering::Relaxed);
+        metrics
+            .webhook_deliveries
+            .spilled
+            .fetch_add(3, Ordering::Relaxed);
+        metrics.set_worker_state(1, WorkerState::Failed);
+        metrics.record_batch(3, 200);
+        metrics.record_batch(7, 200);
+
+        let out = render(&metrics);
+        let lines = lines(&out);
+        for expected in [
+            "# TYPE domichain_inscriptions_last_indexed_slot gauge",
+            "domichain_inscriptions_last_indexed_slot 42",
+            "domichain_inscriptions_slot_lag 8",
+            "domichain_inscriptions_items_total{kind=\"event\",outcome=\"enqueued\"} 5",
+            "domichain_inscriptions_items_total{kind=\"slot\",outcome=\"written\"} 0",
+            "domichain_inscriptions_db_errors_total 3",
+            "domichain_inscriptions_webhook_deliveries_total{outcome=\"spilled\"} 3",
+            "domichain_inscriptions_worker_state{worker=\"1\",state=\"failed\"} 1",
+            "domichain_inscriptions_worker_state{worker=\"1\",state=\"connected\"} 0",
+            // The buckets are cumulative.
+            "domichain_inscriptions_batch_size_bucket{le=\"2\"} 0",
+            "domichain_inscriptions_batch_size_bucket{le=\"5\"} 1",
+            "domichain_inscriptions_batch_size_bucket{le=\"10\"} 2",
+            "domichain_inscriptions_batch_size_bucket{le=\"1000\"} 2",
+            "domichain_inscriptions_batch_size_bucket{le=\"+Inf\"} 2",
+            "domichain_inscriptions_batch_size_sum 10",
+            "domichain_inscriptions_batch_size_count 2",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "missing {:?} in\n{}",
+                expected,
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_is_well_formed() {
+        let out = render(&PluginMetrics::new(1));
+        let mut families = Vec::new();
+        for line in lines(&out) {
+            if let Some(family) = line.strip_prefix("# TYPE ") {
+                let name = family.split(' ').next().unwrap();
+                // Every family is declared once.
+                assert!(!families.contains(&name), "{:?} declared twice", name);
+                families.push(name);
+            } else if !line.starts_with("# HELP ") {
+                let (sample, value) = line.rsplit_once(' ').unwrap();
+                assert!(sample.starts_with(PREFIX), "{:?}", line);
+                assert!(
+                    families.iter().any(|family| sample.starts_with(family)),
+                    "{:?} has no TYPE",
+                    line
+                );
+                value.parse::<u64>().unwrap();
+            }
+        }
+        assert!(families.contains(&"domichain_inscriptions_write_latency_microseconds"));
+    }
+}
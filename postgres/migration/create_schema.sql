@@ -8,3 +8,169 @@ CREATE TABLE inscriptions (
     updated_on TIMESTAMP NOT NULL,
     CONSTRAINT inscriptions_pk PRIMARY KEY (account)
 );
+
+-- Partitioned by slot range, the partitions are created by the plugin.
+CREATE TABLE inscription_events (
+    slot BIGINT NOT NULL,
+    signature TEXT NOT NULL,
+    instruction_index INT NOT NULL,
+    instruction_type VARCHAR(32) NOT NULL,
+    inscription_account TEXT,
+    metadata_account TEXT,
+    mint_account TEXT,
+    authority TEXT,
+    accounts TEXT[] NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT inscription_events_pk PRIMARY KEY (slot, signature, instruction_index)
+) PARTITION BY RANGE (slot);
+
+CREATE INDEX inscription_events_inscription_account ON inscription_events (inscription_account);
+CREATE INDEX inscription_events_signature ON inscription_events (signature);
+
+CREATE TABLE inscription_events_0 PARTITION OF inscription_events FOR VALUES FROM (0) TO (1);
+
+-- The partitions of inscription_events, those detached included.
+CREATE TABLE event_partitions (
+    name TEXT NOT NULL,
+    start_slot BIGINT NOT NULL,
+    end_slot BIGINT NOT NULL,
+    created_on TIMESTAMP NOT NULL,
+    detached_on TIMESTAMP,
+    archived_as TEXT,
+    CONSTRAINT event_partitions_pk PRIMARY KEY (name)
+);
+
+INSERT INTO event_partitions (name, start_slot, end_slot, created_on)
+VALUES ('inscription_events_0', 0, 1, now());
+
+CREATE TABLE slots (
+    slot BIGINT NOT NULL,
+    parent BIGINT,
+    status VARCHAR(16) NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT slots_pk PRIMARY KEY (slot)
+);
+
+-- The highest slot whose events, and those of every slot before it, are
+-- committed, per indexed program.
+CREATE TABLE indexer_state (
+    program_id TEXT NOT NULL,
+    committed_slot BIGINT NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    CONSTRAINT indexer_state_pk PRIMARY KEY (program_id)
+);
+
+-- The slot ranges, both ends included, the plugin missed. A gap is open
+-- until a backfill marks it filled.
+CREATE TABLE backfill_gaps (
+    program_id TEXT NOT NULL,
+    start_slot BIGINT NOT NULL,
+    end_slot BIGINT NOT NULL,
+    detected_on TIMESTAMP NOT NULL,
+    filled_on TIMESTAMP,
+    CONSTRAINT backfill_gaps_pk PRIMARY KEY (program_id, start_slot)
+);
+
+-- base58 presents a key column as base58 text whatever the key_storage of
+-- the tables, base58_decode turns base58 text into the bytes stored by the
+-- binary key_storage, e.g. for
+--   SELECT * FROM inscriptions WHERE account = base58_decode('...')
+CREATE OR REPLACE FUNCTION base58(data BYTEA) RETURNS TEXT
+LANGUAGE plpgsql IMMUTABLE STRICT PARALLEL SAFE AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz';
+    num NUMERIC := 0;
+    encoded TEXT := '';
+BEGIN
+    FOR i IN 0 .. length(data) - 1 LOOP
+        num := num * 256 + get_byte(data, i);
+    END LOOP;
+    WHILE num > 0 LOOP
+        encoded := substr(alphabet, mod(num, 58)::INT + 1, 1) || encoded;
+        num := div(num, 58);
+    END LOOP;
+    FOR i IN 0 .. length(data) - 1 LOOP
+        EXIT WHEN get_byte(data, i) <> 0;
+        encoded := '1' || encoded;
+    END LOOP;
+    RETURN encoded;
+END $$;
+
+CREATE OR REPLACE FUNCTION base58(data TEXT) RETURNS TEXT
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$ SELECT data $$;
+
+CREATE OR REPLACE FUNCTION base58(data BYTEA[]) RETURNS TEXT[]
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$
+    SELECT COALESCE(array_agg(base58(key) ORDER BY position), '{}')
+    FROM unnest(data) WITH ORDINALITY AS keys(key, position)
+$$;
+
+CREATE OR REPLACE FUNCTION base58(data TEXT[]) RETURNS TEXT[]
+LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE AS $$ SELECT data $$;
+
+CREATE OR REPLACE FUNCTION base58_decode(encoded TEXT) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT PARALLEL SAFE AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz';
+    num NUMERIC := 0;
+    digit INT;
+    data BYTEA := '';
+BEGIN
+    FOR i IN 1 .. length(encoded) LOOP
+        digit := strpos(alphabet, substr(encoded, i, 1)) - 1;
+        IF digit < 0 THEN
+            RAISE EXCEPTION 'Invalid base58 character % in %', substr(encoded, i, 1), encoded;
+        END IF;
+        num := num * 58 + digit;
+    END LOOP;
+    WHILE num > 0 LOOP
+        data := set_byte(decode('00', 'hex'), 0, mod(num, 256)::INT) || data;
+        num := div(num, 256);
+    END LOOP;
+    FOR i IN 1 .. length(encoded) LOOP
+        EXIT WHEN substr(encoded, i, 1) <> '1';
+        data := decode('00', 'hex') || data;
+    END LOOP;
+    RETURN data;
+END $$;
+
+-- The tables with their keys in base58, for human consumption.
+CREATE OR REPLACE VIEW inscriptions_base58 AS
+SELECT
+    slot,
+    base58(signature) AS signature,
+    base58(account) AS account,
+    base58(mint_account) AS mint_account,
+    base58(metadata_account) AS metadata_account,
+    base58(authority) AS authority,
+    updated_on
+FROM inscriptions;
+
+CREATE OR REPLACE VIEW inscription_events_base58 AS
+SELECT
+    slot,
+    base58(signature) AS signature,
+    instruction_index,
+    instruction_type,
+    base58(inscription_account) AS inscription_account,
+    base58(metadata_account) AS metadata_account,
+    base58(mint_account) AS mint_account,
+    base58(authority) AS authority,
+    base58(accounts) AS accounts,
+    updated_on
+FROM inscription_events;
+
+-- The migrations of geyser-plugin/migrations this script is equivalent to.
+CREATE TABLE schema_version (
+    version INT NOT NULL,
+    description TEXT NOT NULL,
+    applied_on TIMESTAMP NOT NULL,
+    CONSTRAINT schema_version_pk PRIMARY KEY (version)
+);
+
+INSERT INTO schema_version VALUES
+    (1, 'initial schema', now()),
+    (2, 'partition inscription_events by slot range', now()),
+    (3, 'base58 helper functions and views', now()),
+    (4, 'indexer state watermark', now()),
+    (5, 'backfill gaps', now());
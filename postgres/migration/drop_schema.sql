@@ -1 +1,14 @@
+DROP VIEW inscription_events_base58;
+DROP VIEW inscriptions_base58;
+DROP FUNCTION base58(BYTEA);
+DROP FUNCTION base58(TEXT);
+DROP FUNCTION base58(BYTEA[]);
+DROP FUNCTION base58(TEXT[]);
+DROP FUNCTION base58_decode(TEXT);
+DROP TABLE backfill_gaps;
+DROP TABLE indexer_state;
+DROP TABLE slots;
+DROP TABLE inscription_events;
 DROP TABLE inscriptions;
+DROP TABLE event_partitions;
+DROP TABLE schema_version;